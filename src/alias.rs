@@ -0,0 +1,195 @@
+// Copyright © Killian Kemps (2019)
+//
+// Killian Kemps <developer@killiankemps.fr>
+//
+// This software is a computer program whose purpose is to communicate with
+// the Gopher protocol.
+//
+// This software is governed by the CeCILL license under French law and
+// abiding by the rules of distribution of free software.  You can  use,
+// modify and/ or redistribute the software under the terms of the CeCILL
+// license as circulated by CEA, CNRS and INRIA at the following URL
+// "http://www.cecill.info".
+//
+// As a counterpart to the access to the source code and  rights to copy,
+// modify and redistribute granted by the license, users are provided only
+// with a limited warranty  and the software's author,  the holder of the
+// economic rights,  and the successive licensors  have only  limited
+// liability.
+//
+// In this respect, the user's attention is drawn to the risks associated
+// with loading,  using,  modifying and/or developing or reproducing the
+// software by the user in light of its specific status of free software,
+// that may mean  that it is complicated to manipulate,  and  that  also
+// therefore means  that it is reserved for developers  and  experienced
+// professionals having in-depth computer knowledge. Users are therefore
+// encouraged to load and test the software's suitability as regards their
+// requirements in conditions enabling the security of their systems and/or
+// data to be ensured and,  more generally, to use and operate it in the
+// same conditions as regards security.
+//
+// The fact that you are presently reading this means that you have had
+// knowledge of the CeCILL license and that you accept its terms.
+
+use std::collections::BTreeMap;
+
+use crate::url;
+
+// Replace the first word of the input when it is an alias. An alias expands
+// to one or several commands separated by ';'. The arguments given to the
+// alias replace "{}" in its expansion, or are appended to its last command.
+// Expansions starting with "gopher://" are URL templates to go to, where
+// "%09" separates the selector from search terms, and arguments are
+// percent-encoded.
+//
+// Aliases may use other aliases, but an alias is not expanded again inside
+// its own expansion so that "go = go -something" style aliases don't loop.
+pub fn expand(input: &str, aliases: &BTreeMap<String, String>) -> Result<Vec<String>, String> {
+  let mut expanding = Vec::new();
+  expand_command(input, aliases, &mut expanding)
+}
+
+fn expand_command(
+  input: &str,
+  aliases: &BTreeMap<String, String>,
+  expanding: &mut Vec<String>,
+) -> Result<Vec<String>, String> {
+  let input = input.trim();
+  let (name, args) = match input.find(' ') {
+    Some(idx) => (&input[..idx], input[idx..].trim()),
+    None => (input, ""),
+  };
+
+  let expansion = match aliases.get(name) {
+    Some(expansion) if !expanding.iter().any(|alias| alias == name) => expansion,
+    _ => return Ok(vec![input.to_string()]),
+  };
+
+  let templates: Vec<&str> = expansion
+    .split(';')
+    .map(|command| command.trim())
+    .filter(|command| !command.is_empty())
+    .collect();
+  if templates.is_empty() {
+    return Err(format!("Alias {} is empty", name));
+  }
+  let has_placeholder = expansion.contains("{}");
+
+  expanding.push(name.to_string());
+  let mut commands = Vec::new();
+  for (index, template) in templates.iter().enumerate() {
+    let (mut command, args) = if template.starts_with("gopher://") {
+      (
+        format!("go {}", template.replace("%09", "\t")),
+        url::encode(args),
+      )
+    } else {
+      (template.to_string(), args.to_string())
+    };
+
+    if has_placeholder {
+      command = command.replace("{}", &args);
+    } else if index == templates.len() - 1 && !args.is_empty() {
+      command = format!("{} {}", command, args);
+    }
+
+    commands.extend(expand_command(&command, aliases, expanding)?);
+  }
+  expanding.pop();
+
+  Ok(commands)
+}
+
+// Parse "name value" or "name = value" as given to the alias command
+pub fn parse_definition(definition: &str) -> Result<(String, String), String> {
+  let definition = definition.trim();
+  let (name, value) = match definition.find([' ', '=']) {
+    Some(idx) => (&definition[..idx], definition[idx..].trim_start()),
+    None => return Err("Usage: alias [name] [commands]".to_string()),
+  };
+  let value = value.strip_prefix('=').unwrap_or(value).trim();
+
+  if ["alias", "unalias"].contains(&name) {
+    return Err(format!("{} can't be redefined", name));
+  }
+  if value.is_empty() {
+    return Err(format!("No commands given for alias {}", name));
+  }
+  Ok((name.to_string(), value.to_string()))
+}
+
+#[cfg(test)]
+mod tests_alias {
+  use super::*;
+
+  fn aliases() -> BTreeMap<String, String> {
+    let mut aliases = BTreeMap::new();
+    aliases.insert("b".to_string(), "back".to_string());
+    aliases.insert("q".to_string(), "quit".to_string());
+    aliases.insert("f".to_string(), "find".to_string());
+    aliases.insert(
+      "wp".to_string(),
+      "gopher://gopherpedia.com/7/lookup%09{}".to_string(),
+    );
+    aliases.insert("home".to_string(), "tab new sdf.org; b".to_string());
+    aliases.insert("up".to_string(), "up; bk".to_string());
+    aliases
+  }
+
+  #[test]
+  fn should_expand_aliases() {
+    assert_eq!(Ok(vec!["back".to_string()]), expand("b", &aliases()));
+    assert_eq!(
+      Ok(vec!["find gopher".to_string()]),
+      expand(" f  gopher ", &aliases())
+    );
+    assert_eq!(Ok(vec!["tabs".to_string()]), expand("tabs", &aliases()));
+    // Not an alias when not the first word
+    assert_eq!(Ok(vec!["find q".to_string()]), expand("find q", &aliases()));
+  }
+
+  #[test]
+  fn should_expand_macros() {
+    assert_eq!(
+      Ok(vec![
+        "go gopher://gopherpedia.com/7/lookup\tGopher%20protocol".to_string()
+      ]),
+      expand("wp Gopher protocol", &aliases())
+    );
+    let commands = expand("wp 100%", &aliases()).unwrap();
+    let url = crate::GopherURL::from(commands[0].trim_start_matches("go ")).unwrap();
+    assert_eq!(Some("100%".to_string()), url.search);
+    assert_eq!(
+      Ok(vec!["tab new sdf.org".to_string(), "back".to_string()]),
+      expand("home", &aliases())
+    );
+    // An alias is not expanded in its own expansion
+    assert_eq!(
+      Ok(vec!["up".to_string(), "bk".to_string()]),
+      expand("up", &aliases())
+    );
+  }
+
+  #[test]
+  fn should_parse_definitions() {
+    assert_eq!(
+      Ok(("b".to_string(), "back".to_string())),
+      parse_definition("b back")
+    );
+    assert_eq!(
+      Ok((
+        "wp".to_string(),
+        "gopher://gopherpedia.com/7/lookup%09{}".to_string()
+      )),
+      parse_definition("wp = gopher://gopherpedia.com/7/lookup%09{}")
+    );
+    assert_eq!(
+      Err("alias can't be redefined".to_string()),
+      parse_definition("alias quit")
+    );
+    assert_eq!(
+      Err("No commands given for alias b".to_string()),
+      parse_definition("b =")
+    );
+  }
+}
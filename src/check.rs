@@ -0,0 +1,461 @@
+// Copyright © Killian Kemps (2019)
+//
+// Killian Kemps <developer@killiankemps.fr>
+//
+// This software is a computer program whose purpose is to communicate with
+// the Gopher protocol.
+//
+// This software is governed by the CeCILL license under French law and
+// abiding by the rules of distribution of free software.  You can  use,
+// modify and/ or redistribute the software under the terms of the CeCILL
+// license as circulated by CEA, CNRS and INRIA at the following URL
+// "http://www.cecill.info".
+//
+// As a counterpart to the access to the source code and  rights to copy,
+// modify and redistribute granted by the license, users are provided only
+// with a limited warranty  and the software's author,  the holder of the
+// economic rights,  and the successive licensors  have only  limited
+// liability.
+//
+// In this respect, the user's attention is drawn to the risks associated
+// with loading,  using,  modifying and/or developing or reproducing the
+// software by the user in light of its specific status of free software,
+// that may mean  that it is complicated to manipulate,  and  that  also
+// therefore means  that it is reserved for developers  and  experienced
+// professionals having in-depth computer knowledge. Users are therefore
+// encouraged to load and test the software's suitability as regards their
+// requirements in conditions enabling the security of their systems and/or
+// data to be ensured and,  more generally, to use and operate it in the
+// same conditions as regards security.
+//
+// The fact that you are presently reading this means that you have had
+// knowledge of the CeCILL license and that you accept its terms.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::{ErrorKind, Read, Write};
+use std::time::Duration;
+
+use crate::config::{Config, MenuMode, OutputFormat, SanitizeMode};
+use crate::connect::connect;
+use crate::json;
+use crate::sanitize::sanitize;
+use crate::{parse_error_item, GopherMenuResponse, GopherURL};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+// Enough to see whether a document starts with an error line
+const PROBE_SIZE: usize = 1024;
+
+#[derive(Debug, PartialEq)]
+pub struct CheckOptions {
+  // Number of menus to walk from the start page
+  pub depth: usize,
+  pub format: OutputFormat,
+  pub menus: MenuMode,
+}
+
+// Parse "<url> [--depth n] [--format text|json]" as given to the check command
+pub fn parse_args(args: &str, config: &Config) -> Result<(GopherURL, CheckOptions), String> {
+  let usage = "Usage: check [url] [--depth n] [--format text|json]";
+  let mut args = args.split_whitespace();
+  let url = match args.next() {
+    Some(url) => GopherURL::from(url)?,
+    None => return Err(usage.to_string()),
+  };
+
+  let mut options = CheckOptions {
+    depth: 3,
+    format: config.format,
+    menus: config.menus,
+  };
+  while let Some(arg) = args.next() {
+    let value = match args.next() {
+      Some(value) => value,
+      None => return Err(format!("No value given to {}\n{}", arg, usage)),
+    };
+    match arg {
+      "--depth" => match value.parse::<usize>() {
+        Ok(depth) => options.depth = depth,
+        Err(error) => return Err(format!("Could not parse --depth: {:?}", error)),
+      },
+      "--format" => {
+        options.format = match value {
+          "text" => OutputFormat::Text,
+          "json" => OutputFormat::Json,
+          _ => return Err(format!("Unknown output format \"{}\"", value)),
+        }
+      }
+      _ => return Err(format!("Unknown option {}\n{}", arg, usage)),
+    }
+  }
+  Ok((url, options))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Problem {
+  Unreachable(String),
+  Timeout,
+  Empty,
+  // Description of the "3" item sent instead of the document
+  ErrorLine(String),
+  // Line numbers and parsing errors of a menu
+  InvalidMenu(Vec<(usize, String)>),
+}
+
+impl Problem {
+  fn kind(&self) -> &str {
+    match self {
+      Problem::Unreachable(_) => "unreachable",
+      Problem::Timeout => "timeout",
+      Problem::Empty => "empty",
+      Problem::ErrorLine(_) => "error",
+      Problem::InvalidMenu(_) => "invalid_menu",
+    }
+  }
+
+  fn message(&self) -> String {
+    match self {
+      Problem::Unreachable(msg) => format!("Unreachable: {}", msg),
+      Problem::Timeout => "Timed out".to_string(),
+      Problem::Empty => "Empty response".to_string(),
+      Problem::ErrorLine(msg) => format!("Server error: {}", msg),
+      Problem::InvalidMenu(errors) => {
+        let errors: Vec<String> = errors
+          .iter()
+          .map(|(line, msg)| format!("line {}: {}", line, msg))
+          .collect();
+        format!("Invalid menu: {}", errors.join(", "))
+      }
+    }
+  }
+}
+
+// Connect to the server of the URL, send its selector and read the response,
+// only its beginning unless full is set
+fn probe(url: &GopherURL, full: bool, config: &Config) -> Result<Vec<u8>, Problem> {
+  let timed_out =
+    |e: &std::io::Error| [ErrorKind::TimedOut, ErrorKind::WouldBlock].contains(&e.kind());
+
+  let mut stream = match connect(&url.host, url.port, config, TIMEOUT) {
+    Ok(stream) => stream,
+    Err(e) if timed_out(&e) => return Err(Problem::Timeout),
+    Err(e) => return Err(Problem::Unreachable(e.to_string())),
+  };
+
+  let sent = stream
+    .set_read_timeout(Some(TIMEOUT))
+    .and_then(|_| stream.write_all(format!("{}\r\n", url.get_request()).as_bytes()));
+  if let Err(e) = sent {
+    return Err(Problem::Unreachable(e.to_string()));
+  }
+
+  let mut buffer = Vec::new();
+  let read = if full {
+    stream.read_to_end(&mut buffer)
+  } else {
+    (&mut stream)
+      .take(PROBE_SIZE as u64)
+      .read_to_end(&mut buffer)
+  };
+  match read {
+    Ok(_) => Ok(buffer),
+    Err(e) if timed_out(&e) => Err(Problem::Timeout),
+    Err(e) => Err(Problem::Unreachable(e.to_string())),
+  }
+}
+
+// Look for problems in a response
+fn examine(url: &GopherURL, raw: &[u8], menus: MenuMode) -> Result<(), Problem> {
+  if raw.is_empty() {
+    return Err(Problem::Empty);
+  }
+  if let Some(msg) = parse_error_item(raw, &url.r#type) {
+    return Err(Problem::ErrorLine(msg));
+  }
+  if url.r#type == "1" {
+    let text = String::from_utf8_lossy(raw);
+    let menu = GopherMenuResponse::parse(&text, menus);
+    let errors: Vec<(usize, String)> = menu
+      .lines
+      .iter()
+      .enumerate()
+      .filter_map(|(index, line)| line.as_ref().err().map(|msg| (index + 1, msg.clone())))
+      .collect();
+    if !errors.is_empty() {
+      return Err(Problem::InvalidMenu(errors));
+    }
+  }
+  Ok(())
+}
+
+// Items which can be requested, others are information lines or pointers to
+// other protocols
+fn is_checkable(url: &GopherURL) -> bool {
+  match &url.r#type[..] {
+    "i" | "3" | "8" | "+" | "T" => false,
+    "h" => !url.selector.starts_with("URL:"),
+    _ => true,
+  }
+}
+
+// A broken link found in a page
+struct Report {
+  page: String,
+  description: String,
+  link: String,
+  problem: Problem,
+}
+
+// Walk the menus of the server of the start URL and check every link they
+// contain, including the ones to other servers. Returns the report and the
+// number of broken links.
+pub fn check(
+  start: &GopherURL,
+  options: &CheckOptions,
+  config: &Config,
+) -> Result<(String, usize), String> {
+  let mut results: HashMap<String, Result<(), Problem>> = HashMap::new();
+  let mut reports = Vec::new();
+  let mut walked = HashSet::new();
+  let mut queue = VecDeque::new();
+  let mut pages = 0;
+
+  let start_url = start.get_url().unwrap_or_default();
+  let start_menu =
+    probe(start, true, config).and_then(|raw| examine(start, &raw, options.menus).map(|_| raw));
+  match start_menu {
+    Ok(raw) => queue.push_back((start_url.clone(), raw, 0)),
+    Err(problem) => {
+      return Err(format!("{}: {}", start_url, problem.message()));
+    }
+  }
+  walked.insert(start_url);
+
+  while let Some((page, raw, depth)) = queue.pop_front() {
+    pages += 1;
+    let menu = GopherMenuResponse::from(&String::from_utf8_lossy(&raw));
+    for item in menu.lines.iter().flatten() {
+      let link = match item.to_url() {
+        Some(link) if is_checkable(&link) => link,
+        _ => continue,
+      };
+      let full_url = item.get_url();
+      if results.contains_key(&full_url) {
+        if let Some(Err(problem)) = results.get(&full_url) {
+          reports.push(Report {
+            page: page.clone(),
+            description: item.description.clone(),
+            link: full_url,
+            problem: problem.clone(),
+          });
+        }
+        continue;
+      }
+
+      // Menus of the server are read completely to be walked
+      let same_server = link.host.eq_ignore_ascii_case(&start.host) && link.port == start.port;
+      let result = probe(&link, link.r#type == "1", config).and_then(|raw| {
+        examine(&link, &raw, options.menus)?;
+        if same_server
+          && link.r#type == "1"
+          && depth < options.depth
+          && walked.insert(full_url.clone())
+        {
+          queue.push_back((full_url.clone(), raw, depth + 1));
+        }
+        Ok(())
+      });
+      if let Err(problem) = &result {
+        reports.push(Report {
+          page: page.clone(),
+          description: item.description.clone(),
+          link: full_url.clone(),
+          problem: problem.clone(),
+        });
+      }
+      results.insert(full_url, result);
+    }
+  }
+
+  let summary = match options.format {
+    OutputFormat::Text => text_summary(&reports, results.len(), pages, config.sanitize),
+    OutputFormat::Json => json_summary(&reports, results.len(), pages),
+  };
+  Ok((summary, reports.len()))
+}
+
+// Problems grouped by page, in the order the pages were walked
+fn group_by_page(reports: &[Report]) -> Vec<(&str, Vec<&Report>)> {
+  let mut groups: Vec<(&str, Vec<&Report>)> = Vec::new();
+  for report in reports {
+    match groups.iter_mut().find(|(page, _)| *page == report.page) {
+      Some((_, group)) => group.push(report),
+      None => groups.push((&report.page, vec![report])),
+    }
+  }
+  groups
+}
+
+// Descriptions and messages come from servers and are sanitized like pages
+fn text_summary(reports: &[Report], links: usize, pages: usize, mode: SanitizeMode) -> String {
+  let mut lines = Vec::new();
+  for (page, group) in group_by_page(reports) {
+    lines.push(format!("{}:", page));
+    for report in group {
+      lines.push(format!(
+        "  {} ({}): {}",
+        report.link,
+        sanitize(&report.description, mode),
+        sanitize(&report.problem.message(), mode)
+      ));
+    }
+  }
+  lines.push(format!(
+    "Checked {} links in {} menus, {} broken",
+    links,
+    pages,
+    reports.len()
+  ));
+  lines.join("\n")
+}
+
+fn json_summary(reports: &[Report], links: usize, pages: usize) -> String {
+  let groups: Vec<String> = group_by_page(reports)
+    .iter()
+    .map(|(page, group)| {
+      let problems: Vec<String> = group
+        .iter()
+        .map(|report| {
+          format!(
+            "{{\"url\":{},\"description\":{},\"problem\":{},\"message\":{}}}",
+            json::string(&report.link),
+            json::string(&report.description),
+            json::string(report.problem.kind()),
+            json::string(&report.problem.message())
+          )
+        })
+        .collect();
+      format!(
+        "{{\"url\":{},\"problems\":[{}]}}",
+        json::string(page),
+        problems.join(",")
+      )
+    })
+    .collect();
+  format!(
+    "{{\"links\":{},\"menus\":{},\"broken\":{},\"pages\":[{}]}}",
+    links,
+    pages,
+    reports.len(),
+    groups.join(",")
+  )
+}
+
+#[cfg(test)]
+mod tests_check {
+  use super::*;
+
+  #[test]
+  fn should_parse_options() {
+    let (url, options) =
+      parse_args("sdf.org/1/users --depth 1 --format json", &Config::new()).unwrap();
+    assert_eq!("/users", url.selector);
+    assert_eq!(
+      CheckOptions {
+        depth: 1,
+        format: OutputFormat::Json,
+        menus: MenuMode::Lenient,
+      },
+      options
+    );
+    assert!(parse_args("", &Config::new()).is_err());
+    assert!(parse_args("sdf.org --depth", &Config::new()).is_err());
+  }
+
+  #[test]
+  fn should_find_problems_in_responses() {
+    let menu = GopherURL::from("sdf.org/1/").unwrap();
+    let text = GopherURL::from("sdf.org/0/notes.txt").unwrap();
+    let lenient = MenuMode::Lenient;
+    assert_eq!(
+      Ok(()),
+      examine(&text, b"Notes\r\n3 things to do\r\n", lenient)
+    );
+    assert_eq!(
+      Ok(()),
+      examine(&menu, b"1Users\t/users\tsdf.org\t70\r\n.\r\n", lenient)
+    );
+    assert_eq!(Err(Problem::Empty), examine(&text, b"", lenient));
+    assert_eq!(
+      Err(Problem::ErrorLine(
+        "'/notes.txt' does not exist".to_string()
+      )),
+      examine(
+        &text,
+        b"3'/notes.txt' does not exist\t\terror.host\t1\r\n",
+        lenient
+      )
+    );
+    assert_eq!(
+      Err(Problem::InvalidMenu(vec![(
+        1,
+        "Could not parse host in: \"1Users\t/users\"".to_string()
+      )])),
+      examine(&menu, b"1Users\t/users\r\n", lenient)
+    );
+    assert_eq!(
+      Err(Problem::InvalidMenu(vec![
+        (2, "Could not parse selector in: \"1Users\"".to_string()),
+        (3, "Missing \".\" line at the end of the menu".to_string())
+      ])),
+      examine(
+        &menu,
+        b"iWelcome\t\terror.host\t1\r\n1Users\r\n",
+        MenuMode::Strict
+      )
+    );
+  }
+
+  #[test]
+  fn should_group_problems_by_page() {
+    let report = |page: &str, link: &str| Report {
+      page: page.to_string(),
+      description: "Gone".to_string(),
+      link: link.to_string(),
+      problem: Problem::Timeout,
+    };
+    let reports = vec![
+      report("gopher://sdf.org:70/1", "gopher://a:70/0"),
+      report("gopher://sdf.org:70/1/users", "gopher://b:70/0"),
+      report("gopher://sdf.org:70/1", "gopher://c:70/0"),
+    ];
+    assert_eq!(
+      "gopher://sdf.org:70/1:\n  \
+       gopher://a:70/0 (Gone): Timed out\n  \
+       gopher://c:70/0 (Gone): Timed out\n\
+       gopher://sdf.org:70/1/users:\n  \
+       gopher://b:70/0 (Gone): Timed out\n\
+       Checked 5 links in 2 menus, 3 broken",
+      text_summary(&reports, 5, 2, SanitizeMode::Strict)
+    );
+
+    // Escape sequences sent by servers are not printed
+    let hostile = vec![Report {
+      page: "gopher://sdf.org:70/1".to_string(),
+      description: "Gone\x1b[2J".to_string(),
+      link: "gopher://a:70/0".to_string(),
+      problem: Problem::ErrorLine("\x1b]0;pwned\x07Not found".to_string()),
+    }];
+    assert_eq!(
+      "gopher://sdf.org:70/1:\n  \
+       gopher://a:70/0 (Gone): Server error: Not found\n\
+       Checked 1 links in 1 menus, 1 broken",
+      text_summary(&hostile, 1, 1, SanitizeMode::Strict)
+    );
+    assert_eq!(
+      "{\"links\":5,\"menus\":2,\"broken\":1,\"pages\":[{\"url\":\"gopher://sdf.org:70/1\",\
+       \"problems\":[{\"url\":\"gopher://a:70/0\",\"description\":\"Gone\",\
+       \"problem\":\"timeout\",\"message\":\"Timed out\"}]}]}",
+      json_summary(&reports[..1], 5, 2)
+    );
+  }
+}
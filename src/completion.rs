@@ -0,0 +1,206 @@
+// Copyright © Killian Kemps (2019)
+//
+// Killian Kemps <developer@killiankemps.fr>
+//
+// This software is a computer program whose purpose is to communicate with
+// the Gopher protocol.
+//
+// This software is governed by the CeCILL license under French law and
+// abiding by the rules of distribution of free software.  You can  use,
+// modify and/ or redistribute the software under the terms of the CeCILL
+// license as circulated by CEA, CNRS and INRIA at the following URL
+// "http://www.cecill.info".
+//
+// As a counterpart to the access to the source code and  rights to copy,
+// modify and redistribute granted by the license, users are provided only
+// with a limited warranty  and the software's author,  the holder of the
+// economic rights,  and the successive licensors  have only  limited
+// liability.
+//
+// In this respect, the user's attention is drawn to the risks associated
+// with loading,  using,  modifying and/or developing or reproducing the
+// software by the user in light of its specific status of free software,
+// that may mean  that it is complicated to manipulate,  and  that  also
+// therefore means  that it is reserved for developers  and  experienced
+// professionals having in-depth computer knowledge. Users are therefore
+// encouraged to load and test the software's suitability as regards their
+// requirements in conditions enabling the security of their systems and/or
+// data to be ensured and,  more generally, to use and operate it in the
+// same conditions as regards security.
+//
+// The fact that you are presently reading this means that you have had
+// knowledge of the CeCILL license and that you accept its terms.
+
+use crate::editor::Completion;
+use crate::{session, ClientState, COMMAND_NAMES};
+
+fn complete_words(word: &str, words: &[&str]) -> Vec<Completion> {
+  words
+    .iter()
+    .filter(|candidate| candidate.starts_with(word))
+    .map(|candidate| Completion {
+      replacement: candidate.to_string(),
+      description: String::new(),
+    })
+    .collect()
+}
+
+impl ClientState {
+  // Completions for the word ending the given line, and the position of
+  // this word in the line
+  pub fn complete(&self, line: &str) -> (usize, Vec<Completion>) {
+    let start = line.rfind(' ').map(|idx| idx + 1).unwrap_or(0);
+    let word = &line[start..];
+    let previous_words: Vec<&str> = line[..start].split_whitespace().collect();
+
+    let completions = match &previous_words[..] {
+      [] => {
+        let mut names: Vec<&str> = COMMAND_NAMES.to_vec();
+        names.extend(self.config.aliases.keys().map(|name| &name[..]));
+        names.sort_unstable();
+        names.dedup();
+        complete_words(word, &names)
+      }
+      ["unalias"] => {
+        let names: Vec<&str> = self.config.aliases.keys().map(|name| &name[..]).collect();
+        complete_words(word, &names)
+      }
+      ["bk"] | ["bookmarks"] => {
+        let mut completions = complete_words(word, &["add", "export", "rm"]);
+        completions.extend(self.complete_bookmarks(word));
+        completions
+      }
+      ["bk", "rm"] | ["bookmarks", "rm"] => self.complete_bookmarks(word),
+      ["bk", "add"] | ["bookmarks", "add"] | ["go"] | ["tab", "new"] | ["mirror"] | ["check"] => {
+        self.complete_hosts(word)
+      }
+      ["tab"] => complete_words(word, &["new", "close", "name"]),
+      ["dump"] => complete_words(word, &["json"]),
+      ["view"] => complete_words(word, &["as"]),
+      ["view", "as"] => complete_words(word, &["menu", "text", "raw"]),
+      ["export"] => complete_words(word, &["html"]),
+      ["session"] => complete_words(word, &["save", "load", "list"]),
+      ["session", "load"] | ["session", "save"] => match session::list_sessions() {
+        Ok(names) => {
+          let names: Vec<&str> = names.iter().map(|name| &name[..]).collect();
+          complete_words(word, &names)
+        }
+        Err(_) => Vec::new(),
+      },
+      _ => Vec::new(),
+    };
+    (start, completions)
+  }
+
+  // Bookmarks are completed by index or by any part of their URL
+  fn complete_bookmarks(&self, word: &str) -> Vec<Completion> {
+    self
+      .bookmarks
+      .iter()
+      .enumerate()
+      .filter_map(|(index, url)| {
+        let url = url.get_url().unwrap_or_default();
+        if index.to_string().starts_with(word) || (!word.is_empty() && url.contains(word)) {
+          Some(Completion {
+            replacement: index.to_string(),
+            description: url,
+          })
+        } else {
+          None
+        }
+      })
+      .collect()
+  }
+
+  // Hosts visited in any tab or bookmarked
+  fn complete_hosts(&self, word: &str) -> Vec<Completion> {
+    let mut hosts: Vec<&str> = self
+      .history
+      .iter()
+      .chain(self.tabs.iter().flat_map(|tab| tab.history.iter()))
+      .chain(self.bookmarks.iter())
+      .map(|url| &url.host[..])
+      .filter(|host| !host.is_empty())
+      .collect();
+    hosts.sort_unstable();
+    hosts.dedup();
+
+    let word_host = ["gopher://", "gemini://"]
+      .iter()
+      .find_map(|scheme| word.strip_prefix(scheme))
+      .unwrap_or(word);
+    let scheme = &word[..word.len() - word_host.len()];
+    hosts
+      .iter()
+      .filter(|host| host.starts_with(word_host))
+      .map(|host| Completion {
+        replacement: format!("{}{}", scheme, host),
+        description: String::new(),
+      })
+      .collect()
+  }
+}
+
+#[cfg(test)]
+mod tests_completion {
+  use super::*;
+  use crate::GopherURL;
+
+  fn replacements(completions: (usize, Vec<Completion>)) -> (usize, Vec<String>) {
+    (
+      completions.0,
+      completions
+        .1
+        .into_iter()
+        .map(|completion| completion.replacement)
+        .collect(),
+    )
+  }
+
+  #[test]
+  fn should_complete_commands() {
+    let state = ClientState::new();
+    assert_eq!(
+      (0, vec!["tab".to_string(), "tabs".to_string()]),
+      replacements(state.complete("ta"))
+    );
+    assert_eq!(
+      (4, vec!["close".to_string()]),
+      replacements(state.complete("tab c"))
+    );
+  }
+
+  #[test]
+  fn should_complete_bookmarks_and_hosts() {
+    let mut state = ClientState::new();
+    state.bookmarks = vec![
+      GopherURL::from("gopher://khzae.net").unwrap(),
+      GopherURL::from("gopher://sdf.org/1/users").unwrap(),
+    ];
+    state.history = vec![GopherURL::from("gopher://gopherpedia.com").unwrap()];
+
+    assert_eq!(
+      (3, vec!["1".to_string()]),
+      replacements(state.complete("bk sdf"))
+    );
+    assert_eq!(
+      (6, vec!["0".to_string(), "1".to_string()]),
+      replacements(state.complete("bk rm "))
+    );
+    assert_eq!(
+      (3, vec!["gopherpedia.com".to_string()]),
+      replacements(state.complete("go go"))
+    );
+    assert_eq!(
+      (
+        8,
+        vec![
+          "gopher://gopherpedia.com".to_string(),
+          "gopher://khzae.net".to_string(),
+          "gopher://sdf.org".to_string()
+        ]
+      ),
+      replacements(state.complete("tab new gopher://"))
+    );
+  }
+}
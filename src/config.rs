@@ -0,0 +1,381 @@
+// Copyright © Killian Kemps (2019)
+//
+// Killian Kemps <developer@killiankemps.fr>
+//
+// This software is a computer program whose purpose is to communicate with
+// the Gopher protocol.
+//
+// This software is governed by the CeCILL license under French law and
+// abiding by the rules of distribution of free software.  You can  use,
+// modify and/ or redistribute the software under the terms of the CeCILL
+// license as circulated by CEA, CNRS and INRIA at the following URL
+// "http://www.cecill.info".
+//
+// As a counterpart to the access to the source code and  rights to copy,
+// modify and redistribute granted by the license, users are provided only
+// with a limited warranty  and the software's author,  the holder of the
+// economic rights,  and the successive licensors  have only  limited
+// liability.
+//
+// In this respect, the user's attention is drawn to the risks associated
+// with loading,  using,  modifying and/or developing or reproducing the
+// software by the user in light of its specific status of free software,
+// that may mean  that it is complicated to manipulate,  and  that  also
+// therefore means  that it is reserved for developers  and  experienced
+// professionals having in-depth computer knowledge. Users are therefore
+// encouraged to load and test the software's suitability as regards their
+// requirements in conditions enabling the security of their systems and/or
+// data to be ensured and,  more generally, to use and operate it in the
+// same conditions as regards security.
+//
+// The fact that you are presently reading this means that you have had
+// knowledge of the CeCILL license and that you accept its terms.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::File;
+use std::io::{ErrorKind, Read};
+
+use crate::theme::{ColourMode, Theme};
+use crate::SOFTWARE_NAME;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SanitizeMode {
+  // Every control character and escape sequence is removed
+  Strict,
+  // Same as strict but SGR colour sequences are kept for ANSI art
+  Colours,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WrapMode {
+  Off,
+  // Wrap at the width of the terminal
+  Terminal,
+  Column(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MenuMode {
+  // Accept menus as most servers send them, see GopherMenuLine::from_lenient()
+  Lenient,
+  // Every deviation from RFC 1436 is an error, for server authors
+  Strict,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+  Text,
+  // Pages are printed as JSON for other tools, see json.rs
+  Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AddressPreference {
+  // Both families are tried, the preferred one first, see connect.rs
+  Ipv6,
+  Ipv4,
+  Ipv6Only,
+  Ipv4Only,
+}
+
+impl AddressPreference {
+  fn from(value: &str) -> Result<AddressPreference, String> {
+    match value {
+      "ipv6" => Ok(AddressPreference::Ipv6),
+      "ipv4" => Ok(AddressPreference::Ipv4),
+      "ipv6-only" => Ok(AddressPreference::Ipv6Only),
+      "ipv4-only" => Ok(AddressPreference::Ipv4Only),
+      _ => Err(format!("Unknown address preference \"{}\"", value)),
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Proxy {
+  Direct,
+  // Address of a SOCKS5 proxy resolving host names itself, like Tor
+  Socks5 { host: String, port: u16 },
+}
+
+impl Proxy {
+  // Parse "direct" or "socks5://host:port", the scheme being optional
+  fn from(value: &str) -> Result<Proxy, String> {
+    if value == "direct" || value == "none" {
+      return Ok(Proxy::Direct);
+    }
+    let invalid = || format!("Invalid proxy \"{}\"", value);
+    let address = value.strip_prefix("socks5://").unwrap_or(value);
+    let (host, port) = address.rsplit_once(':').ok_or_else(invalid)?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    match port.parse::<u16>() {
+      Ok(port) if !host.is_empty() && port > 0 => Ok(Proxy::Socks5 {
+        host: host.to_string(),
+        port,
+      }),
+      _ => Err(invalid()),
+    }
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+  pub sanitize: SanitizeMode,
+  pub colours: ColourMode,
+  pub theme: Theme,
+  pub wrap: WrapMode,
+  pub format: OutputFormat,
+  pub menus: MenuMode,
+  // Aliases and macros, see alias::expand()
+  pub aliases: BTreeMap<String, String>,
+  pub prefer: AddressPreference,
+  // Address preferences of hosts, in lower case
+  pub prefer_hosts: BTreeMap<String, AddressPreference>,
+  pub proxy: Proxy,
+  // Proxies of hosts, in lower case, or of domains given as "*.domain"
+  pub proxy_hosts: BTreeMap<String, Proxy>,
+  // Report connections on stderr
+  pub verbose: bool,
+}
+
+impl Config {
+  pub fn new() -> Config {
+    Config {
+      sanitize: SanitizeMode::Strict,
+      colours: ColourMode::Auto,
+      theme: Theme::new(),
+      wrap: WrapMode::Off,
+      format: OutputFormat::Text,
+      menus: MenuMode::Lenient,
+      aliases: BTreeMap::new(),
+      prefer: AddressPreference::Ipv6,
+      prefer_hosts: BTreeMap::new(),
+      proxy: Proxy::Direct,
+      proxy_hosts: BTreeMap::new(),
+      verbose: false,
+    }
+  }
+
+  // Parse configuration contents. Each line is a "key = value" pair and
+  // lines starting with '#' are comments. Invalid lines are skipped and
+  // reported in the returned warnings.
+  pub fn from(contents: &str) -> (Config, Vec<String>) {
+    let mut config = Config::new();
+    let mut warnings = Vec::new();
+
+    for (index, line) in contents.lines().enumerate() {
+      let line = line.trim();
+      if line.is_empty() || line.starts_with('#') {
+        continue;
+      }
+
+      match line.find('=') {
+        Some(idx) => {
+          let key = line[..idx].trim();
+          let value = line[idx + 1..].trim();
+          if let Err(msg) = config.set(key, value) {
+            warnings.push(format!("line {}: {}", index + 1, msg));
+          }
+        }
+        None => warnings.push(format!("line {}: Missing '=' in \"{}\"", index + 1, line)),
+      }
+    }
+
+    (config, warnings)
+  }
+
+  pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+    match key {
+      "sanitize" => {
+        self.sanitize = match value {
+          "strict" => SanitizeMode::Strict,
+          "colours" | "colors" => SanitizeMode::Colours,
+          _ => return Err(format!("Unknown sanitize mode \"{}\"", value)),
+        };
+      }
+      "wrap" => {
+        self.wrap = match value {
+          "off" => WrapMode::Off,
+          "auto" => WrapMode::Terminal,
+          _ => match value.parse::<usize>() {
+            Ok(width) if width > 0 => WrapMode::Column(width),
+            _ => return Err(format!("Invalid wrap column \"{}\"", value)),
+          },
+        };
+      }
+      "format" => {
+        self.format = match value {
+          "text" => OutputFormat::Text,
+          "json" => OutputFormat::Json,
+          _ => return Err(format!("Unknown output format \"{}\"", value)),
+        };
+      }
+      "menus" => {
+        self.menus = match value {
+          "lenient" => MenuMode::Lenient,
+          "strict" => MenuMode::Strict,
+          _ => return Err(format!("Unknown menu parsing mode \"{}\"", value)),
+        };
+      }
+      "verbose" => {
+        self.verbose = match value {
+          "on" => true,
+          "off" => false,
+          _ => return Err(format!("Invalid verbose value \"{}\"", value)),
+        };
+      }
+      "prefer" => self.prefer = AddressPreference::from(value)?,
+      "proxy" => self.proxy = Proxy::from(value)?,
+      _ if key.starts_with("proxy.") && key.len() > 6 => {
+        let proxy = Proxy::from(value)?;
+        self.proxy_hosts.insert(key[6..].to_lowercase(), proxy);
+      }
+      _ if key.starts_with("prefer.") && key.len() > 7 => {
+        let preference = AddressPreference::from(value)?;
+        self
+          .prefer_hosts
+          .insert(key[7..].to_lowercase(), preference);
+      }
+      "colours" | "colors" => self.colours = ColourMode::from(value)?,
+      _ if key.starts_with("alias.") || key.starts_with("macro.") => {
+        let name = &key[6..];
+        if name.is_empty() || name.contains(char::is_whitespace) {
+          return Err(format!("Invalid alias name \"{}\"", name));
+        }
+        self.aliases.insert(name.to_string(), value.to_string());
+      }
+      _ if key.starts_with("theme.") => self.theme.set(&key[6..], value)?,
+      _ => return Err(format!("Unknown configuration key \"{}\"", key)),
+    }
+    Ok(())
+  }
+
+  pub fn address_preference(&self, host: &str) -> AddressPreference {
+    match self.prefer_hosts.get(&host.to_lowercase()) {
+      Some(preference) => *preference,
+      None => self.prefer,
+    }
+  }
+
+  // Proxy of a host: its own, the one of its most specific domain, or the
+  // default one
+  pub fn proxy_for(&self, host: &str) -> &Proxy {
+    let host = host.to_lowercase();
+    if let Some(proxy) = self.proxy_hosts.get(&host) {
+      return proxy;
+    }
+    self
+      .proxy_hosts
+      .iter()
+      .filter(|(pattern, _)| pattern.starts_with("*.") && host.ends_with(&pattern[1..]))
+      .max_by_key(|(pattern, _)| pattern.len())
+      .map_or(&self.proxy, |(_, proxy)| proxy)
+  }
+
+  // Load configuration from ~/.marmotte/config.txt, falling back to the
+  // defaults when the file does not exist
+  pub fn load() -> Config {
+    let home = match env::var("HOME") {
+      Ok(home) => home,
+      Err(_) => return Config::new(),
+    };
+    let config_location = format!("{}/.{}/config.txt", home, SOFTWARE_NAME);
+
+    let mut contents = String::new();
+    match File::open(&config_location) {
+      Ok(mut file) => {
+        if let Err(e) = file.read_to_string(&mut contents) {
+          println!("Problem reading the configuration file: {:?}", e);
+          return Config::new();
+        }
+      }
+      Err(error) => {
+        if error.kind() != ErrorKind::NotFound {
+          println!("Problem opening the configuration file: {:?}", error);
+        }
+        return Config::new();
+      }
+    }
+
+    let (config, warnings) = Config::from(&contents);
+    for warning in warnings {
+      println!("{}: Configuration {}", SOFTWARE_NAME, warning);
+    }
+    config
+  }
+}
+
+#[cfg(test)]
+mod tests_config {
+  use super::*;
+
+  #[test]
+  fn should_parse_config_file() {
+    let (config, warnings) = Config::from(
+      "\
+       # Keep colours from ANSI art holes\n\
+       \n\
+       sanitize = colours\n\
+       colours = always\n\
+       theme.menu = bold cyan\n\
+       wrap = 72\n\
+       format = json\n\
+       menus = strict\n\
+       alias.q = quit\n\
+       macro.wp = gopher://gopherpedia.com/7/lookup%09{}\n\
+       prefer = ipv4\n\
+       prefer.SDF.org = ipv6-only\n\
+       verbose = on\n\
+       proxy.*.onion = socks5://127.0.0.1:9050\n\
+       proxy.*.hidden.onion = [::1]:9150\n\
+       proxy.open.onion = direct\n",
+    );
+    assert_eq!(SanitizeMode::Colours, config.sanitize);
+    assert_eq!(ColourMode::Always, config.colours);
+    assert_eq!("1;36".to_string(), config.theme.menu);
+    assert_eq!(WrapMode::Column(72), config.wrap);
+    assert_eq!(OutputFormat::Json, config.format);
+    assert_eq!(MenuMode::Strict, config.menus);
+    assert_eq!(Some(&"quit".to_string()), config.aliases.get("q"));
+    assert_eq!(
+      Some(&"gopher://gopherpedia.com/7/lookup%09{}".to_string()),
+      config.aliases.get("wp")
+    );
+    assert_eq!(
+      AddressPreference::Ipv4,
+      config.address_preference("khzae.net")
+    );
+    assert_eq!(
+      AddressPreference::Ipv6Only,
+      config.address_preference("sdf.org")
+    );
+    assert!(config.verbose);
+    let tor = Proxy::Socks5 {
+      host: "127.0.0.1".to_string(),
+      port: 9050,
+    };
+    let tor_browser = Proxy::Socks5 {
+      host: "::1".to_string(),
+      port: 9150,
+    };
+    assert_eq!(&tor, config.proxy_for("Gopher.ONION"));
+    assert_eq!(&tor_browser, config.proxy_for("a.hidden.onion"));
+    assert_eq!(&Proxy::Direct, config.proxy_for("open.onion"));
+    assert_eq!(&Proxy::Direct, config.proxy_for("sdf.org"));
+    assert!(warnings.is_empty());
+  }
+
+  #[test]
+  fn should_report_invalid_lines() {
+    let (config, warnings) = Config::from("sanitize = rainbow\nunknown = 1\ngarbage\n");
+    assert_eq!(SanitizeMode::Strict, config.sanitize);
+    assert_eq!(
+      vec![
+        "line 1: Unknown sanitize mode \"rainbow\"".to_string(),
+        "line 2: Unknown configuration key \"unknown\"".to_string(),
+        "line 3: Missing '=' in \"garbage\"".to_string(),
+      ],
+      warnings
+    );
+  }
+}
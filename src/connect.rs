@@ -0,0 +1,337 @@
+// Copyright © Killian Kemps (2019)
+//
+// Killian Kemps <developer@killiankemps.fr>
+//
+// This software is a computer program whose purpose is to communicate with
+// the Gopher protocol.
+//
+// This software is governed by the CeCILL license under French law and
+// abiding by the rules of distribution of free software.  You can  use,
+// modify and/ or redistribute the software under the terms of the CeCILL
+// license as circulated by CEA, CNRS and INRIA at the following URL
+// "http://www.cecill.info".
+//
+// As a counterpart to the access to the source code and  rights to copy,
+// modify and redistribute granted by the license, users are provided only
+// with a limited warranty  and the software's author,  the holder of the
+// economic rights,  and the successive licensors  have only  limited
+// liability.
+//
+// In this respect, the user's attention is drawn to the risks associated
+// with loading,  using,  modifying and/or developing or reproducing the
+// software by the user in light of its specific status of free software,
+// that may mean  that it is complicated to manipulate,  and  that  also
+// therefore means  that it is reserved for developers  and  experienced
+// professionals having in-depth computer knowledge. Users are therefore
+// encouraged to load and test the software's suitability as regards their
+// requirements in conditions enabling the security of their systems and/or
+// data to be ensured and,  more generally, to use and operate it in the
+// same conditions as regards security.
+//
+// The fact that you are presently reading this means that you have had
+// knowledge of the CeCILL license and that you accept its terms.
+
+// Connection to servers with every address of their host, racing IPv6 and
+// IPv4 attempts as described by RFC 8305 (happy eyeballs): an attempt starts
+// when the previous one failed or has been pending for a moment, and the
+// first established connection wins. Hosts may also be reached through a
+// SOCKS5 proxy (RFC 1928), which resolves their name itself.
+
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+use crate::config::{AddressPreference, Config, Proxy};
+
+// Time given to an attempt before starting the next one, RFC 8305 section 5
+const ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+// Time given to each attempt to establish the connection
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Order addresses by alternating families, starting with the preferred one
+fn sort_addresses(addresses: Vec<SocketAddr>, preference: AddressPreference) -> Vec<SocketAddr> {
+  let (ipv6, ipv4): (Vec<SocketAddr>, Vec<SocketAddr>) =
+    addresses.into_iter().partition(SocketAddr::is_ipv6);
+  let (first, second) = match preference {
+    AddressPreference::Ipv6 => (ipv6, ipv4),
+    AddressPreference::Ipv4 => (ipv4, ipv6),
+    AddressPreference::Ipv6Only => (ipv6, Vec::new()),
+    AddressPreference::Ipv4Only => (ipv4, Vec::new()),
+  };
+
+  let mut sorted = Vec::with_capacity(first.len() + second.len());
+  let mut first = first.into_iter();
+  let mut second = second.into_iter();
+  loop {
+    match (first.next(), second.next()) {
+      (None, None) => return sorted,
+      (a, b) => sorted.extend(a.into_iter().chain(b)),
+    }
+  }
+}
+
+// Try the addresses in order, each attempt in its own thread. Connections
+// established after the winning one are closed when their thread ends.
+fn race(addresses: &[SocketAddr], timeout: Duration) -> io::Result<TcpStream> {
+  let (sender, receiver) = mpsc::channel();
+  let mut addresses = addresses.iter();
+  let mut pending = 0;
+  let mut last_error = None;
+
+  loop {
+    if let Some(address) = addresses.next() {
+      let sender = sender.clone();
+      let address = *address;
+      thread::spawn(move || {
+        let _ = sender.send(TcpStream::connect_timeout(&address, timeout));
+      });
+      pending += 1;
+    }
+    if pending == 0 {
+      break;
+    }
+
+    let result = if addresses.len() > 0 {
+      receiver.recv_timeout(ATTEMPT_DELAY)
+    } else {
+      receiver.recv().map_err(|_| RecvTimeoutError::Disconnected)
+    };
+    match result {
+      Ok(Ok(stream)) => return Ok(stream),
+      Ok(Err(e)) => {
+        pending -= 1;
+        last_error = Some(e);
+      }
+      // Still pending, the next attempt starts in parallel
+      Err(_) => (),
+    }
+  }
+
+  Err(
+    last_error
+      .unwrap_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No address to connect to")),
+  )
+}
+
+// Connect to a server, directly or through its proxy, reporting the address
+// used when verbose
+pub fn connect(host: &str, port: u16, config: &Config, timeout: Duration) -> io::Result<TcpStream> {
+  let (proxy_host, proxy_port) = match config.proxy_for(host) {
+    Proxy::Direct => return connect_directly(host, port, config, timeout),
+    Proxy::Socks5 { host, port } => (host, *port),
+  };
+  let mut stream = connect_directly(proxy_host, proxy_port, config, timeout)?;
+  stream.set_read_timeout(Some(timeout))?;
+  stream.set_write_timeout(Some(timeout))?;
+  socks5_handshake(&mut stream, host, port)?;
+  stream.set_read_timeout(None)?;
+  stream.set_write_timeout(None)?;
+  if config.verbose {
+    eprintln!("Connected to {}:{} through proxy", host, port);
+  }
+  Ok(stream)
+}
+
+fn connect_directly(
+  host: &str,
+  port: u16,
+  config: &Config,
+  timeout: Duration,
+) -> io::Result<TcpStream> {
+  let addresses = (host, port).to_socket_addrs()?.collect();
+  let addresses = sort_addresses(addresses, config.address_preference(host));
+  if addresses.is_empty() {
+    return Err(io::Error::new(
+      io::ErrorKind::NotFound,
+      format!("No address allowed for {}", host),
+    ));
+  }
+
+  let stream = race(&addresses, timeout)?;
+  if config.verbose {
+    if let Ok(address) = stream.peer_addr() {
+      eprintln!("Connected to {} ({})", address, host);
+    }
+  }
+  Ok(stream)
+}
+
+fn socks5_error(message: &str) -> io::Error {
+  io::Error::other(format!("SOCKS5 proxy: {}", message))
+}
+
+// Ask the proxy to connect to the host, without authentication. Host names
+// are sent as is so that they are resolved by the proxy.
+fn socks5_handshake(stream: &mut TcpStream, host: &str, port: u16) -> io::Result<()> {
+  stream.write_all(&[5, 1, 0])?;
+  let mut reply = [0; 2];
+  stream.read_exact(&mut reply)?;
+  if reply != [5, 0] {
+    return Err(socks5_error("authentication required"));
+  }
+
+  let mut request = vec![5, 1, 0];
+  match host.parse::<IpAddr>() {
+    Ok(IpAddr::V4(address)) => {
+      request.push(1);
+      request.extend_from_slice(&address.octets());
+    }
+    Ok(IpAddr::V6(address)) => {
+      request.push(4);
+      request.extend_from_slice(&address.octets());
+    }
+    Err(_) if host.len() <= 255 => {
+      request.push(3);
+      request.push(host.len() as u8);
+      request.extend_from_slice(host.as_bytes());
+    }
+    Err(_) => return Err(socks5_error("host name too long")),
+  }
+  request.extend_from_slice(&port.to_be_bytes());
+  stream.write_all(&request)?;
+
+  let mut reply = [0; 4];
+  stream.read_exact(&mut reply)?;
+  let message = match reply[1] {
+    0 => None,
+    1 => Some("general failure"),
+    2 => Some("connection not allowed by ruleset"),
+    3 => Some("network unreachable"),
+    4 => Some("host unreachable"),
+    5 => Some("connection refused"),
+    6 => Some("TTL expired"),
+    7 => Some("command not supported"),
+    8 => Some("address type not supported"),
+    _ => Some("unknown error"),
+  };
+  if let Some(message) = message {
+    return Err(socks5_error(message));
+  }
+
+  // The address bound by the proxy is not used
+  let address_length = match reply[3] {
+    1 => 4,
+    4 => 16,
+    3 => {
+      let mut length = [0; 1];
+      stream.read_exact(&mut length)?;
+      length[0] as usize
+    }
+    _ => return Err(socks5_error("invalid reply")),
+  };
+  let mut bound = vec![0; address_length + 2];
+  stream.read_exact(&mut bound)?;
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests_connect {
+  use super::*;
+  use std::net::TcpListener;
+
+  // Local stand-in of a SOCKS5 proxy accepting one connection and serving a
+  // menu whatever the destination, which is returned with the selector
+  fn socks5_proxy(reply_code: u8) -> (u16, thread::JoinHandle<(Vec<u8>, String)>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let handle = thread::spawn(move || {
+      let (mut stream, _) = listener.accept().unwrap();
+      let mut greeting = [0; 3];
+      stream.read_exact(&mut greeting).unwrap();
+      stream.write_all(&[5, 0]).unwrap();
+
+      let mut request = [0; 5];
+      stream.read_exact(&mut request).unwrap();
+      let mut destination = vec![0; request[4] as usize + 2];
+      stream.read_exact(&mut destination).unwrap();
+      stream
+        .write_all(&[5, reply_code, 0, 1, 127, 0, 0, 1, 0, 70])
+        .unwrap();
+      let mut destination_request = request.to_vec();
+      destination_request.extend(destination);
+      if reply_code != 0 {
+        return (destination_request, String::new());
+      }
+
+      let mut selector = String::new();
+      let mut byte = [0; 1];
+      while stream.read(&mut byte).unwrap() == 1 && byte[0] != b'\n' {
+        selector.push(byte[0] as char);
+      }
+      stream
+        .write_all(b"iHidden hole\t\tnull.host\t1\r\n.\r\n")
+        .unwrap();
+      (destination_request, selector)
+    });
+    (port, handle)
+  }
+
+  #[test]
+  fn should_connect_through_socks5_proxy() {
+    let (port, proxy) = socks5_proxy(0);
+    let (config, _) = Config::from(&format!("proxy.*.onion = socks5://127.0.0.1:{}", port));
+    let mut stream = connect("gopher.onion", 70, &config, CONNECT_TIMEOUT).unwrap();
+    stream.write_all(b"/phlog\r\n").unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+
+    let (request, selector) = proxy.join().unwrap();
+    // The host name is sent to the proxy, not resolved locally
+    assert_eq!(
+      b"\x05\x01\x00\x03\x0cgopher.onion\x00\x46".to_vec(),
+      request
+    );
+    assert_eq!("/phlog\r", selector);
+    assert!(response.starts_with("iHidden hole"));
+  }
+
+  #[test]
+  fn should_report_socks5_errors() {
+    let (port, proxy) = socks5_proxy(4);
+    let (config, _) = Config::from(&format!("proxy = 127.0.0.1:{}", port));
+    let error = connect("gopher.onion", 70, &config, CONNECT_TIMEOUT).unwrap_err();
+    proxy.join().unwrap();
+    assert_eq!("SOCKS5 proxy: host unreachable", error.to_string());
+  }
+
+  #[test]
+  fn should_alternate_address_families() {
+    let addresses: Vec<SocketAddr> = ["[::1]:70", "[::2]:70", "[::3]:70", "10.0.0.1:70"]
+      .iter()
+      .map(|address| address.parse().unwrap())
+      .collect();
+    let sorted = |preference| -> Vec<String> {
+      sort_addresses(addresses.clone(), preference)
+        .iter()
+        .map(|address| address.to_string())
+        .collect()
+    };
+    assert_eq!(
+      vec!["[::1]:70", "10.0.0.1:70", "[::2]:70", "[::3]:70"],
+      sorted(AddressPreference::Ipv6)
+    );
+    assert_eq!(
+      vec!["10.0.0.1:70", "[::1]:70", "[::2]:70", "[::3]:70"],
+      sorted(AddressPreference::Ipv4)
+    );
+    assert_eq!(vec!["10.0.0.1:70"], sorted(AddressPreference::Ipv4Only));
+  }
+
+  #[test]
+  fn should_fall_back_to_next_addresses() {
+    // The port of a closed listener refuses connections
+    let closed = TcpListener::bind("127.0.0.1:0")
+      .unwrap()
+      .local_addr()
+      .unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let open = listener.local_addr().unwrap();
+
+    let stream = race(&[closed, open], CONNECT_TIMEOUT).unwrap();
+    assert_eq!(open, stream.peer_addr().unwrap());
+    assert!(race(&[closed], CONNECT_TIMEOUT).is_err());
+    assert!(race(&[], CONNECT_TIMEOUT).is_err());
+  }
+}
@@ -0,0 +1,545 @@
+// Copyright © Killian Kemps (2019)
+//
+// Killian Kemps <developer@killiankemps.fr>
+//
+// This software is a computer program whose purpose is to communicate with
+// the Gopher protocol.
+//
+// This software is governed by the CeCILL license under French law and
+// abiding by the rules of distribution of free software.  You can  use,
+// modify and/ or redistribute the software under the terms of the CeCILL
+// license as circulated by CEA, CNRS and INRIA at the following URL
+// "http://www.cecill.info".
+//
+// As a counterpart to the access to the source code and  rights to copy,
+// modify and redistribute granted by the license, users are provided only
+// with a limited warranty  and the software's author,  the holder of the
+// economic rights,  and the successive licensors  have only  limited
+// liability.
+//
+// In this respect, the user's attention is drawn to the risks associated
+// with loading,  using,  modifying and/or developing or reproducing the
+// software by the user in light of its specific status of free software,
+// that may mean  that it is complicated to manipulate,  and  that  also
+// therefore means  that it is reserved for developers  and  experienced
+// professionals having in-depth computer knowledge. Users are therefore
+// encouraged to load and test the software's suitability as regards their
+// requirements in conditions enabling the security of their systems and/or
+// data to be ensured and,  more generally, to use and operate it in the
+// same conditions as regards security.
+//
+// The fact that you are presently reading this means that you have had
+// knowledge of the CeCILL license and that you accept its terms.
+
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, stdin, IsTerminal, Write};
+use std::path::PathBuf;
+
+use crate::SOFTWARE_NAME;
+
+// Number of commands kept in ~/.marmotte/history.txt
+const HISTORY_SIZE: usize = 1000;
+
+pub struct Completion {
+  // Text replacing the word being completed
+  pub replacement: String,
+  // Displayed next to the replacement when listing candidates
+  pub description: String,
+}
+
+#[derive(Debug, PartialEq)]
+enum Key {
+  Char(char),
+  Ctrl(char),
+  Enter,
+  Tab,
+  Backspace,
+  Delete,
+  Left,
+  Right,
+  Up,
+  Down,
+  Home,
+  End,
+  Escape,
+  Unknown,
+}
+
+// Line editor with command history and completion. Raw terminal mode is
+// only used while reading a line, and only when stdin is a terminal: piped
+// input is read line by line.
+pub struct LineEditor {
+  history: Vec<String>,
+  history_path: Option<PathBuf>,
+}
+
+impl LineEditor {
+  pub fn new() -> LineEditor {
+    let history_path = env::var("HOME")
+      .ok()
+      .map(|home| PathBuf::from(format!("{}/.{}/history.txt", home, SOFTWARE_NAME)));
+    let mut history: Vec<String> = match &history_path {
+      Some(path) => match fs::read_to_string(path) {
+        Ok(contents) => contents.lines().map(|line| line.to_string()).collect(),
+        Err(_) => Vec::new(),
+      },
+      None => Vec::new(),
+    };
+    if history.len() > HISTORY_SIZE {
+      history.drain(..history.len() - HISTORY_SIZE);
+    }
+    LineEditor {
+      history,
+      history_path,
+    }
+  }
+
+  fn add_history(&mut self, line: &str) {
+    if line.is_empty() || self.history.last().map(|last| &last[..]) == Some(line) {
+      return;
+    }
+    self.history.push(line.to_string());
+
+    if let Some(path) = &self.history_path {
+      if self.history.len() > HISTORY_SIZE {
+        self.history.remove(0);
+        let _ = fs::write(path, format!("{}\n", self.history.join("\n")));
+      } else if let Some(prefix) = path.parent() {
+        let _ = fs::create_dir_all(prefix);
+        if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(path) {
+          let _ = writeln!(file, "{}", line);
+        }
+      }
+    }
+  }
+
+  // Read a line, returning None at the end of input
+  pub fn read_line(
+    &mut self,
+    prompt: &str,
+    complete: &dyn Fn(&str) -> (usize, Vec<Completion>),
+  ) -> Option<String> {
+    print!("{}", prompt);
+    io::stdout().flush().unwrap();
+
+    let line = if stdin().is_terminal() {
+      self.edit_line(prompt, complete)
+    } else {
+      let mut line = String::new();
+      match stdin().read_line(&mut line) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(line.trim_end_matches(['\r', '\n']).to_string()),
+      }
+    };
+
+    if let Some(line) = &line {
+      self.add_history(line.trim());
+    }
+    line
+  }
+
+  #[cfg(not(unix))]
+  fn edit_line(
+    &mut self,
+    _prompt: &str,
+    _complete: &dyn Fn(&str) -> (usize, Vec<Completion>),
+  ) -> Option<String> {
+    let mut line = String::new();
+    match stdin().read_line(&mut line) {
+      Ok(0) | Err(_) => None,
+      Ok(_) => Some(line.trim_end_matches(['\r', '\n']).to_string()),
+    }
+  }
+
+  #[cfg(unix)]
+  fn edit_line(
+    &mut self,
+    prompt: &str,
+    complete: &dyn Fn(&str) -> (usize, Vec<Completion>),
+  ) -> Option<String> {
+    if !terminal::enable_raw_mode() {
+      let mut line = String::new();
+      return match stdin().read_line(&mut line) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(line.trim_end_matches(['\r', '\n']).to_string()),
+      };
+    }
+
+    let mut buffer: Vec<char> = Vec::new();
+    let mut cursor = 0;
+    // Position in history, history.len() being the line being edited
+    let mut history_index = self.history.len();
+    let mut edited_line: Vec<char> = Vec::new();
+
+    let result = loop {
+      let key = match terminal::read_key() {
+        Some(key) => key,
+        None => break None,
+      };
+
+      match key {
+        Key::Enter => break Some(buffer.iter().collect()),
+        Key::Char(c) => {
+          buffer.insert(cursor, c);
+          cursor += 1;
+        }
+        Key::Backspace | Key::Ctrl('h') if cursor > 0 => {
+          cursor -= 1;
+          buffer.remove(cursor);
+        }
+        Key::Delete if cursor < buffer.len() => {
+          buffer.remove(cursor);
+        }
+        // End of input on an empty line, delete otherwise
+        Key::Ctrl('d') => {
+          if buffer.is_empty() {
+            break None;
+          }
+          if cursor < buffer.len() {
+            buffer.remove(cursor);
+          }
+        }
+        // Cancel the current line
+        Key::Ctrl('c') => {
+          print!("^C\r\n");
+          buffer.clear();
+          cursor = 0;
+          history_index = self.history.len();
+        }
+        Key::Left | Key::Ctrl('b') => cursor = cursor.saturating_sub(1),
+        Key::Right | Key::Ctrl('f') => cursor = (cursor + 1).min(buffer.len()),
+        Key::Home | Key::Ctrl('a') => cursor = 0,
+        Key::End | Key::Ctrl('e') => cursor = buffer.len(),
+        Key::Ctrl('k') => buffer.truncate(cursor),
+        Key::Ctrl('u') => {
+          buffer.drain(..cursor);
+          cursor = 0;
+        }
+        // Delete the word before the cursor
+        Key::Ctrl('w') => {
+          let mut start = cursor;
+          while start > 0 && buffer[start - 1] == ' ' {
+            start -= 1;
+          }
+          while start > 0 && buffer[start - 1] != ' ' {
+            start -= 1;
+          }
+          buffer.drain(start..cursor);
+          cursor = start;
+        }
+        Key::Ctrl('l') => print!("\u{1b}[H\u{1b}[2J"),
+        Key::Up | Key::Ctrl('p') if history_index > 0 => {
+          if history_index == self.history.len() {
+            edited_line = buffer.clone();
+          }
+          history_index -= 1;
+          buffer = self.history[history_index].chars().collect();
+          cursor = buffer.len();
+        }
+        Key::Down | Key::Ctrl('n') if history_index < self.history.len() => {
+          history_index += 1;
+          buffer = if history_index == self.history.len() {
+            edited_line.clone()
+          } else {
+            self.history[history_index].chars().collect()
+          };
+          cursor = buffer.len();
+        }
+        Key::Tab => {
+          let before_cursor: String = buffer[..cursor].iter().collect();
+          let (start, completions) = complete(&before_cursor);
+          let start = before_cursor[..start].chars().count();
+          let word: String = buffer[start..cursor].iter().collect();
+          let replacements: Vec<&str> = completions
+            .iter()
+            .map(|completion| &completion.replacement[..])
+            .collect();
+
+          if replacements.len() == 1 {
+            let mut replacement = replacements[0].to_string();
+            if !replacement.ends_with('/') {
+              replacement.push(' ');
+            }
+            buffer.splice(start..cursor, replacement.chars());
+            cursor = start + replacement.chars().count();
+          } else if replacements.len() > 1 {
+            let prefix = common_prefix(&replacements);
+            if prefix.chars().count() > word.chars().count() {
+              buffer.splice(start..cursor, prefix.chars());
+              cursor = start + prefix.chars().count();
+            } else {
+              print!("\r\n");
+              for completion in &completions {
+                print!("{}  {}\r\n", completion.replacement, completion.description);
+              }
+            }
+          } else {
+            print!("\u{07}");
+          }
+        }
+        Key::Ctrl('r') => {
+          if let Some((line, accepted)) = self.reverse_search(&buffer) {
+            buffer = line.chars().collect();
+            cursor = buffer.len();
+            if accepted {
+              break Some(line);
+            }
+          }
+        }
+        _ => {}
+      }
+
+      refresh_line(prompt, &buffer, cursor);
+    };
+
+    print!("\r\n");
+    io::stdout().flush().unwrap();
+    terminal::disable_raw_mode();
+    result
+  }
+
+  // Incremental search in history (Ctrl-R). Return the found line and
+  // whether it has been accepted with Enter, or None when cancelled.
+  #[cfg(unix)]
+  fn reverse_search(&self, buffer: &[char]) -> Option<(String, bool)> {
+    let mut query = String::new();
+    let mut found: Option<usize> = None;
+
+    loop {
+      let line = found.map(|idx| &self.history[idx][..]).unwrap_or("");
+      print!("\r(reverse-i-search)`{}': {}\u{1b}[K", query, line);
+      io::stdout().flush().unwrap();
+
+      match terminal::read_key() {
+        Some(Key::Char(c)) => {
+          query.push(c);
+          found = self.search_history(&query, found.map(|idx| idx + 1));
+        }
+        Some(Key::Backspace) => {
+          query.pop();
+          found = self.search_history(&query, None);
+        }
+        // Look for an older match
+        Some(Key::Ctrl('r')) => {
+          if let Some(idx) = found {
+            if let Some(older) = self.search_history(&query, Some(idx)) {
+              found = Some(older);
+            }
+          }
+        }
+        Some(Key::Enter) => return Some((line.to_string(), true)),
+        Some(Key::Ctrl('g')) | Some(Key::Ctrl('c')) | Some(Key::Escape) | None => {
+          return Some((buffer.iter().collect(), false));
+        }
+        // Any other key keeps the found line for edition
+        Some(_) => return Some((line.to_string(), false)),
+      }
+    }
+  }
+
+  // Index of the most recent history entry containing the query, searching
+  // before the given index
+  fn search_history(&self, query: &str, before: Option<usize>) -> Option<usize> {
+    if query.is_empty() {
+      return None;
+    }
+    let end = before.unwrap_or(self.history.len()).min(self.history.len());
+    self.history[..end]
+      .iter()
+      .rposition(|line| line.contains(query))
+  }
+}
+
+fn refresh_line(prompt: &str, buffer: &[char], cursor: usize) {
+  let line: String = buffer.iter().collect();
+  print!("\r{}{}\u{1b}[K", prompt, line);
+  if buffer.len() > cursor {
+    print!("\u{1b}[{}D", buffer.len() - cursor);
+  }
+  io::stdout().flush().unwrap();
+}
+
+fn common_prefix(words: &[&str]) -> String {
+  let mut prefix: Vec<char> = match words.first() {
+    Some(word) => word.chars().collect(),
+    None => return String::new(),
+  };
+  for word in words {
+    let length = prefix
+      .iter()
+      .zip(word.chars())
+      .take_while(|(a, b)| *a == b)
+      .count();
+    prefix.truncate(length);
+  }
+  prefix.into_iter().collect()
+}
+
+// Restore the terminal if marmotte is stopped while reading a line
+pub fn restore_terminal() {
+  #[cfg(unix)]
+  terminal::disable_raw_mode();
+}
+
+#[cfg(unix)]
+mod terminal {
+  use std::sync::Mutex;
+
+  use super::Key;
+
+  // Terminal settings before switching to raw mode
+  static ORIGINAL_TERMIOS: Mutex<Option<libc::termios>> = Mutex::new(None);
+
+  pub fn enable_raw_mode() -> bool {
+    unsafe {
+      let mut termios: libc::termios = std::mem::zeroed();
+      if libc::tcgetattr(libc::STDIN_FILENO, &mut termios) != 0 {
+        return false;
+      }
+      let original = termios;
+      termios.c_iflag &= !(libc::ICRNL | libc::IXON);
+      termios.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG | libc::IEXTEN);
+      termios.c_cc[libc::VMIN] = 1;
+      termios.c_cc[libc::VTIME] = 0;
+      if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSAFLUSH, &termios) != 0 {
+        return false;
+      }
+      if let Ok(mut saved) = ORIGINAL_TERMIOS.lock() {
+        *saved = Some(original);
+      }
+    }
+    true
+  }
+
+  pub fn disable_raw_mode() {
+    let original = match ORIGINAL_TERMIOS.lock() {
+      Ok(mut saved) => saved.take(),
+      Err(_) => None,
+    };
+    if let Some(original) = original {
+      unsafe {
+        libc::tcsetattr(libc::STDIN_FILENO, libc::TCSAFLUSH, &original);
+      }
+    }
+  }
+
+  fn read_byte() -> Option<u8> {
+    let mut byte = 0u8;
+    loop {
+      let read = unsafe {
+        libc::read(
+          libc::STDIN_FILENO,
+          &mut byte as *mut u8 as *mut libc::c_void,
+          1,
+        )
+      };
+      match read {
+        1 => return Some(byte),
+        -1 if std::io::Error::last_os_error().kind() == std::io::ErrorKind::Interrupted => continue,
+        _ => return None,
+      }
+    }
+  }
+
+  // Whether more input is available within a short delay, to tell the
+  // Escape key apart from escape sequences
+  fn has_pending_input() -> bool {
+    let mut poll_fd = libc::pollfd {
+      fd: libc::STDIN_FILENO,
+      events: libc::POLLIN,
+      revents: 0,
+    };
+    unsafe { libc::poll(&mut poll_fd, 1, 50) > 0 }
+  }
+
+  pub fn read_key() -> Option<Key> {
+    let byte = read_byte()?;
+    let key = match byte {
+      b'\r' | b'\n' => Key::Enter,
+      b'\t' => Key::Tab,
+      127 => Key::Backspace,
+      1..=26 => Key::Ctrl((b'a' + byte - 1) as char),
+      27 => {
+        if !has_pending_input() {
+          return Some(Key::Escape);
+        }
+        match read_byte()? {
+          b'[' | b'O' => {
+            // Parameters of the sequence, e.g. "3" in ESC [ 3 ~
+            let mut params = Vec::new();
+            let mut last = read_byte()?;
+            while last.is_ascii_digit() || last == b';' {
+              params.push(last);
+              last = read_byte()?;
+            }
+            match (last, &params[..]) {
+              (b'A', _) => Key::Up,
+              (b'B', _) => Key::Down,
+              (b'C', _) => Key::Right,
+              (b'D', _) => Key::Left,
+              (b'H', _) | (b'~', b"1") | (b'~', b"7") => Key::Home,
+              (b'F', _) | (b'~', b"4") | (b'~', b"8") => Key::End,
+              (b'~', b"3") => Key::Delete,
+              _ => Key::Unknown,
+            }
+          }
+          _ => Key::Unknown,
+        }
+      }
+      // UTF-8 encoded character
+      _ => {
+        let length = match byte {
+          0x00..=0x7f => 1,
+          0xc0..=0xdf => 2,
+          0xe0..=0xef => 3,
+          0xf0..=0xf7 => 4,
+          _ => return Some(Key::Unknown),
+        };
+        let mut bytes = vec![byte];
+        for _ in 1..length {
+          bytes.push(read_byte()?);
+        }
+        match std::str::from_utf8(&bytes)
+          .ok()
+          .and_then(|s| s.chars().next())
+        {
+          Some(c) if !c.is_control() => Key::Char(c),
+          _ => Key::Unknown,
+        }
+      }
+    };
+    Some(key)
+  }
+}
+
+#[cfg(test)]
+mod tests_editor {
+  use super::*;
+
+  #[test]
+  fn should_search_history_backwards() {
+    let editor = LineEditor {
+      history: vec![
+        "go khzae.net".to_string(),
+        "bk".to_string(),
+        "go sdf.org".to_string(),
+      ],
+      history_path: None,
+    };
+    assert_eq!(Some(2), editor.search_history("go", None));
+    assert_eq!(Some(0), editor.search_history("go", Some(2)));
+    assert_eq!(None, editor.search_history("go", Some(0)));
+    assert_eq!(None, editor.search_history("", None));
+  }
+
+  #[test]
+  fn should_find_common_prefix() {
+    assert_eq!("tab".to_string(), common_prefix(&["tab", "tabs"]));
+    assert_eq!(
+      "gopher://".to_string(),
+      common_prefix(&["gopher://sdf.org", "gopher://khzae.net"])
+    );
+    assert_eq!("".to_string(), common_prefix(&[]));
+  }
+}
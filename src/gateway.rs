@@ -0,0 +1,433 @@
+// Copyright © Killian Kemps (2019)
+//
+// Killian Kemps <developer@killiankemps.fr>
+//
+// This software is a computer program whose purpose is to communicate with
+// the Gopher protocol.
+//
+// This software is governed by the CeCILL license under French law and
+// abiding by the rules of distribution of free software.  You can  use,
+// modify and/ or redistribute the software under the terms of the CeCILL
+// license as circulated by CEA, CNRS and INRIA at the following URL
+// "http://www.cecill.info".
+//
+// As a counterpart to the access to the source code and  rights to copy,
+// modify and redistribute granted by the license, users are provided only
+// with a limited warranty  and the software's author,  the holder of the
+// economic rights,  and the successive licensors  have only  limited
+// liability.
+//
+// In this respect, the user's attention is drawn to the risks associated
+// with loading,  using,  modifying and/or developing or reproducing the
+// software by the user in light of its specific status of free software,
+// that may mean  that it is complicated to manipulate,  and  that  also
+// therefore means  that it is reserved for developers  and  experienced
+// professionals having in-depth computer knowledge. Users are therefore
+// encouraged to load and test the software's suitability as regards their
+// requirements in conditions enabling the security of their systems and/or
+// data to be ensured and,  more generally, to use and operate it in the
+// same conditions as regards security.
+//
+// The fact that you are presently reading this means that you have had
+// knowledge of the CeCILL license and that you accept its terms.
+
+// HTTP gateway serving gopher pages to web browsers. Gopher URLs are given
+// in the path without their scheme, like /sdf.org:70/1/users: menus are
+// rendered as HTML with links through the gateway, text documents are sent
+// as they are and binary content is streamed.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{IpAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::thread;
+
+use crate::config::Config;
+use crate::sniff::{self, Content, SNIFF_SIZE};
+use crate::url;
+use crate::{parse_error_item, parse_response, request, GopherResponse, GopherURL};
+
+pub const DEFAULT_ADDRESS: &str = "127.0.0.1:8070";
+// Every hole is served from the origin of the gateway, so HTML pages sent by
+// servers must not run scripts able to read other pages of the gateway
+const SANDBOX_HEADERS: &str = "Content-Security-Policy: sandbox\r\n";
+
+const INDEX_PAGE: &str = "<!DOCTYPE html>\n\
+                          <html>\n\
+                          <head>\n\
+                          <meta charset=\"utf-8\">\n\
+                          <title>marmotte</title>\n\
+                          </head>\n\
+                          <body>\n\
+                          <form action=\"/\">\n\
+                          <input name=\"url\" placeholder=\"gopher://\" autofocus>\n\
+                          <button>Go</button>\n\
+                          </form>\n\
+                          </body>\n\
+                          </html>\n";
+
+// Path of a gopher URL on the gateway
+fn gateway_path(url: &GopherURL) -> Option<String> {
+  url
+    .get_url()
+    .map(|full_url| format!("/{}", full_url.trim_start_matches("gopher://")))
+}
+
+// Browsers must not guess another type than the one given
+fn respond_with_headers(
+  client: &mut dyn Write,
+  status: &'static str,
+  content_type: &str,
+  headers: &str,
+  body: &[u8],
+) -> io::Result<&'static str> {
+  write!(
+    client,
+    "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nX-Content-Type-Options: nosniff\r\n{}Connection: close\r\n\r\n",
+    status,
+    content_type,
+    body.len(),
+    headers
+  )?;
+  client.write_all(body)?;
+  Ok(status)
+}
+
+fn respond(
+  client: &mut dyn Write,
+  status: &'static str,
+  content_type: &str,
+  body: &[u8],
+) -> io::Result<&'static str> {
+  respond_with_headers(client, status, content_type, "", body)
+}
+
+fn respond_error(
+  client: &mut dyn Write,
+  status: &'static str,
+  message: &str,
+) -> io::Result<&'static str> {
+  respond(
+    client,
+    status,
+    "text/plain; charset=utf-8",
+    format!("{}\n", message).as_bytes(),
+  )
+}
+
+// Answer a GET request for the target, returning the status sent
+fn answer(target: &str, config: &Config, client: &mut dyn Write) -> io::Result<&'static str> {
+  let (path, query) = match target.split_once('?') {
+    Some((path, query)) => (path, Some(query)),
+    None => (target, None),
+  };
+
+  if path == "/" {
+    // URLs entered in the form of the index page
+    let entered = query
+      .unwrap_or("")
+      .split('&')
+      .find_map(|parameter| parameter.strip_prefix("url="));
+    let url = match entered {
+      Some(value) => url::decode(&value.replace('+', " ")).map_err(String::from),
+      None => {
+        return respond(
+          client,
+          "200 OK",
+          "text/html; charset=utf-8",
+          INDEX_PAGE.as_bytes(),
+        )
+      }
+    };
+    return match url.and_then(|url| GopherURL::from(&url).map_err(String::from)) {
+      Ok(url) => {
+        let location = gateway_path(&url).unwrap_or_default();
+        write!(
+          client,
+          "HTTP/1.1 302 Found\r\nLocation: {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+          location
+        )?;
+        Ok("302 Found")
+      }
+      Err(msg) => respond_error(client, "400 Bad Request", &msg),
+    };
+  }
+
+  let url = match GopherURL::from(&path[1..]) {
+    Ok(url) => url,
+    Err(error) => return respond_error(client, "400 Bad Request", &error.to_string()),
+  };
+  let mut stream = match request(&url, config) {
+    Ok(stream) => stream,
+    Err(msg) => return respond_error(client, "502 Bad Gateway", &msg),
+  };
+
+  let mut raw = Vec::new();
+  if let Err(e) = (&mut stream).take(SNIFF_SIZE as u64).read_to_end(&mut raw) {
+    return respond_error(
+      client,
+      "502 Bad Gateway",
+      &format!("Failed to receive data: {}", e),
+    );
+  }
+  if let Content::Binary(_) = sniff::sniff(&raw) {
+    write!(
+      client,
+      "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nX-Content-Type-Options: nosniff\r\n{}Connection: close\r\n\r\n",
+      sniff::mime_type(&raw, &url.selector),
+      SANDBOX_HEADERS
+    )?;
+    client.write_all(&raw)?;
+    io::copy(&mut stream, client)?;
+    return Ok("200 OK");
+  }
+
+  if let Err(e) = stream.read_to_end(&mut raw) {
+    return respond_error(
+      client,
+      "502 Bad Gateway",
+      &format!("Failed to receive data: {}", e),
+    );
+  }
+  if let Some(msg) = parse_error_item(&raw, &url.r#type) {
+    return respond_error(client, "404 Not Found", &msg);
+  }
+  // Pages are shown as menus or text like in the client, HTML being kept
+  match parse_response(&raw, &url.r#type, config.menus) {
+    menu @ GopherResponse::Menu(_) => {
+      // Links to gopher pages stay on the gateway
+      let link = |target: &str| {
+        GopherURL::from(target)
+          .ok()
+          .and_then(|url| gateway_path(&url))
+          .unwrap_or_else(|| target.to_string())
+      };
+      let html = menu.to_html(Some(&url), &link);
+      respond(
+        client,
+        "200 OK",
+        "text/html; charset=utf-8",
+        html.as_bytes(),
+      )
+    }
+    _ if sniff::sniff(&raw) == Content::Html => respond_with_headers(
+      client,
+      "200 OK",
+      "text/html; charset=utf-8",
+      SANDBOX_HEADERS,
+      &raw,
+    ),
+    _ => respond(client, "200 OK", "text/plain; charset=utf-8", &raw),
+  }
+}
+
+// Whether the Host header names the gateway: the address it listens on, or
+// any IP address or localhost with its port. Other names may have been
+// pointed at the gateway by a web page to read its answers.
+fn is_allowed_host(host: &str, address: &str, port: u16) -> bool {
+  if host.eq_ignore_ascii_case(address) {
+    return true;
+  }
+  let (name, host_port) = match host.rsplit_once(':') {
+    Some((name, host_port)) if !name.ends_with(':') => (name, host_port.parse::<u16>().ok()),
+    _ => (host, Some(80)),
+  };
+  let name = name.trim_start_matches('[').trim_end_matches(']');
+  host_port == Some(port)
+    && (name.eq_ignore_ascii_case("localhost") || name.parse::<IpAddr>().is_ok())
+}
+
+fn handle(client: TcpStream, config: &Config, address: &str, port: u16) -> io::Result<()> {
+  let mut reader = BufReader::new(client.try_clone()?);
+  let mut request_line = String::new();
+  reader.read_line(&mut request_line)?;
+  // Only the host is used among headers
+  let mut host = None;
+  let mut header = String::new();
+  while reader.read_line(&mut header)? > 0 && header.trim() != "" {
+    if let Some((name, value)) = header.split_once(':') {
+      if name.trim().eq_ignore_ascii_case("host") {
+        host = Some(value.trim().to_string());
+      }
+    }
+    header.clear();
+  }
+
+  let mut client = client;
+  let mut parts = request_line.split_whitespace();
+  let status = match (parts.next(), parts.next()) {
+    _ if !host.is_some_and(|host| is_allowed_host(&host, address, port)) => respond_error(
+      &mut client,
+      "421 Misdirected Request",
+      "The gateway is only reachable by its own address",
+    )?,
+    (Some("GET"), Some(target)) if target.starts_with('/') => answer(target, config, &mut client)?,
+    (Some(_), Some(_)) => respond_error(
+      &mut client,
+      "405 Method Not Allowed",
+      "Only GET requests are supported",
+    )?,
+    _ => respond_error(&mut client, "400 Bad Request", "Invalid request")?,
+  };
+  println!("{:?} -> {}", request_line.trim(), status);
+  Ok(())
+}
+
+// Serve requests until the program is stopped, each in its own thread. The
+// gateway connects to any host and port given by clients, internal ones
+// included, so it only listens on loopback addresses unless allowed.
+pub fn serve(address: &str, allow_remote: bool, config: Config) -> Result<(), String> {
+  let is_loopback = match address.to_socket_addrs() {
+    Ok(mut addresses) => addresses.all(|address| address.ip().is_loopback()),
+    Err(e) => return Err(format!("Invalid address {}: {}", address, e)),
+  };
+  if !is_loopback && !allow_remote {
+    return Err(format!(
+      "Listening on {} would let anyone reach any host through the gateway, \
+       internal ones included, use --allow-remote to do it anyway",
+      address
+    ));
+  }
+  let listener = match TcpListener::bind(address) {
+    Ok(listener) => listener,
+    Err(e) => return Err(format!("Failed to listen on {}: {}", address, e)),
+  };
+  println!("Serving gopher pages on http://{}/", address);
+
+  let port = listener.local_addr().map_or(0, |local| local.port());
+  let config = Arc::new(config);
+  let address = Arc::new(address.to_string());
+  for client in listener.incoming() {
+    match client {
+      Ok(client) => {
+        let config = config.clone();
+        let address = address.clone();
+        thread::spawn(move || {
+          if let Err(e) = handle(client, &config, &address, port) {
+            eprintln!("Failed to answer request: {}", e);
+          }
+        });
+      }
+      Err(e) => eprintln!("Failed to accept connection: {}", e),
+    }
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests_gateway {
+  use super::*;
+
+  // Gopher server stand-in answering one request with the response
+  fn gopher_server(response: &'static [u8]) -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    thread::spawn(move || {
+      let (mut stream, _) = listener.accept().unwrap();
+      let mut selector = String::new();
+      BufReader::new(stream.try_clone().unwrap())
+        .read_line(&mut selector)
+        .unwrap();
+      stream.write_all(response).unwrap();
+    });
+    port
+  }
+
+  fn get(target: &str) -> String {
+    let mut client = Vec::new();
+    answer(target, &Config::new(), &mut client).unwrap();
+    String::from_utf8_lossy(&client).to_string()
+  }
+
+  #[test]
+  fn should_render_menus_with_links_through_gateway() {
+    let port = gopher_server(b"0Read me\t/readme.txt\t127.0.0.1\t7070\r\n.\r\n");
+    let response = get(&format!("/127.0.0.1:{}/1/", port));
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\n"));
+    assert!(response.contains("<a href=\"/127.0.0.1:7070/0/readme.txt\">Read me</a>"));
+  }
+
+  #[test]
+  fn should_serve_text_and_binary_content() {
+    // Types of text documents are trusted
+    let port = gopher_server(b"1Looks like a menu\t/\t127.0.0.1\t70\r\n");
+    let response = get(&format!("/127.0.0.1:{}/0/notes.txt", port));
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\nContent-Type: text/plain; charset=utf-8\r\n"));
+
+    let port = gopher_server(b"Hello\r\n");
+    let response = get(&format!("/gopher://127.0.0.1:{}/0/hello.txt", port));
+    assert!(response.contains("Content-Type: text/plain; charset=utf-8\r\nContent-Length: 7\r\n"));
+    assert!(response.contains("\r\nX-Content-Type-Options: nosniff\r\n"));
+
+    assert!(response.ends_with("\r\n\r\nHello\r\n"));
+    let port = gopher_server(b"<html><script>alert(1)</script></html>");
+    let response = get(&format!("/127.0.0.1:{}/h/page", port));
+    assert!(response.contains("\r\nContent-Security-Policy: sandbox\r\n"));
+
+    let port = gopher_server(b"\x89PNG\r\n\x1a\n\0\0");
+    let response = get(&format!("/127.0.0.1:{}/I/logo", port));
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\nContent-Type: image/png\r\n"));
+    assert!(response.ends_with("\r\n\r\n\u{fffd}PNG\r\n\x1a\n\0\0"));
+  }
+
+  #[test]
+  fn should_report_errors() {
+    let port = gopher_server(b"3Not found\t\terror.host\t1\r\n");
+    assert!(get(&format!("/127.0.0.1:{}/0/gone", port)).starts_with("HTTP/1.1 404 Not Found"));
+    assert!(get("/sdf.org:99999/").starts_with("HTTP/1.1 400 Bad Request"));
+    assert!(get("/?url=gopher%3A%2F%2Fsdf.org%2F1%2Fmy+files")
+      .starts_with("HTTP/1.1 302 Found\r\nLocation: /sdf.org:70/1/my%20files\r\n"));
+  }
+
+  #[test]
+  fn should_refuse_line_breaks_in_selectors() {
+    assert!(
+      get("/127.0.0.1:25/0x%0D%0AMAIL%20FROM:%3Ca@b.c%3E").starts_with("HTTP/1.1 400 Bad Request")
+    );
+  }
+
+  #[test]
+  fn should_only_answer_requests_for_the_gateway() {
+    let request = |host: &str| {
+      let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+      let port = listener.local_addr().unwrap().port();
+      let mut client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+      write!(
+        client,
+        "GET / HTTP/1.1\r\n{}\r\n",
+        host.replace("PORT", &port.to_string())
+      )
+      .unwrap();
+      let (server, _) = listener.accept().unwrap();
+      handle(server, &Config::new(), "127.0.0.1:8070", port).unwrap();
+      let mut response = String::new();
+      client.read_to_string(&mut response).unwrap();
+      response
+    };
+    assert!(request("Host: 127.0.0.1:PORT\r\n").starts_with("HTTP/1.1 200 OK"));
+    assert!(request("host: localhost:PORT\r\n").starts_with("HTTP/1.1 200 OK"));
+    assert!(
+      request("Host: rebound.example:PORT\r\n").starts_with("HTTP/1.1 421 Misdirected Request")
+    );
+    assert!(request("").starts_with("HTTP/1.1 421 Misdirected Request"));
+
+    assert!(is_allowed_host("[::1]:8070", "[::1]:8070", 8070));
+    assert!(is_allowed_host(
+      "gateway.lan:8070",
+      "gateway.lan:8070",
+      8070
+    ));
+    assert!(is_allowed_host("127.0.0.1", "127.0.0.1:80", 80));
+    assert!(!is_allowed_host("127.0.0.1:8071", "127.0.0.1:8070", 8070));
+    assert!(!is_allowed_host(
+      "localhost.evil.example:8070",
+      "127.0.0.1:8070",
+      8070
+    ));
+  }
+
+  #[test]
+  fn should_only_listen_on_loopback_when_not_allowed() {
+    let msg = serve("0.0.0.0:0", false, Config::new()).unwrap_err();
+    assert!(msg.starts_with("Listening on 0.0.0.0:0 would let anyone reach any host"));
+  }
+}
@@ -0,0 +1,668 @@
+// Copyright © Killian Kemps (2019)
+//
+// Killian Kemps <developer@killiankemps.fr>
+//
+// This software is a computer program whose purpose is to communicate with
+// the Gopher protocol.
+//
+// This software is governed by the CeCILL license under French law and
+// abiding by the rules of distribution of free software.  You can  use,
+// modify and/ or redistribute the software under the terms of the CeCILL
+// license as circulated by CEA, CNRS and INRIA at the following URL
+// "http://www.cecill.info".
+//
+// As a counterpart to the access to the source code and  rights to copy,
+// modify and redistribute granted by the license, users are provided only
+// with a limited warranty  and the software's author,  the holder of the
+// economic rights,  and the successive licensors  have only  limited
+// liability.
+//
+// In this respect, the user's attention is drawn to the risks associated
+// with loading,  using,  modifying and/or developing or reproducing the
+// software by the user in light of its specific status of free software,
+// that may mean  that it is complicated to manipulate,  and  that  also
+// therefore means  that it is reserved for developers  and  experienced
+// professionals having in-depth computer knowledge. Users are therefore
+// encouraged to load and test the software's suitability as regards their
+// requirements in conditions enabling the security of their systems and/or
+// data to be ensured and,  more generally, to use and operate it in the
+// same conditions as regards security.
+//
+// The fact that you are presently reading this means that you have had
+// knowledge of the CeCILL license and that you accept its terms.
+
+// Gemini protocol: requests are full URLs sent over TLS, and responses start
+// with a header giving a status and a meta field, followed by a body on
+// success. Servers use self-signed certificates, which are trusted on first
+// use: their fingerprint is kept in ~/.marmotte/known_hosts.txt and must not
+// change afterwards.
+
+use std::convert::TryFrom;
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Read, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use ring::digest;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{self, CryptoProvider};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{ClientConfig, ClientConnection, DigitallySignedStruct, SignatureScheme, StreamOwned};
+
+use crate::config::{Config, WrapMode};
+use crate::sanitize::sanitize;
+use crate::theme::Theme;
+use crate::{connect, prompt, url, wrap};
+use crate::{ClientState, GopherResponse, GopherTextResponse, GopherURL, Scheme, SOFTWARE_NAME};
+
+pub const DEFAULT_PORT: u16 = 1965;
+const MAX_REDIRECTS: usize = 5;
+// Headers are a status, a space and up to 1024 bytes of meta
+const MAX_HEADER_SIZE: usize = 1029;
+
+// Certificates are checked against known hosts once the handshake is done,
+// only their signature of the handshake is verified here
+#[derive(Debug)]
+struct TofuVerifier(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for TofuVerifier {
+  fn verify_server_cert(
+    &self,
+    _end_entity: &CertificateDer<'_>,
+    _intermediates: &[CertificateDer<'_>],
+    _server_name: &ServerName<'_>,
+    _ocsp_response: &[u8],
+    _now: UnixTime,
+  ) -> Result<ServerCertVerified, rustls::Error> {
+    Ok(ServerCertVerified::assertion())
+  }
+
+  fn verify_tls12_signature(
+    &self,
+    message: &[u8],
+    cert: &CertificateDer<'_>,
+    dss: &DigitallySignedStruct,
+  ) -> Result<HandshakeSignatureValid, rustls::Error> {
+    crypto::verify_tls12_signature(
+      message,
+      cert,
+      dss,
+      &self.0.signature_verification_algorithms,
+    )
+  }
+
+  fn verify_tls13_signature(
+    &self,
+    message: &[u8],
+    cert: &CertificateDer<'_>,
+    dss: &DigitallySignedStruct,
+  ) -> Result<HandshakeSignatureValid, rustls::Error> {
+    crypto::verify_tls13_signature(
+      message,
+      cert,
+      dss,
+      &self.0.signature_verification_algorithms,
+    )
+  }
+
+  fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+    self.0.signature_verification_algorithms.supported_schemes()
+  }
+}
+
+fn tls_config() -> Result<ClientConfig, String> {
+  let provider = Arc::new(crypto::ring::default_provider());
+  let config = ClientConfig::builder_with_provider(provider.clone())
+    .with_safe_default_protocol_versions()
+    .map_err(|e| format!("Failed to set up TLS: {}", e))?
+    .dangerous()
+    .with_custom_certificate_verifier(Arc::new(TofuVerifier(provider)))
+    .with_no_client_auth();
+  Ok(config)
+}
+
+fn fingerprint(certificate: &[u8]) -> String {
+  let hash = digest::digest(&digest::SHA256, certificate);
+  let hex: Vec<String> = hash
+    .as_ref()
+    .iter()
+    .map(|byte| format!("{:02x}", byte))
+    .collect();
+  format!("sha256:{}", hex.join(""))
+}
+
+fn get_known_hosts_path() -> Result<PathBuf, String> {
+  match env::var("HOME") {
+    Ok(home) => Ok(PathBuf::from(format!(
+      "{}/.{}/known_hosts.txt",
+      home, SOFTWARE_NAME
+    ))),
+    Err(e) => Err(format!(
+      "Could not get path to known hosts because $HOME is not set: {:?}",
+      e
+    )),
+  }
+}
+
+#[derive(Debug, PartialEq)]
+enum Trust {
+  New,
+  Known,
+  Changed,
+}
+
+// Known hosts are stored one per line as "<host>:<port> <fingerprint>"
+fn check_known_hosts(known_hosts: &str, server: &str, fingerprint: &str) -> Trust {
+  let known = known_hosts
+    .lines()
+    .filter_map(|line| line.split_once(' '))
+    .find(|(known_server, _)| *known_server == server);
+  match known {
+    None => Trust::New,
+    Some((_, known_fingerprint)) if known_fingerprint.trim() == fingerprint => Trust::Known,
+    Some(_) => Trust::Changed,
+  }
+}
+
+// Trust the certificate of a server if it is the first one seen for this
+// server or the same as the first one
+fn trust(server: &str, fingerprint: &str, config: &Config) -> Result<(), String> {
+  let path = get_known_hosts_path()?;
+  let known_hosts = match fs::read_to_string(&path) {
+    Ok(known_hosts) => known_hosts,
+    Err(error) if error.kind() == ErrorKind::NotFound => String::new(),
+    Err(error) => return Err(format!("Problem reading the known hosts file: {:?}", error)),
+  };
+
+  match check_known_hosts(&known_hosts, server, fingerprint) {
+    Trust::Known => Ok(()),
+    Trust::Changed => Err(format!(
+      "The certificate of {} is not the one trusted before, which may be an attack. \
+       Remove its line from {} to trust the new certificate",
+      server,
+      path.display()
+    )),
+    Trust::New => {
+      if let Some(directory) = path.parent() {
+        let _ = fs::create_dir_all(directory);
+      }
+      let written = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut file| writeln!(file, "{} {}", server, fingerprint));
+      if let Err(e) = written {
+        return Err(format!("Problem writing the known hosts file: {:?}", e));
+      }
+      if config.verbose {
+        eprintln!("Trusting certificate {} of {}", fingerprint, server);
+      }
+      Ok(())
+    }
+  }
+}
+
+// Send the URL over TLS and read the whole response
+fn request(url: &GopherURL, config: &Config) -> Result<Vec<u8>, String> {
+  let stream = match connect::connect(&url.host, url.port, config, connect::CONNECT_TIMEOUT) {
+    Ok(stream) => stream,
+    Err(e) => return Err(format!("Failed to connect: {}", e)),
+  };
+  let server_name = match ServerName::try_from(url.host.clone()) {
+    Ok(server_name) => server_name,
+    Err(_) => return Err(format!("Invalid host \"{}\"", url.host)),
+  };
+  let connection = match ClientConnection::new(Arc::new(tls_config()?), server_name) {
+    Ok(connection) => connection,
+    Err(e) => return Err(format!("Failed to set up TLS: {}", e)),
+  };
+  let mut tls = StreamOwned::new(connection, stream);
+
+  // The certificate is checked before sending anything
+  while tls.conn.is_handshaking() {
+    if let Err(e) = tls.conn.complete_io(&mut tls.sock) {
+      return Err(format!("TLS handshake failed: {}", e));
+    }
+  }
+  let certificate = match tls.conn.peer_certificates().and_then(|chain| chain.first()) {
+    Some(certificate) => fingerprint(certificate),
+    None => return Err("The server sent no certificate".to_string()),
+  };
+  trust(&url.get_server(), &certificate, config)?;
+
+  let request = format!("{}\r\n", url.get_url().unwrap_or_default());
+  if let Err(e) = tls.write_all(request.as_bytes()).and_then(|_| tls.flush()) {
+    return Err(format!("Failed to send request: {}", e));
+  }
+  let mut buffer = Vec::new();
+  match tls.read_to_end(&mut buffer) {
+    Ok(_) => Ok(buffer),
+    // Many servers close the connection without TLS close notification
+    Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(buffer),
+    Err(e) => Err(format!("Failed to receive data: {}", e)),
+  }
+}
+
+// Split a response into its status, meta and body
+fn parse_header(response: &[u8]) -> Result<(u8, String, &[u8]), String> {
+  let end = response
+    .iter()
+    .take(MAX_HEADER_SIZE + 2)
+    .position(|byte| *byte == b'\n');
+  let (header, body) = match end {
+    Some(end) => (&response[..end], &response[end + 1..]),
+    None => return Err("Invalid response: no header".to_string()),
+  };
+  let header = String::from_utf8_lossy(header);
+  let header = header.trim_end_matches('\r');
+  let status = match header.get(..2).map(|status| status.parse::<u8>()) {
+    Some(Ok(status)) if header.as_bytes()[0].is_ascii_digit() && status >= 10 => status,
+    _ => return Err(format!("Invalid response header \"{}\"", header)),
+  };
+  Ok((status, header[2..].trim().to_string(), body))
+}
+
+// Request a page, asking for input and following redirects to other gemini
+// URLs. Returns the final URL, the MIME type and the body of the page.
+pub fn fetch(mut url: GopherURL, config: &Config) -> Result<(GopherURL, String, Vec<u8>), String> {
+  let server_error = |msg: String| {
+    Theme::paint(
+      &config.theme.error,
+      &format!("Server error: {}", sanitize(&msg, config.sanitize)),
+      config.colours.enabled(),
+    )
+  };
+
+  for _ in 0..=MAX_REDIRECTS {
+    let response = request(&url, config)?;
+    let (status, meta, body) = parse_header(&response)?;
+    match status / 10 {
+      1 => {
+        let answer = prompt(&format!("{}: ", sanitize(&meta, config.sanitize)));
+        if answer.is_empty() {
+          return Err("No input given, it may also be given in the URL after '?'".to_string());
+        }
+        url.search = Some(url::encode(&answer).replace('?', "%3F"));
+      }
+      2 => {
+        // An empty meta stands for gemtext
+        let mime_type = match meta.as_str() {
+          "" => "text/gemini".to_string(),
+          _ => meta,
+        };
+        return Ok((url, mime_type, body.to_vec()));
+      }
+      3 => {
+        let target = resolve(&url, &meta);
+        url = GopherURL::from(&target)?;
+        if url.scheme != Scheme::Gemini {
+          return Err(format!(
+            "Redirected to {}, which is not a gemini URL",
+            target
+          ));
+        }
+      }
+      4 | 5 => return Err(server_error(format!("{} ({})", meta, status))),
+      6 => {
+        return Err(server_error(format!(
+          "{} ({}), client certificates are not supported",
+          meta, status
+        )))
+      }
+      _ => return Err(format!("Unknown response status {}", status)),
+    }
+  }
+  Err("Too many redirects".to_string())
+}
+
+// Display the page of a gemini URL and add it to history
+pub fn manage_request(url: GopherURL, state: &mut ClientState) -> Result<(), String> {
+  let (url, mime_type, body) = fetch(url, &state.config)?;
+  state.last_response = parse_response(&body, &mime_type, &url);
+  // Keep original bytes to be able to save the page
+  state.last_raw_response = body;
+  state.last_response.display(&state.config, Some(&url));
+  state.history.insert(0, url);
+  Ok(())
+}
+
+// Gemtext is rendered with its links, other text is displayed as is and
+// other content is replaced by a notice as it can't be displayed
+pub fn parse_response(body: &[u8], mime_type: &str, url: &GopherURL) -> GopherResponse {
+  let essence = mime_type
+    .split(';')
+    .next()
+    .unwrap_or("")
+    .trim()
+    .to_lowercase();
+  let text = String::from_utf8_lossy(body);
+  if essence == "text/gemini" {
+    GopherResponse::Gemini(GeminiResponse::from(&text, url))
+  } else if essence.starts_with("text/") {
+    GopherResponse::Text(GopherTextResponse::from(&text))
+  } else {
+    GopherResponse::Text(GopherTextResponse::from(&format!(
+      "({}, {} bytes, use the save command to keep it)",
+      essence,
+      body.len()
+    )))
+  }
+}
+
+// Remove "." and ".." segments of a path starting with '/'
+fn remove_dot_segments(path: &str) -> String {
+  let segments: Vec<&str> = path.split('/').collect();
+  let mut kept: Vec<&str> = Vec::new();
+  for (index, segment) in segments.iter().enumerate() {
+    let is_last = index == segments.len() - 1;
+    match *segment {
+      "." => {}
+      ".." => {
+        if kept.len() > 1 {
+          kept.pop();
+        }
+      }
+      segment => {
+        kept.push(segment);
+        continue;
+      }
+    }
+    // A path ending with a dot segment is a directory
+    if is_last {
+      kept.push("");
+    }
+  }
+  if kept.len() == 1 {
+    return "/".to_string();
+  }
+  kept.join("/")
+}
+
+// Absolute URL of a link, which may be relative to the page as in RFC 3986
+pub fn resolve(base: &GopherURL, link: &str) -> String {
+  let link = link.split('#').next().unwrap_or("");
+  let scheme_length = link.find(':').unwrap_or(0);
+  if scheme_length > 0
+    && link[..scheme_length]
+      .chars()
+      .all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+  {
+    return link.to_string();
+  }
+  if link.starts_with("//") {
+    return format!("gemini:{}", link);
+  }
+
+  let base_url = base.get_url().unwrap_or_default();
+  let origin_length =
+    base_url.len() - base.selector.len() - base.search.as_ref().map_or(0, |query| query.len() + 1);
+  let origin = &base_url[..origin_length];
+  let (path, query) = match link.find('?') {
+    Some(idx) => link.split_at(idx),
+    None => (link, ""),
+  };
+  let path = if path.is_empty() {
+    if query.is_empty() {
+      return base_url;
+    }
+    base.selector.clone()
+  } else if path.starts_with('/') {
+    path.to_string()
+  } else {
+    let directory = &base.selector[..base.selector.rfind('/').map_or(0, |idx| idx + 1)];
+    format!("{}{}", directory, path)
+  };
+  format!("{}{}{}", origin, remove_dot_segments(&path), query)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GeminiLine {
+  Text(String),
+  // Absolute URL and description of the link
+  Link(String, String),
+  // Level and text of the heading
+  Heading(usize, String),
+  ListItem(String),
+  Quote(String),
+  Preformatted(String),
+}
+
+#[derive(Clone)]
+pub struct GeminiResponse {
+  pub lines: Vec<GeminiLine>,
+  // URLs of the links, in order of appearance
+  pub links: Vec<String>,
+}
+
+impl GeminiResponse {
+  // Parse gemtext, resolving links against the URL of the page
+  pub fn from(text: &str, url: &GopherURL) -> GeminiResponse {
+    let mut lines = Vec::new();
+    let mut links = Vec::new();
+    let mut preformatted = false;
+
+    for line in text.lines() {
+      let line = line.trim_end_matches('\r');
+      // Toggle lines may give alt text, which is not displayed
+      if line.starts_with("```") {
+        preformatted = !preformatted;
+        continue;
+      }
+      if preformatted {
+        lines.push(GeminiLine::Preformatted(line.to_string()));
+      } else if let Some(link) = line.strip_prefix("=>") {
+        let link = link.trim();
+        let (target, description) = match link.split_once(char::is_whitespace) {
+          Some((target, description)) => (target, description.trim()),
+          None => (link, link),
+        };
+        if target.is_empty() {
+          lines.push(GeminiLine::Text(line.to_string()));
+          continue;
+        }
+        let target = resolve(url, target);
+        links.push(target.clone());
+        lines.push(GeminiLine::Link(target, description.to_string()));
+      } else if line.starts_with('#') {
+        let level = line.chars().take_while(|c| *c == '#').count().min(3);
+        lines.push(GeminiLine::Heading(level, line[level..].trim().to_string()));
+      } else if let Some(item) = line.strip_prefix("* ") {
+        lines.push(GeminiLine::ListItem(item.trim().to_string()));
+      } else if let Some(quote) = line.strip_prefix('>') {
+        lines.push(GeminiLine::Quote(quote.trim().to_string()));
+      } else {
+        lines.push(GeminiLine::Text(line.to_string()));
+      }
+    }
+
+    GeminiResponse { lines, links }
+  }
+
+  // Render the page as lines ready to be printed. Links are numbered and
+  // marked with their host when they point to another server, and every
+  // line but preformatted text is wrapped on its own.
+  pub fn render(&self, config: &Config, host: &str) -> Vec<String> {
+    let colours = config.colours.enabled();
+    let theme = &config.theme;
+    let width = match config.wrap {
+      WrapMode::Off => None,
+      WrapMode::Terminal => Some(wrap::terminal_width()),
+      WrapMode::Column(width) => Some(width),
+    };
+
+    let mut rendered = Vec::new();
+    let mut link_count = 0;
+    for line in self.lines.iter() {
+      let rendered_line = match line {
+        GeminiLine::Preformatted(text) => {
+          rendered.push(sanitize(text, config.sanitize));
+          continue;
+        }
+        GeminiLine::Text(text) => sanitize(text, config.sanitize),
+        GeminiLine::Link(target, description) => {
+          link_count += 1;
+          let mut rendered_link = format!(
+            "{} {}",
+            Theme::paint(&theme.index, &format!("[{}]", link_count), colours),
+            Theme::paint(
+              &theme.text,
+              &sanitize(description, config.sanitize),
+              colours
+            )
+          );
+          let external = match GopherURL::from(target) {
+            Ok(url) if url.scheme == Scheme::Gemini && url.host.eq_ignore_ascii_case(host) => None,
+            Ok(url) if url.scheme == Scheme::Gemini => Some(url.host),
+            // Other protocols are shown with their scheme
+            _ => Some(target.split('/').take(3).collect::<Vec<&str>>().join("/")),
+          };
+          if let Some(external) = external {
+            rendered_link.push_str(&Theme::paint(
+              &theme.external,
+              &format!(" \u{2197} {}", sanitize(&external, config.sanitize)),
+              colours,
+            ));
+          }
+          rendered_link
+        }
+        GeminiLine::Heading(level, text) => Theme::paint(
+          &theme.menu,
+          &format!("{} {}", "#".repeat(*level), sanitize(text, config.sanitize)),
+          colours,
+        ),
+        GeminiLine::ListItem(text) => format!("* {}", sanitize(text, config.sanitize)),
+        GeminiLine::Quote(text) => Theme::paint(
+          &theme.info,
+          &format!("> {}", sanitize(text, config.sanitize)),
+          colours,
+        ),
+      };
+      match width {
+        Some(width) => rendered.extend(wrap::reflow(&[rendered_line], width)),
+        None => rendered.push(rendered_line),
+      }
+    }
+    rendered
+  }
+}
+
+#[cfg(test)]
+mod tests_gemini {
+  use super::*;
+
+  fn page_url() -> GopherURL {
+    GopherURL::from("gemini://example.org/gemlog/post.gmi?draft").unwrap()
+  }
+
+  #[test]
+  fn should_resolve_links() {
+    let url = page_url();
+    let cases = [
+      ("other.gmi", "gemini://example.org/gemlog/other.gmi"),
+      ("../", "gemini://example.org/"),
+      ("./a/../b.gmi#part", "gemini://example.org/gemlog/b.gmi"),
+      ("/about/.", "gemini://example.org/about/"),
+      ("/../../x", "gemini://example.org/x"),
+      ("?search", "gemini://example.org/gemlog/post.gmi?search"),
+      ("", "gemini://example.org/gemlog/post.gmi?draft"),
+      ("//other.org:1966/", "gemini://other.org:1966/"),
+      ("gopher://sdf.org/1/", "gopher://sdf.org/1/"),
+      ("mailto:me@example.org", "mailto:me@example.org"),
+    ];
+    for (link, expected) in cases.iter() {
+      assert_eq!(expected.to_string(), resolve(&url, link), "{}", link);
+    }
+  }
+
+  #[test]
+  fn should_parse_headers() {
+    assert_eq!(
+      Ok((20, "text/gemini; lang=fr".to_string(), &b"# Hi\n"[..])),
+      parse_header(b"20 text/gemini; lang=fr\r\n# Hi\n")
+    );
+    assert_eq!(
+      Ok((10, "Search".to_string(), &b""[..])),
+      parse_header(b"10 Search\r\n")
+    );
+    assert_eq!(
+      Err("Invalid response header \"HTTP/1.1 400\"".to_string()),
+      parse_header(b"HTTP/1.1 400\r\n")
+    );
+    assert!(parse_header(b"20 text/gemini").is_err());
+  }
+
+  #[test]
+  fn should_trust_certificates_on_first_use() {
+    let known_hosts = "example.org:1965 sha256:aa\n[::1]:1966 sha256:bb\n";
+    assert_eq!(
+      Trust::Known,
+      check_known_hosts(known_hosts, "example.org:1965", "sha256:aa")
+    );
+    assert_eq!(
+      Trust::Changed,
+      check_known_hosts(known_hosts, "example.org:1965", "sha256:cc")
+    );
+    assert_eq!(
+      Trust::New,
+      check_known_hosts(known_hosts, "example.org:1966", "sha256:aa")
+    );
+    assert_eq!(
+      Trust::Known,
+      check_known_hosts(known_hosts, "[::1]:1966", "sha256:bb")
+    );
+  }
+
+  #[test]
+  fn should_parse_gemtext() {
+    let page = GeminiResponse::from(
+      "# Title\r\n\
+       Some text\n\
+       =>other.gmi\n\
+       => gemini://other.org/ Other capsule\n\
+       =>\n\
+       * item\n\
+       > quote\n\
+       ```ascii art\n\
+       => not a link\n\
+       ```\n",
+      &page_url(),
+    );
+    assert_eq!(
+      vec![
+        GeminiLine::Heading(1, "Title".to_string()),
+        GeminiLine::Text("Some text".to_string()),
+        GeminiLine::Link(
+          "gemini://example.org/gemlog/other.gmi".to_string(),
+          "other.gmi".to_string()
+        ),
+        GeminiLine::Link(
+          "gemini://other.org/".to_string(),
+          "Other capsule".to_string()
+        ),
+        GeminiLine::Text("=>".to_string()),
+        GeminiLine::ListItem("item".to_string()),
+        GeminiLine::Quote("quote".to_string()),
+        GeminiLine::Preformatted("=> not a link".to_string()),
+      ],
+      page.lines
+    );
+    assert_eq!(2, page.links.len());
+
+    let mut config = Config::new();
+    config.colours = crate::theme::ColourMode::Never;
+    assert_eq!(
+      vec![
+        "# Title",
+        "Some text",
+        "[1] other.gmi",
+        "[2] Other capsule \u{2197} other.org",
+        "=>",
+        "* item",
+        "> quote",
+        "=> not a link",
+      ],
+      page.render(&config, "example.org")
+    );
+  }
+}
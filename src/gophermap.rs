@@ -0,0 +1,301 @@
+// Copyright © Killian Kemps (2019)
+//
+// Killian Kemps <developer@killiankemps.fr>
+//
+// This software is a computer program whose purpose is to communicate with
+// the Gopher protocol.
+//
+// This software is governed by the CeCILL license under French law and
+// abiding by the rules of distribution of free software.  You can  use,
+// modify and/ or redistribute the software under the terms of the CeCILL
+// license as circulated by CEA, CNRS and INRIA at the following URL
+// "http://www.cecill.info".
+//
+// As a counterpart to the access to the source code and  rights to copy,
+// modify and redistribute granted by the license, users are provided only
+// with a limited warranty  and the software's author,  the holder of the
+// economic rights,  and the successive licensors  have only  limited
+// liability.
+//
+// In this respect, the user's attention is drawn to the risks associated
+// with loading,  using,  modifying and/or developing or reproducing the
+// software by the user in light of its specific status of free software,
+// that may mean  that it is complicated to manipulate,  and  that  also
+// therefore means  that it is reserved for developers  and  experienced
+// professionals having in-depth computer knowledge. Users are therefore
+// encouraged to load and test the software's suitability as regards their
+// requirements in conditions enabling the security of their systems and/or
+// data to be ensured and,  more generally, to use and operate it in the
+// same conditions as regards security.
+//
+// The fact that you are presently reading this means that you have had
+// knowledge of the CeCILL license and that you accept its terms.
+
+// Authoring of gophermaps, the menus of directories read by gopher servers
+// like the serve command. In gophermaps, lines without tab are information
+// text and items may leave out their host and port, which are the ones of
+// the server.
+
+use std::fs;
+
+use crate::{wire_field, GopherMenuLine, GopherURL};
+
+const USAGE: &str =
+  "Usage: marmotte gophermap lint [file]...\n       marmotte gophermap fmt [file]";
+
+// Whether a line without tab looks like an item written with spaces: a
+// selector starting with '/' followed by a host and a port. Prose may have
+// either one.
+fn has_spaces_instead_of_tabs(line: &str) -> bool {
+  let item_type = match line.get(0..1) {
+    Some(item_type) if !["i", "3"].contains(&item_type) => item_type,
+    _ => return false,
+  };
+  if !is_known_type(item_type) {
+    return false;
+  }
+  let words: Vec<&str> = line[1..].split_whitespace().collect();
+  words.len() >= 3
+    && words[..words.len() - 2]
+      .iter()
+      .any(|word| word.starts_with('/'))
+    && words.last().map(|word| word.parse::<u16>().is_ok()) == Some(true)
+}
+
+fn is_known_type(item_type: &str) -> bool {
+  let item = GopherMenuLine::from(&format!("{}\t\t\t", item_type));
+  item.map(|item| item.get_type_label() != "UNKNOWN") == Ok(true)
+}
+
+// Problems of a line, which ends without CR LF
+fn lint_line(line: &str) -> Option<String> {
+  if !line.contains('\t') {
+    if has_spaces_instead_of_tabs(line) {
+      return Some("Spaces instead of tabs".to_string());
+    }
+    return None;
+  }
+
+  let mut fields: Vec<&str> = line.split('\t').collect();
+  if fields.len() > 5 {
+    return Some(format!("{} fields instead of 4", fields.len()));
+  }
+  if fields[1..].iter().any(|field| field.trim() != *field) {
+    return Some("Spaces around a field".to_string());
+  }
+  fields.resize(fields.len().max(4), "");
+  let item = match GopherMenuLine::from(&fields.join("\t")) {
+    Ok(item) => item,
+    Err(msg) => return Some(msg),
+  };
+  if item.get_type_label() == "UNKNOWN" {
+    return Some(format!("Unknown item type \"{}\"", item.r#type));
+  }
+  if item.host.contains(char::is_whitespace) {
+    return Some(format!("Invalid host \"{}\"", item.host));
+  }
+  match item.port.parse::<u16>() {
+    _ if item.port.is_empty() => None,
+    Ok(port) if port > 0 => None,
+    _ => Some(format!("Invalid port \"{}\"", item.port)),
+  }
+}
+
+// Problems of a gophermap, with their line number
+pub fn lint(gophermap: &str) -> Vec<(usize, String)> {
+  let mut problems = Vec::new();
+  let mut lines = gophermap.lines().enumerate();
+  for (index, line) in lines.by_ref() {
+    let line = line.trim_end_matches('\r');
+    if line == "." {
+      break;
+    }
+    if let Some(problem) = lint_line(line) {
+      problems.push((index + 1, problem));
+    }
+  }
+  if let Some((index, _)) = lines.find(|(_, line)| line.trim() != "") {
+    problems.push((
+      index + 1,
+      "Lines after the \".\" line are ignored".to_string(),
+    ));
+  }
+  problems
+}
+
+// Item of a "=> url description" line: gopher URLs, paths of the server or
+// URLs of other protocols, given with the "URL:" selector convention
+fn link_item(link: &str) -> Result<GopherMenuLine, String> {
+  let (url, description) = match link.split_once(char::is_whitespace) {
+    Some((url, description)) => (url, description.trim()),
+    None => (link, link),
+  };
+  let mut item = GopherMenuLine {
+    r#type: "1".to_string(),
+    description: wire_field(description),
+    selector: url.to_string(),
+    host: String::new(),
+    port: String::new(),
+    gopher_plus: None,
+  };
+
+  if url.starts_with('/') {
+    if !url.ends_with('/') {
+      item.r#type = "0".to_string();
+    }
+  } else if url.starts_with("gopher://") {
+    let url = GopherURL::from(url)?;
+    item.r#type = url.r#type;
+    item.selector = url.selector;
+    item.host = url.host;
+    item.port = url.port.to_string();
+  } else if url.contains("://") {
+    item.r#type = "h".to_string();
+    item.selector = format!("URL:{}", url);
+  } else {
+    return Err(format!("Not a URL or a path: \"{}\"", url));
+  }
+  Ok(item)
+}
+
+// Convert loose text, made of information lines and "=> url description"
+// links, to a gophermap. Links which can't be converted are reported with
+// their line number and left out.
+pub fn format(text: &str) -> (String, Vec<(usize, String)>) {
+  let mut gophermap = String::new();
+  let mut problems = Vec::new();
+  for (index, line) in text.lines().enumerate() {
+    let line = line.trim_end_matches('\r');
+    if let Some(link) = line.strip_prefix("=>") {
+      match link_item(link.trim()) {
+        Ok(item) => gophermap.push_str(&format!("{}\n", item)),
+        Err(msg) => problems.push((index + 1, msg)),
+      }
+    } else if line == "." {
+      // Would end the gophermap
+      gophermap.push_str("i.\t\t\t\n");
+    } else {
+      gophermap.push_str(&format!("{}\n", wire_field(line)));
+    }
+  }
+  (gophermap, problems)
+}
+
+// Run "gophermap lint|fmt" with its arguments, returning the exit status
+pub fn run(args: &[String]) -> i32 {
+  let (action, files) = match args.split_first() {
+    Some((action, files))
+      if (action == "lint" && !files.is_empty()) || (action == "fmt" && files.len() == 1) =>
+    {
+      (action, files)
+    }
+    _ => {
+      eprintln!("{}", USAGE);
+      return 2;
+    }
+  };
+
+  let mut status = 0;
+  for file in files {
+    let contents = match fs::read_to_string(file) {
+      Ok(contents) => contents,
+      Err(e) => {
+        eprintln!("Failed to open {}: {}", file, e);
+        return 2;
+      }
+    };
+    let problems = if action == "lint" {
+      lint(&contents)
+    } else {
+      let (gophermap, problems) = format(&contents);
+      print!("{}", gophermap);
+      problems
+    };
+    for (line, problem) in problems.iter() {
+      eprintln!("{}:{}: {}", file, line, problem);
+    }
+    if !problems.is_empty() {
+      status = 1;
+    }
+  }
+  status
+}
+
+#[cfg(test)]
+mod tests_gophermap {
+  use super::*;
+
+  #[test]
+  fn should_report_problems() {
+    let gophermap = "Welcome to my hole\n\
+                     0About\tabout.txt\n\
+                     1Floodgap\t/\tgopher.floodgap.com\t70\t+\n\
+                     0Notes  /notes.txt  localhost  70\n\
+                     0Notes\t/notes.txt\tlocalhost\t70\t+\textra\n\
+                     1Sub\t/sub \tlocalhost\t70\n\
+                     1Sub\t/sub\tlocalhost\tseventy\n\
+                     1Sub\t/sub\tlocal host\t70\n\
+                     QQuestion\t/q\n\
+                     \t/nothing\n\
+                     .\n\
+                     \n\
+                     forgotten\n";
+    assert_eq!(
+      vec![
+        (4, "Spaces instead of tabs".to_string()),
+        (5, "6 fields instead of 4".to_string()),
+        (6, "Spaces around a field".to_string()),
+        (7, "Invalid port \"seventy\"".to_string()),
+        (8, "Invalid host \"local host\"".to_string()),
+        (9, "Unknown item type \"Q\"".to_string()),
+        (
+          10,
+          "Could not parse item type in: \"\t/nothing\t\t\"".to_string()
+        ),
+        (13, "Lines after the \".\" line are ignored".to_string()),
+      ],
+      lint(gophermap)
+    );
+  }
+  #[test]
+  fn should_not_take_prose_for_items() {
+    assert_eq!(None, lint_line("0 errors in 2020"));
+    assert_eq!(None, lint_line("1 apple costs 70"));
+    assert_eq!(None, lint_line("1 path to remember: /usr/bin"));
+    assert_eq!(
+      Some("Spaces instead of tabs".to_string()),
+      lint_line("1Phlog /phlog sdf.org 70")
+    );
+  }
+
+  #[test]
+  fn should_format_loose_text() {
+    let (gophermap, problems) = format(
+      "My hole\n\
+       \n\
+       => /about.txt About me\n\
+       => /phlog/ Phlog\n\
+       => gopher://sdf.org/1/users/ SDF users\n\
+       => https://example.org\n\
+       => sdf.org Missing scheme\n\
+       .\n\
+       Tab\there\n",
+    );
+    assert_eq!(
+      "My hole\n\
+       \n\
+       0About me\t/about.txt\t\t\n\
+       1Phlog\t/phlog/\t\t\n\
+       1SDF users\t/users/\tsdf.org\t70\n\
+       hhttps://example.org\tURL:https://example.org\t\t\n\
+       i.\t\t\t\n\
+       Tab here\n",
+      gophermap
+    );
+    assert_eq!(
+      vec![(7, "Not a URL or a path: \"sdf.org\"".to_string())],
+      problems
+    );
+    assert!(lint(&gophermap).is_empty());
+  }
+}
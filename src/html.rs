@@ -0,0 +1,450 @@
+// Copyright © Killian Kemps (2019)
+//
+// Killian Kemps <developer@killiankemps.fr>
+//
+// This software is a computer program whose purpose is to communicate with
+// the Gopher protocol.
+//
+// This software is governed by the CeCILL license under French law and
+// abiding by the rules of distribution of free software.  You can  use,
+// modify and/ or redistribute the software under the terms of the CeCILL
+// license as circulated by CEA, CNRS and INRIA at the following URL
+// "http://www.cecill.info".
+//
+// As a counterpart to the access to the source code and  rights to copy,
+// modify and redistribute granted by the license, users are provided only
+// with a limited warranty  and the software's author,  the holder of the
+// economic rights,  and the successive licensors  have only  limited
+// liability.
+//
+// In this respect, the user's attention is drawn to the risks associated
+// with loading,  using,  modifying and/or developing or reproducing the
+// software by the user in light of its specific status of free software,
+// that may mean  that it is complicated to manipulate,  and  that  also
+// therefore means  that it is reserved for developers  and  experienced
+// professionals having in-depth computer knowledge. Users are therefore
+// encouraged to load and test the software's suitability as regards their
+// requirements in conditions enabling the security of their systems and/or
+// data to be ensured and,  more generally, to use and operate it in the
+// same conditions as regards security.
+//
+// The fact that you are presently reading this means that you have had
+// knowledge of the CeCILL license and that you accept its terms.
+
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::path::Path;
+
+use crate::config::{Config, SanitizeMode};
+use crate::gemini::GeminiLine;
+use crate::sanitize::sanitize;
+use crate::save::{confirm_overwrite, write_file};
+use crate::{fetch, find_urls, parse_response, ClientState, GopherResponse, GopherURL};
+
+// Pages following links of the current page on the same server, when
+// exporting to a directory without giving a depth
+const DEFAULT_EXPORT_DEPTH: usize = 1;
+// Schemes of links given by servers which may become anchors, others like
+// javascript: would run in the exported page
+const SAFE_SCHEMES: [&str; 5] = ["http", "https", "gopher", "gemini", "mailto"];
+
+fn escape(text: &str) -> String {
+  let mut escaped = String::with_capacity(text.len());
+  for c in text.chars() {
+    match c {
+      '&' => escaped.push_str("&amp;"),
+      '<' => escaped.push_str("&lt;"),
+      '>' => escaped.push_str("&gt;"),
+      '"' => escaped.push_str("&quot;"),
+      '\'' => escaped.push_str("&#39;"),
+      c => escaped.push(c),
+    }
+  }
+  escaped
+}
+
+fn is_safe_link(url: &str) -> bool {
+  match url.split_once(':') {
+    Some((scheme, _)) => SAFE_SCHEMES.contains(&&scheme.to_lowercase()[..]),
+    None => false,
+  }
+}
+
+// Escape a line of text, turning the URLs it contains into anchors
+fn linkify(line: &str, link: &dyn Fn(&str) -> String) -> String {
+  let mut html = String::new();
+  let mut position = 0;
+  for url in find_urls(line) {
+    // URLs are returned in order and are slices of the line
+    let start = url.as_ptr() as usize - line.as_ptr() as usize;
+    html.push_str(&escape(&line[position..start]));
+    html.push_str(&format!(
+      "<a href=\"{}\">{}</a>",
+      escape(&link(url)),
+      escape(url)
+    ));
+    position = start + url.len();
+  }
+  html.push_str(&escape(&line[position..]));
+  html
+}
+
+impl GopherResponse {
+  // Turn the page into a standalone HTML document. Gopher URLs are given to
+  // the link function, which returns the address to use in anchors: the URL
+  // itself, or the path of another exported file.
+  pub fn to_html(&self, url: Option<&GopherURL>, link: &dyn Fn(&str) -> String) -> String {
+    let title = url.and_then(|url| url.get_url()).unwrap_or_default();
+    let mut body = Vec::new();
+
+    match self {
+      GopherResponse::Text(response) => {
+        let mut lines: Vec<String> = response
+          .lines
+          .iter()
+          .map(|line| {
+            linkify(
+              &sanitize(line.trim_end_matches('\r'), SanitizeMode::Strict),
+              link,
+            )
+          })
+          .collect();
+        // Documents end with a line break, not with an empty line
+        if lines.last().map(|line| line.is_empty()) == Some(true) {
+          lines.pop();
+        }
+        body.push(format!("<pre>{}</pre>", lines.join("\n")));
+      }
+      GopherResponse::Gemini(response) => {
+        // Consecutive preformatted lines and list items are grouped
+        let mut group = "";
+        for line in response.lines.iter() {
+          let line_group = match line {
+            GeminiLine::Preformatted(_) => "pre",
+            GeminiLine::ListItem(_) => "ul",
+            _ => "",
+          };
+          if line_group != group {
+            if !group.is_empty() {
+              body.push(format!("</{}>", group));
+            }
+            if !line_group.is_empty() {
+              body.push(format!("<{}>", line_group));
+            }
+            group = line_group;
+          }
+          let text = |text: &str| escape(&sanitize(text, SanitizeMode::Strict));
+          body.push(match line {
+            GeminiLine::Text(line) if line.trim() == "" => continue,
+            GeminiLine::Text(line) => format!("<p>{}</p>", text(line)),
+            GeminiLine::Link(target, description) if !is_safe_link(target) => {
+              format!("<p>{}</p>", text(description))
+            }
+            GeminiLine::Link(target, description) => format!(
+              "<p><a href=\"{}\">{}</a></p>",
+              escape(&link(target)),
+              text(description)
+            ),
+            GeminiLine::Heading(level, line) => format!("<h{}>{}</h{}>", level, text(line), level),
+            GeminiLine::ListItem(line) => format!("<li>{}</li>", text(line)),
+            GeminiLine::Quote(line) => format!("<blockquote>{}</blockquote>", text(line)),
+            GeminiLine::Preformatted(line) => text(line),
+          });
+        }
+        if !group.is_empty() {
+          body.push(format!("</{}>", group));
+        }
+      }
+      GopherResponse::Menu(response) => {
+        // Consecutive information lines are kept together as preformatted
+        // text, as they often hold ASCII art
+        let mut info = Vec::new();
+        for item in response.lines.iter().flatten() {
+          let description = sanitize(&item.description, SanitizeMode::Strict);
+          if item.r#type == "i" {
+            info.push(escape(&description));
+            continue;
+          }
+          if !info.is_empty() {
+            body.push(format!("<pre>{}</pre>", info.join("\n")));
+            info.clear();
+          }
+
+          let href = match &item.r#type[..] {
+            "3" => None,
+            "8" | "T" => Some(format!("telnet://{}:{}", item.host, item.port)),
+            // Links to the web are given as selectors by convention
+            "h" if item.selector.starts_with("URL:") => Some(item.selector[4..].to_string()),
+            _ => Some(link(&item.get_url())).filter(|href| !href.is_empty()),
+          };
+          body.push(match href {
+            Some(href) if item.r#type == "h" && !is_safe_link(&href) => {
+              format!("<p>{} {}</p>", item.get_type_label(), escape(&description))
+            }
+            Some(href) => format!(
+              "<p>{} <a href=\"{}\">{}</a></p>",
+              item.get_type_label(),
+              escape(&href),
+              escape(&description)
+            ),
+            None => format!("<p class=\"error\">{}</p>", escape(&description)),
+          });
+        }
+        if !info.is_empty() {
+          body.push(format!("<pre>{}</pre>", info.join("\n")));
+        }
+      }
+    }
+
+    format!(
+      "<!DOCTYPE html>\n\
+       <html>\n\
+       <head>\n\
+       <meta charset=\"utf-8\">\n\
+       <title>{}</title>\n\
+       </head>\n\
+       <body>\n\
+       {}\n\
+       </body>\n\
+       </html>\n",
+      escape(&title),
+      body.join("\n")
+    )
+  }
+}
+
+// Name of the exported file of a page, the same for every URL of the page.
+// Slashes become '_' and other characters are escaped as '+' and their
+// bytes in hexadecimal, so that different pages never share a file.
+fn export_filename(url: &GopherURL) -> String {
+  let mut name = String::new();
+  for byte in format!("{}{}", url.r#type, url.selector.trim_end_matches('/')).bytes() {
+    match byte {
+      b'/' => name.push('_'),
+      b'-' | b'.' => name.push(byte as char),
+      _ if byte.is_ascii_alphanumeric() => name.push(byte as char),
+      _ => name.push_str(&format!("+{:02X}", byte)),
+    }
+  }
+  format!("{}.html", name)
+}
+
+impl ClientState {
+  // Export the current page to an HTML file, or to a directory when the path
+  // ends with '/'. In a directory, pages of the same server linked from the
+  // current page are exported too, up to the given depth, and links between
+  // exported pages are relative.
+  pub fn export_html(&self, args: &str) -> Result<String, String> {
+    let url = match self.history.first() {
+      Some(url) => url,
+      None => return Err("There is no current document".to_string()),
+    };
+    let mut args = args.split_whitespace();
+    let path = match args.next() {
+      Some(path) => path,
+      None => return Err("No file given to export to".to_string()),
+    };
+
+    if !path.ends_with('/') {
+      let html = self
+        .last_response
+        .to_html(Some(url), &|url| url.to_string());
+      return write_file(path, html.as_bytes());
+    }
+
+    let depth = match args.next() {
+      Some(depth) => match depth.parse::<usize>() {
+        Ok(depth) => depth,
+        Err(error) => return Err(format!("Could not parse the depth: {:?}", error)),
+      },
+      None => DEFAULT_EXPORT_DEPTH,
+    };
+    let pages = crawl(url, &self.last_response, depth, &self.config);
+    export_directory(Path::new(path), &pages)
+  }
+}
+
+// Request pages of the same server reachable from the start page, in the
+// order they are found. Pages which could not be requested are left out.
+fn crawl(
+  start: &GopherURL,
+  response: &GopherResponse,
+  depth: usize,
+  config: &Config,
+) -> Vec<(GopherURL, GopherResponse)> {
+  let mut pages = vec![(start.clone(), response.clone(), 0)];
+  let mut seen: Vec<String> = start.get_url().into_iter().collect();
+  let mut queue = VecDeque::new();
+  queue.push_back(0);
+
+  while let Some(index) = queue.pop_front() {
+    let (_, response, page_depth) = &pages[index];
+    if *page_depth >= depth {
+      continue;
+    }
+    let page_depth = *page_depth;
+    for link in response.get_links() {
+      let (url, full_url) = match GopherURL::from(&link).map(|url| (url.get_url(), url)) {
+        Ok((Some(full_url), url)) => (url, full_url),
+        _ => continue,
+      };
+      if !url.host.eq_ignore_ascii_case(&start.host)
+        || url.port != start.port
+        || !["0", "1"].contains(&&url.r#type[..])
+        || seen.contains(&full_url)
+      {
+        continue;
+      }
+      seen.push(full_url);
+      match fetch(&url, config) {
+        Ok(raw_response) => {
+          let response = parse_response(&raw_response, &url.r#type, config.menus);
+          pages.push((url, response, page_depth + 1));
+          queue.push_back(pages.len() - 1);
+        }
+        Err(msg) => println!("{}: {}", link, msg),
+      }
+    }
+  }
+
+  pages
+    .into_iter()
+    .map(|(url, response, _)| (url, response))
+    .collect()
+}
+
+fn export_directory(
+  directory: &Path,
+  pages: &[(GopherURL, GopherResponse)],
+) -> Result<String, String> {
+  if let Err(e) = fs::create_dir_all(directory) {
+    return Err(format!("Problem creating {}: {}", directory.display(), e));
+  }
+
+  // The start page is the index of the directory
+  let mut filenames = HashMap::new();
+  for (index, (url, _)) in pages.iter().enumerate() {
+    if let Some(full_url) = url.get_url() {
+      let filename = if index == 0 {
+        "index.html".to_string()
+      } else {
+        export_filename(url)
+      };
+      filenames.insert(full_url, filename);
+    }
+  }
+  // Existing files are only replaced once confirmed, before writing any
+  for filename in filenames.values() {
+    let path = directory.join(filename);
+    if path.exists() && !confirm_overwrite(&path.to_string_lossy()) {
+      return Err("Nothing exported".to_string());
+    }
+  }
+
+  let link = |target: &str| match GopherURL::from(target).map(|url| url.get_url()) {
+    Ok(Some(full_url)) => filenames
+      .get(&full_url)
+      .cloned()
+      .unwrap_or_else(|| target.to_string()),
+    _ => target.to_string(),
+  };
+
+  for (url, response) in pages {
+    let filename = match url.get_url().and_then(|full_url| filenames.get(&full_url)) {
+      Some(filename) => filename,
+      None => continue,
+    };
+    let path = directory.join(filename);
+    let html = response.to_html(Some(url), &link);
+    if let Err(e) = fs::write(&path, html) {
+      return Err(format!("Problem writing {}: {}", path.display(), e));
+    }
+  }
+
+  Ok(format!(
+    "Exported {} pages to {}",
+    pages.len(),
+    directory.display()
+  ))
+}
+
+#[cfg(test)]
+mod tests_html {
+  use super::*;
+  use crate::gemini::GeminiResponse;
+  use crate::{GopherMenuResponse, GopherTextResponse};
+
+  #[test]
+  fn should_escape_html() {
+    assert_eq!(
+      "&lt;b&gt; &amp; &quot;q&quot; &#39;s&#39;",
+      escape("<b> & \"q\" 's'")
+    );
+  }
+
+  #[test]
+  fn should_export_menus() {
+    let response = GopherResponse::Menu(GopherMenuResponse::from(
+      "i  /\\_/\\\tfake\terror.host\t1\r\ni <o.o>\tfake\terror.host\t1\r\n\
+       1Sub & co\t/sub\tlocalhost\t70\r\n\
+       hWeb\tURL:https://example.org\tlocalhost\t70\r\n\
+       hClick\tURL:javascript:alert(document.cookie)\tlocalhost\t70\r\n\
+       3Gone\t\terror.host\t1\r\n",
+    ));
+    let html = response.to_html(None, &|url| url.to_string());
+    assert!(html.contains("<pre>  /\\_/\\\n &lt;o.o&gt;</pre>\n"));
+    assert!(html.contains("<p>MENU <a href=\"gopher://localhost:70/1/sub\">Sub &amp; co</a></p>\n"));
+    assert!(html.contains("<p>HTML <a href=\"https://example.org\">Web</a></p>\n"));
+    assert!(html.contains("<p>HTML Click</p>\n"));
+    assert!(!html.contains("javascript"));
+    assert!(html.contains("<p class=\"error\">Gone</p>\n"));
+  }
+
+  #[test]
+  fn should_export_gemini_pages() {
+    let url = GopherURL::from("gemini://localhost/docs/").unwrap();
+    let response = GopherResponse::Gemini(GeminiResponse::from(
+      "# Docs\n=> intro.gmi Intro\n=> javascript:alert(document.cookie) Click\n",
+      &url,
+    ));
+    let html = response.to_html(Some(&url), &|url| url.to_string());
+    assert!(html.contains("<h1>Docs</h1>\n"));
+    assert!(html.contains("<p><a href=\"gemini://localhost/docs/intro.gmi\">Intro</a></p>\n"));
+    assert!(html.contains("<p>Click</p>\n"));
+    assert!(!html.contains("javascript"));
+  }
+
+  #[test]
+  fn should_linkify_text() {
+    let response = GopherResponse::Text(GopherTextResponse::from(
+      "See <gopher://localhost/1/sub>.\r\n\x1b[31mred\r\n",
+    ));
+    let html = response.to_html(None, &|_| "sub.html".to_string());
+    assert!(html
+      .contains("<pre>See &lt;<a href=\"sub.html\">gopher://localhost/1/sub</a>&gt;.\nred</pre>"));
+  }
+
+  #[test]
+  fn should_name_exported_files() {
+    assert_eq!(
+      "1_phlog.html",
+      export_filename(&GopherURL::from("gopher://localhost/1/phlog/").unwrap())
+    );
+    assert_eq!(
+      "0_phlog_2020-01-01.txt.html",
+      export_filename(&GopherURL::from("gopher://localhost/0/phlog/2020-01-01.txt").unwrap())
+    );
+    assert_eq!(
+      "1_a+5Fb.html",
+      export_filename(&GopherURL::from("gopher://localhost/1/a_b").unwrap())
+    );
+    assert_eq!(
+      "1_a_b.html",
+      export_filename(&GopherURL::from("gopher://localhost/1/a/b").unwrap())
+    );
+    assert_eq!(
+      "0_+7Esam_caf+C3+A9.html",
+      export_filename(&GopherURL::from("gopher://localhost/0/~sam/caf%C3%A9").unwrap())
+    );
+  }
+}
@@ -0,0 +1,208 @@
+// Copyright © Killian Kemps (2019)
+//
+// Killian Kemps <developer@killiankemps.fr>
+//
+// This software is a computer program whose purpose is to communicate with
+// the Gopher protocol.
+//
+// This software is governed by the CeCILL license under French law and
+// abiding by the rules of distribution of free software.  You can  use,
+// modify and/ or redistribute the software under the terms of the CeCILL
+// license as circulated by CEA, CNRS and INRIA at the following URL
+// "http://www.cecill.info".
+//
+// As a counterpart to the access to the source code and  rights to copy,
+// modify and redistribute granted by the license, users are provided only
+// with a limited warranty  and the software's author,  the holder of the
+// economic rights,  and the successive licensors  have only  limited
+// liability.
+//
+// In this respect, the user's attention is drawn to the risks associated
+// with loading,  using,  modifying and/or developing or reproducing the
+// software by the user in light of its specific status of free software,
+// that may mean  that it is complicated to manipulate,  and  that  also
+// therefore means  that it is reserved for developers  and  experienced
+// professionals having in-depth computer knowledge. Users are therefore
+// encouraged to load and test the software's suitability as regards their
+// requirements in conditions enabling the security of their systems and/or
+// data to be ensured and,  more generally, to use and operate it in the
+// same conditions as regards security.
+//
+// The fact that you are presently reading this means that you have had
+// knowledge of the CeCILL license and that you accept its terms.
+
+use crate::gemini::GeminiLine;
+use crate::{GopherResponse, GopherURL};
+
+// Quote and escape a string for JSON. Control characters, DEL and C1
+// controls included, are kept, escaped, so that tools get the page exactly
+// as sent by the server while terminals don't interpret them.
+pub fn string(value: &str) -> String {
+  let mut quoted = String::with_capacity(value.len() + 2);
+  quoted.push('"');
+  for c in value.chars() {
+    match c {
+      '"' => quoted.push_str("\\\""),
+      '\\' => quoted.push_str("\\\\"),
+      '\n' => quoted.push_str("\\n"),
+      '\r' => quoted.push_str("\\r"),
+      '\t' => quoted.push_str("\\t"),
+      c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+      c => quoted.push(c),
+    }
+  }
+  quoted.push('"');
+  quoted
+}
+
+fn optional_string(value: Option<String>) -> String {
+  match value {
+    Some(value) => string(&value),
+    None => "null".to_string(),
+  }
+}
+
+impl GopherResponse {
+  // Describe the page on one line of JSON. Menus give every item with its
+  // link index when it has one, and the lines which could not be parsed.
+  // Text documents give their lines, and gemtext pages their typed lines.
+  pub fn to_json(&self, url: Option<&GopherURL>) -> String {
+    let url = optional_string(url.and_then(|url| url.get_url()));
+    match self {
+      GopherResponse::Text(response) => {
+        let lines: Vec<String> = response
+          .lines
+          .iter()
+          .map(|line| string(line.trim_end_matches('\r')))
+          .collect();
+        format!(
+          "{{\"url\":{},\"type\":\"text\",\"lines\":[{}]}}",
+          url,
+          lines.join(",")
+        )
+      }
+      GopherResponse::Gemini(response) => {
+        let mut link_count = 0;
+        let lines: Vec<String> = response
+          .lines
+          .iter()
+          .map(|line| match line {
+            GeminiLine::Link(target, description) => {
+              link_count += 1;
+              format!(
+                "{{\"type\":\"link\",\"text\":{},\"link\":{},\"url\":{}}}",
+                string(description),
+                link_count,
+                string(target)
+              )
+            }
+            GeminiLine::Heading(level, text) => format!(
+              "{{\"type\":\"heading\",\"level\":{},\"text\":{}}}",
+              level,
+              string(text)
+            ),
+            GeminiLine::Text(text) => format!("{{\"type\":\"text\",\"text\":{}}}", string(text)),
+            GeminiLine::ListItem(text) => {
+              format!("{{\"type\":\"list\",\"text\":{}}}", string(text))
+            }
+            GeminiLine::Quote(text) => format!("{{\"type\":\"quote\",\"text\":{}}}", string(text)),
+            GeminiLine::Preformatted(text) => {
+              format!("{{\"type\":\"preformatted\",\"text\":{}}}", string(text))
+            }
+          })
+          .collect();
+        format!(
+          "{{\"url\":{},\"type\":\"gemini\",\"lines\":[{}]}}",
+          url,
+          lines.join(",")
+        )
+      }
+      GopherResponse::Menu(response) => {
+        let mut items = Vec::new();
+        let mut errors = Vec::new();
+        for (index, line) in response.lines.iter().enumerate() {
+          match line {
+            Ok(item) => {
+              let link = match response.links.iter().position(|link| *link == index) {
+                Some(position) => (position + 1).to_string(),
+                None => "null".to_string(),
+              };
+              // Information and error lines don't point anywhere
+              let item_url = match &item.r#type[..] {
+                "i" | "3" => None,
+                _ => Some(item.get_url()).filter(|url| !url.is_empty()),
+              };
+              items.push(format!(
+                "{{\"line\":{},\"type\":{},\"description\":{},\"selector\":{},\"host\":{},\"port\":{},\"link\":{},\"url\":{}}}",
+                index + 1,
+                string(&item.r#type),
+                string(&item.description),
+                string(&item.selector),
+                string(&item.host),
+                string(&item.port),
+                link,
+                optional_string(item_url),
+              ));
+            }
+            Err(msg) => errors.push(format!(
+              "{{\"line\":{},\"message\":{}}}",
+              index + 1,
+              string(msg)
+            )),
+          }
+        }
+        format!(
+          "{{\"url\":{},\"type\":\"menu\",\"items\":[{}],\"errors\":[{}]}}",
+          url,
+          items.join(","),
+          errors.join(",")
+        )
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests_json {
+  use super::*;
+  use crate::{GopherMenuResponse, GopherTextResponse};
+
+  #[test]
+  fn should_escape_strings() {
+    assert_eq!("\"plain\"", string("plain"));
+    assert_eq!(
+      "\"\\\"quoted\\\" \\\\ tab\\t\\u001b[1m\"",
+      string("\"quoted\" \\ tab\t\x1b[1m")
+    );
+    assert_eq!(
+      "\"\\u009b31m \\u007f \\u0085\"",
+      string("\u{9b}31m \u{7f} \u{85}")
+    );
+  }
+
+  #[test]
+  fn should_describe_menus() {
+    let response = GopherResponse::Menu(GopherMenuResponse::from(
+      "iWelcome\t\terror.host\t1\r\n1Sub\t/sub\tlocalhost\t7070\r\n1Broken\t/x\r\n",
+    ));
+    let url = GopherURL::from("localhost:7070").unwrap();
+    assert_eq!(
+      "{\"url\":\"gopher://localhost:7070/1\",\"type\":\"menu\",\"items\":[\
+       {\"line\":1,\"type\":\"i\",\"description\":\"Welcome\",\"selector\":\"\",\
+       \"host\":\"error.host\",\"port\":\"1\",\"link\":null,\"url\":null},\
+       {\"line\":2,\"type\":\"1\",\"description\":\"Sub\",\"selector\":\"/sub\",\
+       \"host\":\"localhost\",\"port\":\"7070\",\"link\":1,\"url\":\"gopher://localhost:7070/1/sub\"}],\
+       \"errors\":[{\"line\":3,\"message\":\"Could not parse host in: \\\"1Broken\\t/x\\\"\"}]}",
+      response.to_json(Some(&url))
+    );
+  }
+
+  #[test]
+  fn should_describe_text() {
+    let response = GopherResponse::Text(GopherTextResponse::from("Hello\r\n\"world\"\r\n.\r\n"));
+    assert_eq!(
+      "{\"url\":null,\"type\":\"text\",\"lines\":[\"Hello\",\"\\\"world\\\"\"]}",
+      response.to_json(None)
+    );
+  }
+}
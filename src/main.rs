@@ -31,112 +31,186 @@
 // The fact that you are presently reading this means that you have had
 // knowledge of the CeCILL license and that you accept its terms.
 
+mod alias;
+mod check;
+mod completion;
+mod config;
+mod connect;
+mod editor;
+mod gateway;
+mod gemini;
+mod gophermap;
+mod html;
+mod json;
+mod mirror;
+mod sanitize;
+mod save;
+mod script;
+mod search;
+mod server;
+mod session;
+mod signals;
+mod sniff;
+mod tabs;
+mod theme;
+mod url;
+mod wrap;
+
 use std::env;
+use std::fmt;
 use std::fs::{File, OpenOptions};
-use std::io::{self, stdin, BufReader, ErrorKind, Read, Write};
+use std::io::{self, stdin, BufReader, ErrorKind, IsTerminal, Read, Write};
 use std::net::TcpStream;
 use std::path::Path;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use config::{Config, MenuMode, OutputFormat, SanitizeMode, WrapMode};
+use editor::LineEditor;
+use sanitize::sanitize;
+use search::Pattern;
+use sniff::Content;
+use tabs::Tab;
+use theme::Theme;
 
 const SOFTWARE_NAME: &str = "marmotte";
 const VERSION: &str = env!("CARGO_PKG_VERSION");
+// Set when running a script, so that nothing waits for an answer
+static BATCH_MODE: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Scheme {
+  Gopher,
+  // Gemini URLs have no type, their selector is the path and their search
+  // the query
+  Gemini,
+}
 
-#[derive(Debug, Clone)]
+// Parsing and serialization are in url.rs
+#[derive(Debug, Clone, PartialEq)]
 struct GopherURL {
+  scheme: Scheme,
   host: String,
-  port: String,
+  port: u16,
   r#type: String,
   selector: String,
+  search: Option<String>,
+  gopher_plus: Option<String>,
 }
 
 impl GopherURL {
   fn new() -> GopherURL {
     GopherURL {
+      scheme: Scheme::Gopher,
       host: String::new(),
-      port: String::from("70"),
+      port: 70,
       r#type: String::from("1"),
       selector: String::new(),
+      search: None,
+      gopher_plus: None,
     }
   }
 
-  fn from(url: &str) -> GopherURL {
-    let mut parsed_url = url;
-    // Remove scheme from URL when included
-    if url.starts_with("gopher://") {
-      parsed_url = &url[9..];
+  #[allow(clippy::needless_return)]
+  fn get_server(&self) -> String {
+    // IPv6 addresses are enclosed in brackets to separate the port
+    if self.host.contains(':') {
+      return format!("[{}]:{}", &self.host, &self.port);
     }
+    return format!("{}:{}", &self.host, &self.port);
+  }
 
-    // Create GopherURL variable to receive the URL
-    let mut parsed_gopher_url = GopherURL::new();
-    // Split URL on "/" in three first elements
-    let url_elements: Vec<&str> = parsed_url.splitn(2, "/").collect();
-
-    // Get host from URL and port if specified
-    // If the URL contains a ":", it means the port is specified
-    if url_elements[0].contains(":") {
-      let port_idx = url_elements[0].find(":").unwrap();
-      parsed_gopher_url.host = url_elements[0][0..port_idx].to_string();
-      parsed_gopher_url.port = url_elements[0][port_idx + 1..].to_string();
-    } else {
-      parsed_gopher_url.host = url_elements[0].to_string();
+  // Line sent to the server to request the item
+  #[allow(clippy::needless_return)]
+  fn get_request(&self) -> String {
+    let mut request = self.selector.clone();
+    if let Some(search) = &self.search {
+      request.push('\t');
+      request.push_str(search);
     }
-
-    // Get resource type and selector if specified
-    if let Some(elm) = url_elements.get(1) {
-      parsed_gopher_url.r#type = elm[0..1].to_string();
-      parsed_gopher_url.selector = elm[1..].to_string();
+    if let Some(gopher_plus) = &self.gopher_plus {
+      if self.search.is_none() {
+        request.push('\t');
+      }
+      request.push('\t');
+      request.push_str(gopher_plus);
     }
-
-    return parsed_gopher_url;
-  }
-
-  fn get_server(&self) -> String {
-    return format!("{}:{}", &self.host, &self.port);
+    return request;
   }
 
+  #[allow(clippy::needless_return)]
   fn get_url(&self) -> Option<String> {
-    if &self.host == "" {
+    if self.host.is_empty() {
       return None;
-    } else {
-      return Some(format!(
-        "gopher://{}:{}/{}{}",
-        &self.host, &self.port, &self.r#type, &self.selector
-      ));
     }
+    if self.scheme == Scheme::Gemini {
+      let server = self.get_server();
+      let server = match self.port {
+        gemini::DEFAULT_PORT => server
+          .rsplit_once(':')
+          .map_or(&server[..], |(host, _)| host),
+        _ => &server,
+      };
+      let query = match &self.search {
+        Some(query) => format!("?{}", query),
+        None => String::new(),
+      };
+      return Some(format!("gemini://{}{}{}", server, self.selector, query));
+    }
+    let mut url = format!(
+      "gopher://{}/{}{}",
+      self.get_server(),
+      url::encode(&self.r#type),
+      url::encode(&self.selector)
+    );
+    if self.search.is_some() || self.gopher_plus.is_some() {
+      url.push_str("%09");
+      url.push_str(&url::encode(self.search.as_deref().unwrap_or("")));
+    }
+    if let Some(gopher_plus) = &self.gopher_plus {
+      url.push_str("%09");
+      url.push_str(&url::encode(gopher_plus));
+    }
+    return Some(url);
   }
 
+  #[allow(clippy::needless_return)]
   fn get_url_parent_selector(&self) -> Option<String> {
-    if &self.host == "" {
+    // At the server root there is no parent
+    if self.host.is_empty() || self.selector.is_empty() {
       return None;
     }
-    // This means we are at the server root, so no parent.
-    else if &self.selector == "" {
-      return None;
-    } else {
-      match self.selector.trim_end_matches('/').rfind("/") {
-        Some(idx) => {
-          return Some(format!(
-            "gopher://{}:{}/{}{}",
-            &self.host,
-            &self.port,
-            "1",
-            &self.selector[..idx]
-          ));
-        }
-        None => {
-          return Some(format!("gopher://{}:{}", &self.host, &self.port));
-        }
+    if self.scheme == Scheme::Gemini {
+      let path = self.selector.trim_end_matches('/');
+      let mut parent = self.clone();
+      parent.search = None;
+      parent.selector = path[..path.rfind('/')? + 1].to_string();
+      return parent.get_url();
+    }
+    match self.selector.trim_end_matches('/').rfind("/") {
+      Some(idx) => {
+        let mut parent = GopherURL::new();
+        parent.host = self.host.clone();
+        parent.port = self.port;
+        parent.selector = self.selector[..idx].to_string();
+        return parent.get_url();
       }
+      // Selectors without '/' are right below the server root
+      None => return Some(format!("gopher://{}", self.get_server())),
     }
   }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 struct GopherMenuLine {
   r#type: String,
   description: String,
   selector: String,
   host: String,
   port: String,
+  // Extra field of Gopher+ items, usually "+"
+  gopher_plus: Option<String>,
 }
 
 impl GopherMenuLine {
@@ -172,24 +246,178 @@ impl GopherMenuLine {
       None => return Err(format!("Could not parse port in: \"{}\"", line)),
     };
 
+    let gopher_plus = splitted_elements.get(4).map(|el| el.to_string());
+
     Ok(GopherMenuLine {
       r#type: item_type,
-      description: description,
-      selector: selector,
-      host: host,
-      port: port,
+      description,
+      selector,
+      host,
+      port,
+      gopher_plus,
     })
   }
 
+  // Accept lines as they are commonly found: ending with LF only or with
+  // trailing spaces, without port or even without any tab, which is then
+  // taken as information text like empty lines.
+  fn from_lenient(line: &str) -> Result<GopherMenuLine, String> {
+    let line = line.trim_end_matches(['\r', ' ']);
+    if !line.contains('\t') {
+      return Ok(GopherMenuLine {
+        r#type: "i".to_string(),
+        description: line.to_string(),
+        selector: String::new(),
+        host: String::new(),
+        port: "70".to_string(),
+        gopher_plus: None,
+      });
+    }
+
+    let mut elements = line.split('\t');
+    // Can't fail as the line contains a tab
+    let first_element = elements.next().unwrap_or("");
+    let item_type = match first_element.get(0..1) {
+      Some(el) => el.to_string(),
+      None => return Err(format!("Could not parse item type in: \"{}\"", line)),
+    };
+    let description = first_element.get(1..).unwrap_or("").to_string();
+    let selector = elements.next().unwrap_or("").to_string();
+    let host = elements.next().unwrap_or("").trim().to_string();
+    let port = match elements.next().map(|port| port.trim()) {
+      Some(port) if port.parse::<u16>().is_ok() => port.to_string(),
+      _ => "70".to_string(),
+    };
+    let gopher_plus = elements.next().map(|el| el.to_string());
+
+    if host.is_empty() && !["i", "3"].contains(&&item_type[..]) {
+      return Err(format!("Could not parse host in: \"{}\"", line));
+    }
+
+    Ok(GopherMenuLine {
+      r#type: item_type,
+      description,
+      selector,
+      host,
+      port,
+      gopher_plus,
+    })
+  }
+
+  // Reject every deviation from RFC 1436 in lines, which are expected to end
+  // with CR LF
+  fn from_strict(line: &str) -> Result<GopherMenuLine, String> {
+    let line = match line.strip_suffix('\r') {
+      Some(line) => line,
+      None => return Err(format!("Missing CR before LF in: \"{}\"", line)),
+    };
+    if line.is_empty() {
+      return Err("Empty line".to_string());
+    }
+    if line == "." {
+      return Err("\".\" line before the end of the menu".to_string());
+    }
+    if line.ends_with(char::is_whitespace) {
+      return Err(format!("Trailing whitespace in: \"{}\"", line));
+    }
+
+    let menu_line = GopherMenuLine::from(line)?;
+    if menu_line.port.parse::<u16>().is_err() {
+      return Err(format!("Invalid port in: \"{}\"", line));
+    }
+    Ok(menu_line)
+  }
+
+  fn get_type_label(&self) -> &str {
+    match &self.r#type[..] {
+      "0" => "TXT",
+      "1" => "MENU",
+      "2" => "CSO",
+      "3" => "ERR",
+      "4" => "BINHEX",
+      "5" => "DOS",
+      "6" => "UUE",
+      "7" => "SEARCH",
+      "8" => "TELNET",
+      "9" => "BIN",
+      "+" => "MIRROR",
+      "g" => "GIF",
+      "I" => "IMG",
+      "T" => "TN3270",
+      "h" => "HTML",
+      "s" => "SND",
+      "d" => "DOC",
+      "i" => "",
+      _ => "UNKNOWN",
+    }
+  }
+
+  // Every item but information and errors gets a link number, as it can at
+  // least be saved
+  fn is_link(&self) -> bool {
+    !["i", "3"].contains(&&self.r#type[..])
+  }
+
+  // Whether the item points to another server than the given host.
+  // Information and error lines usually have a dummy host so they are ignored.
+  fn is_external(&self, host: &str) -> bool {
+    !["i", "3"].contains(&&self.r#type[..])
+      && !self.host.is_empty()
+      && !host.is_empty()
+      && !self.host.eq_ignore_ascii_case(host)
+  }
+
+  #[allow(clippy::needless_return)]
+  fn to_url(&self) -> Option<GopherURL> {
+    if self.host.is_empty() {
+      return None;
+    }
+    let mut url = GopherURL::new();
+    url.host = self.host.clone();
+    url.port = match self.port.trim() {
+      "" => 70,
+      port => port.parse::<u16>().ok().filter(|port| *port > 0)?,
+    };
+    url.r#type = self.r#type.clone();
+    url.selector = self.selector.clone();
+    return Some(url);
+  }
+
+  // Line of the item as sent by servers, see the Display implementation
+  #[allow(clippy::needless_return)]
+  fn to_wire(&self) -> String {
+    return format!("{}\r\n", self);
+  }
+
+  #[allow(clippy::needless_return)]
   fn get_url(&self) -> String {
-    if &self.host == "" {
-      return String::new();
-    } else {
-      return format!(
-        "gopher://{}:{}/{}{}",
-        &self.host, &self.port, &self.r#type, &self.selector
-      );
+    return self
+      .to_url()
+      .and_then(|url| url.get_url())
+      .unwrap_or_default();
+  }
+}
+
+// Tabs and line breaks can't be sent in fields, they are replaced by spaces
+fn wire_field(field: &str) -> String {
+  field.replace(['\t', '\r', '\n'], " ")
+}
+
+impl fmt::Display for GopherMenuLine {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(
+      f,
+      "{}{}\t{}\t{}\t{}",
+      wire_field(&self.r#type),
+      wire_field(&self.description),
+      wire_field(&self.selector),
+      wire_field(&self.host),
+      wire_field(&self.port)
+    )?;
+    if let Some(gopher_plus) = &self.gopher_plus {
+      write!(f, "\t{}", wire_field(gopher_plus))?;
     }
+    Ok(())
   }
 }
 
@@ -201,20 +429,36 @@ struct GopherMenuResponse {
 
 impl GopherMenuResponse {
   fn from(response: &str) -> GopherMenuResponse {
+    GopherMenuResponse::parse(response, MenuMode::Lenient)
+  }
+
+  // Parse a menu, keeping one entry per line of the response so that errors
+  // can be located. In strict mode, lines deviating from RFC 1436 are errors.
+  fn parse(response: &str, mode: MenuMode) -> GopherMenuResponse {
     let mut lines = Vec::new();
     let mut links = Vec::new();
 
-    for (index, line) in response.split("\r\n").enumerate() {
-      // dot indicates end of response
-      if line == "." || line.trim() == "" {
-        break;
-      }
+    let raw_lines: Vec<&str> = response.split('\n').collect();
+    // Lines after the last one with content are ignored, as well as the
+    // final dot which indicates the end of the response
+    let mut end = raw_lines
+      .iter()
+      .rposition(|line| line.trim() != "")
+      .map_or(0, |index| index + 1);
+    let terminated = end > 0 && raw_lines[end - 1].trim_end() == ".";
+    if terminated {
+      end -= 1;
+    }
 
-      let gopherline = GopherMenuLine::from(line);
+    for (index, raw_line) in raw_lines[..end].iter().enumerate() {
+      let gopherline = match mode {
+        MenuMode::Lenient => GopherMenuLine::from_lenient(raw_line),
+        MenuMode::Strict => GopherMenuLine::from_strict(raw_line),
+      };
 
       // We detect lines which are links and push them into dedicated vector
       if let Ok(gopherline) = &gopherline {
-        if ["0".to_string(), "1".to_string()].contains(&gopherline.r#type) {
+        if gopherline.is_link() {
           links.push(index);
         }
       }
@@ -222,103 +466,329 @@ impl GopherMenuResponse {
       lines.push(gopherline);
     }
 
+    if mode == MenuMode::Strict && !terminated {
+      lines.push(Err("Missing \".\" line at the end of the menu".to_string()));
+    }
+
     GopherMenuResponse { lines, links }
   }
+
+  fn from_items(items: Vec<GopherMenuLine>) -> GopherMenuResponse {
+    let links = items
+      .iter()
+      .enumerate()
+      .filter(|(_, item)| item.is_link())
+      .map(|(index, _)| index)
+      .collect();
+    GopherMenuResponse {
+      lines: items.into_iter().map(Ok).collect(),
+      links,
+    }
+  }
+
+  // Menu as sent by servers, see the Display implementation
+  fn to_wire(&self) -> String {
+    format!("{}", self)
+  }
+}
+
+// Lines which could not be parsed are left out
+impl fmt::Display for GopherMenuResponse {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    for item in self.lines.iter().flatten() {
+      write!(f, "{}", item.to_wire())?;
+    }
+    write!(f, ".\r\n")
+  }
 }
 
 #[derive(Clone)]
 struct GopherTextResponse {
   lines: Vec<String>,
+  // URLs found in the text, in order of appearance
+  links: Vec<String>,
 }
 
 impl GopherTextResponse {
   fn new() -> GopherTextResponse {
-    GopherTextResponse { lines: Vec::new() }
+    GopherTextResponse {
+      lines: Vec::new(),
+      links: Vec::new(),
+    }
   }
 
   fn from(response: &str) -> GopherTextResponse {
     let mut lines = Vec::new();
+    let mut links = Vec::new();
 
     for line in response.split("\n").collect::<Vec<&str>>() {
       // dot indicates end of response
-      if line == "." {
+      if line.trim_end_matches('\r') == "." {
         break;
       }
 
+      for url in find_urls(&sanitize(line, SanitizeMode::Strict)) {
+        links.push(url.to_string());
+      }
       lines.push(line.to_string());
     }
 
-    GopherTextResponse { lines }
+    GopherTextResponse { lines, links }
+  }
+}
+
+// Schemes of URLs which can be followed from text documents
+const LINK_SCHEMES: [&str; 2] = ["gopher://", "gemini://"];
+
+// Find URLs in a line of text. A URL ends at the first whitespace or
+// control character, and trailing punctuation is not considered part of it.
+fn find_urls(line: &str) -> Vec<&str> {
+  let mut urls = Vec::new();
+  let mut position = 0;
+
+  while position < line.len() {
+    let next_url = LINK_SCHEMES
+      .iter()
+      .filter_map(|scheme| line[position..].find(scheme).map(|idx| (idx, scheme.len())))
+      .min();
+    let (start, scheme_length) = match next_url {
+      Some((idx, scheme_length)) => (position + idx, scheme_length),
+      None => break,
+    };
+
+    let length = line[start..]
+      .find(|c: char| c.is_whitespace() || c.is_control() || "<>\"".contains(c))
+      .unwrap_or(line.len() - start);
+    let url = line[start..start + length].trim_end_matches(|c| ".,;:!?)'".contains(c));
+    if url.len() > scheme_length {
+      urls.push(url);
+    }
+    position = start + length.max(scheme_length);
+  }
+
+  urls
+}
+
+// Append the link number after each URL of a line, glued to the URL when
+// text is wrapped so that they are never separated
+fn number_links(line: &str, link_count: &mut usize, config: &Config) -> String {
+  let mut numbered = String::new();
+  let mut position = 0;
+  for url in find_urls(line) {
+    // URLs are returned in order and are slices of the line
+    let end = url.as_ptr() as usize - line.as_ptr() as usize + url.len();
+    *link_count += 1;
+    numbered.push_str(&line[position..end]);
+    numbered.push(if config.wrap != WrapMode::Off {
+      wrap::GLUE
+    } else {
+      ' '
+    });
+    numbered.push_str(&Theme::paint(
+      &config.theme.index,
+      &format!("[{}]", link_count),
+      config.colours.enabled(),
+    ));
+    position = end;
   }
+  numbered.push_str(&line[position..]);
+  numbered
 }
 
 #[derive(Clone)]
 enum GopherResponse {
   Text(GopherTextResponse),
   Menu(GopherMenuResponse),
+  Gemini(gemini::GeminiResponse),
 }
 
 impl GopherResponse {
-  fn display(&self) {
+  // Render the response as lines ready to be printed. Menus are laid out in
+  // columns: item type, link index and description. The host of the current
+  // page is used to mark items pointing to another server.
+  fn render(&self, config: &Config, host: &str) -> Vec<String> {
+    let colours = config.colours.enabled();
+    let theme = &config.theme;
+
     match &self {
       GopherResponse::Text(response) => {
-        for line in &response.lines {
-          println!("{}", line);
+        let mut link_count = 0;
+        let lines: Vec<String> = response
+          .lines
+          .iter()
+          .map(|line| {
+            // Links were found without colours, which could change where
+            // URLs end, so lines with links lose their colours
+            let strict = sanitize(line, SanitizeMode::Strict);
+            if find_urls(&strict).is_empty() {
+              sanitize(line, config.sanitize)
+            } else {
+              number_links(&strict, &mut link_count, config)
+            }
+          })
+          .collect();
+        match config.wrap {
+          WrapMode::Off => lines,
+          WrapMode::Terminal => wrap::reflow(&lines, wrap::terminal_width()),
+          WrapMode::Column(width) => wrap::reflow(&lines, width),
         }
       }
+      GopherResponse::Gemini(response) => response.render(config, host),
       GopherResponse::Menu(response) => {
+        let type_width = response
+          .lines
+          .iter()
+          .map(|line| match line {
+            Ok(line) if line.r#type == "i" => 0,
+            Ok(line) => line.get_type_label().len(),
+            Err(_) => "ERR".len(),
+          })
+          .max()
+          .unwrap_or(0);
+        let index_width = format!("[{}]", response.links.len()).len();
+        let empty_columns = format!(
+          "{:tw$}  {:iw$}  ",
+          "",
+          "",
+          tw = type_width,
+          iw = index_width
+        );
+
+        let mut rendered = Vec::new();
         for (index, line) in response.lines.iter().enumerate() {
-          match line {
+          let rendered_line = match line {
             Ok(line) => {
-              match &line.r#type[..] {
-                "0" => {
-                  let resource_type = "TXT";
-                  // We increase the link index by 1 for a more user-friendly display
-                  let displayed_index =
-                    response.links.iter().position(|&x| x == index).unwrap() + 1;
-                  println!(
-                    "{}\t[{}]\t{}",
-                    resource_type,
-                    displayed_index.to_string(),
-                    line.description
-                  );
-                }
-                "1" => {
-                  let resource_type = "MENU";
+              let description = sanitize(&line.description, config.sanitize);
+              let label = format!("{:<width$}", line.get_type_label(), width = type_width);
+              let style = match &line.r#type[..] {
+                "0" => &theme.text,
+                "1" => &theme.menu,
+                "3" => &theme.error,
+                _ => &theme.other,
+              };
+              let mut columns = match &line.r#type[..] {
+                "i" => format!(
+                  "{}{}",
+                  empty_columns,
+                  Theme::paint(&theme.info, &description, colours)
+                ),
+                "3" => format!(
+                  "{}  {:width$}  {}",
+                  Theme::paint(style, &label, colours),
+                  "",
+                  Theme::paint(style, &description, colours),
+                  width = index_width
+                ),
+                _ => {
                   // We increase the link index by 1 for a more user-friendly display
                   let displayed_index =
                     response.links.iter().position(|&x| x == index).unwrap() + 1;
-                  println!(
-                    "{}\t[{}]\t{}/",
-                    resource_type,
-                    displayed_index.to_string(),
-                    line.description
-                  );
-                }
-                "i" => {
-                  println!("\t\t{}", line.description);
-                }
-                _ => {
-                  let resource_type = "UNKNOWN";
-                  println!("{}\t\t{}", resource_type, line.description);
+                  let suffix = if line.r#type == "1" { "/" } else { "" };
+                  format!(
+                    "{}  {}  {}",
+                    Theme::paint(style, &label, colours),
+                    Theme::paint(
+                      &theme.index,
+                      &format!(
+                        "{:<width$}",
+                        format!("[{}]", displayed_index),
+                        width = index_width
+                      ),
+                      colours
+                    ),
+                    Theme::paint(style, &format!("{}{}", description, suffix), colours)
+                  )
                 }
+              };
+              if line.is_external(host) {
+                columns.push_str(&Theme::paint(
+                  &theme.external,
+                  &format!(" \u{2197} {}", sanitize(&line.host, config.sanitize)),
+                  colours,
+                ));
               }
+              columns
             }
-            Err(line) => println!("ERR\t\tmarmotte: Problem parsing line {}: {}", index, line),
-          }
+            Err(line) => format!(
+              "{}  {:width$}  {}",
+              Theme::paint(
+                &theme.error,
+                &format!("{:<tw$}", "ERR", tw = type_width),
+                colours
+              ),
+              "",
+              Theme::paint(
+                &theme.error,
+                &format!(
+                  "marmotte: Problem parsing line {}: {}",
+                  // Menus keep one entry per line of the response
+                  index + 1,
+                  sanitize(line, config.sanitize)
+                ),
+                colours
+              ),
+              width = index_width
+            ),
+          };
+          rendered.push(rendered_line.trim_end().to_string());
         }
+        rendered
       }
     }
   }
 
+  // Print the page as rendered, or as JSON when asked for
+  fn display(&self, config: &Config, url: Option<&GopherURL>) {
+    if config.format == OutputFormat::Json {
+      println!("{}", self.to_json(url));
+      return;
+    }
+    let host = url.map(|url| &url.host[..]).unwrap_or("");
+    for line in self.render(config, host) {
+      println!("{}", line);
+    }
+  }
+
+  // URL of a link which can be displayed, that is text or menu items for
+  // menus
   fn get_link_url(&self, link_idx: &str) -> Result<String, String> {
+    let url = self.get_item_url(link_idx)?;
+    if let GopherResponse::Menu(response) = &self {
+      let index = link_idx.parse::<usize>().unwrap_or(0);
+      if let Some(Ok(item)) = response.lines.get(response.links[index - 1]) {
+        if item.r#type != "0" && item.r#type != "1" {
+          return Err(format!(
+            "Link {} is not a text or a menu, use \"save {}\" to download it",
+            index, index
+          ));
+        }
+      }
+    }
+    Ok(url)
+  }
+
+  // URL of any link, whatever the type of item it points to
+  #[allow(clippy::needless_return)]
+  fn get_item_url(&self, link_idx: &str) -> Result<String, String> {
     // Note: Index given by the user has been increased by 1 for a more user-friendly display
     let idx = link_idx.parse::<usize>();
     match idx {
       Ok(index) => {
         match &self {
-          GopherResponse::Text(_response) => {
-            Err("There is no link in the current document".to_string())
+          GopherResponse::Text(response) => {
+            if response.links.is_empty() {
+              return Err("There is no link in the current document".to_string());
+            }
+            if index == 0 || response.links.len() < index {
+              return Err("Given index is out of bounds".to_string());
+            }
+            Ok(response.links[index - 1].clone())
+          }
+          GopherResponse::Gemini(response) => {
+            if index == 0 || response.links.len() < index {
+              return Err("Given index is out of bounds".to_string());
+            }
+            Ok(response.links[index - 1].clone())
           }
           GopherResponse::Menu(response) => {
             // Check if the given index is out of bounds
@@ -339,51 +809,164 @@ impl GopherResponse {
       }
     }
   }
-}
 
-fn manage_url_request(url: GopherURL, state: &mut ClientState) {
-  match TcpStream::connect(url.get_server()) {
-    Ok(mut stream) => {
-      stream
-        .write(format!("{}\r\n", url.selector).as_bytes())
-        .unwrap();
-
-      let mut buffer = String::new();
-
-      match stream.read_to_string(&mut buffer) {
-        Ok(_) => {
-          // Parse Gopher menu according to Gopher selector
-          if url.r#type == "1" {
-            state.last_response = GopherResponse::Menu(GopherMenuResponse::from(&buffer));
-          } else {
-            state.last_response = GopherResponse::Text(GopherTextResponse::from(&buffer));
-          }
-          state.last_response.display();
-          // Insert displayed page to history
-          state.history.insert(0, url);
-        }
-        Err(e) => {
-          println!("Failed to receive data: {}", e);
-        }
-      }
+  // URLs of every link, in the order of their index
+  fn get_links(&self) -> Vec<String> {
+    match &self {
+      GopherResponse::Text(response) => response.links.clone(),
+      GopherResponse::Gemini(response) => response.links.clone(),
+      GopherResponse::Menu(response) => response
+        .links
+        .iter()
+        .filter_map(|index| response.lines[*index].as_ref().ok())
+        .filter(|line| line.r#type == "0" || line.r#type == "1")
+        .map(|line| line.get_url())
+        .collect(),
     }
-    Err(e) => {
-      println!("Failed to connect: {}", e);
+  }
+}
+
+// Send the selector to the server, returning the stream to read the
+// response from
+fn request(url: &GopherURL, config: &Config) -> Result<TcpStream, String> {
+  if url.scheme != Scheme::Gopher {
+    return Err(format!(
+      "Only gopher URLs are supported here, not {}",
+      url.get_url().unwrap_or_default()
+    ));
+  }
+  // Selectors of menu items are not parsed from URLs
+  let request = url.get_request();
+  if request.contains(['\r', '\n', '\0']) {
+    return Err(url::ParseError::InvalidCharacter(request).to_string());
+  }
+  match connect::connect(&url.host, url.port, config, connect::CONNECT_TIMEOUT) {
+    Ok(mut stream) => match stream.write_all(format!("{}\r\n", request).as_bytes()) {
+      Ok(_) => Ok(stream),
+      Err(e) => Err(format!("Failed to send request: {}", e)),
+    },
+    Err(e) => Err(format!("Failed to connect: {}", e)),
+  }
+}
+
+// Send the selector to the server and read the whole response. Gemini
+// pages are requested following redirects, and give their body.
+fn fetch(url: &GopherURL, config: &Config) -> Result<Vec<u8>, String> {
+  if url.scheme == Scheme::Gemini {
+    return gemini::fetch(url.clone(), config).map(|(_, _, body)| body);
+  }
+  let mut stream = request(url, config)?;
+  let mut buffer = Vec::new();
+  match stream.read_to_end(&mut buffer) {
+    Ok(_) => Ok(buffer),
+    Err(e) => Err(format!("Failed to receive data: {}", e)),
+  }
+}
+
+// Parse response according to the type of its URL when it is a text or a
+// menu, unless its content is obviously something else, or according to its
+// content otherwise as the type is often missing or wrong. Binary content is
+// replaced by a notice as it can't be displayed.
+fn parse_response(raw_response: &[u8], item_type: &str, menus: MenuMode) -> GopherResponse {
+  let buffer = String::from_utf8_lossy(raw_response);
+  let content = match (sniff::sniff(raw_response), item_type) {
+    (content @ Content::Binary(_), _) | (content @ Content::Html, _) => content,
+    (_, "0") => Content::Text,
+    (_, "1") => Content::Menu,
+    (content, _) => content,
+  };
+  match content {
+    Content::Menu => GopherResponse::Menu(GopherMenuResponse::parse(&buffer, menus)),
+    Content::Binary(name) => GopherResponse::Text(GopherTextResponse::from(&format!(
+      "({}, {} bytes, use the save command to keep it)",
+      name,
+      raw_response.len()
+    ))),
+    Content::Text | Content::Html => GopherResponse::Text(GopherTextResponse::from(&buffer)),
+  }
+}
+
+// Servers report failures, like missing documents, with a menu whose first
+// line is an item of type "3", whatever the type requested. Documents of
+// other types may start the same way, so the item must be complete unless a
+// menu was requested. Returns the message of the server.
+fn parse_error_item(raw_response: &[u8], item_type: &str) -> Option<String> {
+  let response = String::from_utf8_lossy(raw_response);
+  let first_line = response.lines().next().unwrap_or("");
+  if !first_line.starts_with('3') || !first_line.contains('\t') {
+    return None;
+  }
+  match GopherMenuLine::from(first_line.trim_end_matches('\r')) {
+    Ok(line) if !line.host.is_empty() && line.port.parse::<u16>().is_ok() => Some(line.description),
+    // Some servers only send the description
+    _ if ["1", "7"].contains(&item_type) => first_line[1..]
+      .split('\t')
+      .next()
+      .map(|msg| msg.to_string()),
+    _ => None,
+  }
+}
+
+// Errors sent by the server are not kept as the current page nor in history
+fn manage_url_request(url: GopherURL, state: &mut ClientState) -> Result<(), String> {
+  if url.scheme == Scheme::Gemini {
+    return gemini::manage_request(url, state);
+  }
+  let raw_response = fetch(&url, &state.config)?;
+  if let Some(msg) = parse_error_item(&raw_response, &url.r#type) {
+    return Err(Theme::paint(
+      &state.config.theme.error,
+      &format!("Server error: {}", sanitize(&msg, state.config.sanitize)),
+      state.config.colours.enabled(),
+    ));
+  }
+  state.last_response = parse_response(&raw_response, &url.r#type, state.config.menus);
+  // Keep original bytes to be able to save the page
+  state.last_raw_response = raw_response;
+  state.last_response.display(&state.config, Some(&url));
+  if let Some(note) = sniff::mismatch(&url.r#type, &state.last_raw_response) {
+    // Keep JSON output parseable
+    match state.config.format {
+      OutputFormat::Text => println!("{}", note),
+      OutputFormat::Json => eprintln!("{}", note),
     }
   }
+  // Insert displayed page to history
+  state.history.insert(0, url);
+  Ok(())
 }
 
 #[derive(Clone)]
 struct ClientState {
   bookmarks: Vec<GopherURL>,
+  // Lines of the bookmarks file which are not URLs, written back as they are
+  unparsed_bookmarks: Vec<String>,
   history: Vec<GopherURL>,
   last_response: GopherResponse,
+  last_raw_response: Vec<u8>,
+  tabs: Vec<Tab>,
+  current_tab: usize,
+  config: Config,
 }
 
 impl ClientState {
+  fn new() -> ClientState {
+    ClientState {
+      bookmarks: Vec::new(),
+      unparsed_bookmarks: Vec::new(),
+      history: Vec::new(),
+      last_response: GopherResponse::Text(GopherTextResponse::new()),
+      last_raw_response: Vec::new(),
+      tabs: vec![Tab::new()],
+      current_tab: 0,
+      config: Config::new(),
+    }
+  }
+
   // Get back URL and update history
   // We update the history because we are going back and rewriting it
   // We remove the back URL because it will be put to history again after being requested
+  #[allow(clippy::needless_return)]
   fn prepare_going_back(&mut self) -> Result<GopherURL, String> {
     // Get second-to-last url
     match self.history.get(1) {
@@ -401,11 +984,12 @@ impl ClientState {
   }
 
   // Get back URL and send request
+  #[allow(clippy::needless_return)]
   fn go_back(&mut self) -> Result<String, String> {
     match self.prepare_going_back() {
       Ok(previous_url) => {
         // Load previous url
-        manage_url_request(previous_url, self);
+        manage_url_request(previous_url, self)?;
         return Ok("Went back to previous document".to_string());
       }
       Err(msg) => {
@@ -437,7 +1021,7 @@ impl ClientState {
 
               // Create folder for bookmarks and then file
               match std::fs::create_dir_all(prefix) {
-                Ok(_) => match File::create(bookmarks_location.to_string()) {
+                Ok(_) => match File::create(&bookmarks_location) {
                   Ok(created_file) => Ok(created_file),
                   Err(e) => Err(format!("Problem creating the bookmarks file: {:?}", e)),
                 },
@@ -465,14 +1049,7 @@ impl ClientState {
         let mut buf_reader = BufReader::new(file);
         let mut contents = String::new();
         match buf_reader.read_to_string(&mut contents) {
-          Ok(_) => {
-            let mut bookmarks: Vec<GopherURL> = Vec::new();
-            for line in contents.trim().split("\n") {
-              let url = GopherURL::from(line);
-              bookmarks.push(url);
-            }
-            self.bookmarks = bookmarks;
-          }
+          Ok(_) => self.parse_bookmarks(&contents),
           // This error may happen if the file has been created and has only
           // write permission.
           Err(_) => {
@@ -487,13 +1064,36 @@ impl ClientState {
     }
   }
 
+  fn parse_bookmarks(&mut self, contents: &str) {
+    self.bookmarks = Vec::new();
+    self.unparsed_bookmarks = Vec::new();
+    for line in contents.trim().split("\n") {
+      match GopherURL::from(line) {
+        Ok(url) => self.bookmarks.push(url),
+        // Like the empty line of an empty file
+        Err(_) if line.trim() == "" => (),
+        // Kept so that saving bookmarks doesn't lose lines edited by hand
+        Err(_) => self.unparsed_bookmarks.push(line.to_string()),
+      }
+    }
+  }
+
+  fn format_bookmarks(&self) -> String {
+    let mut contents = String::new();
+    for url in self.bookmarks.iter() {
+      contents.push_str(&format!("{}\n", url.get_url().unwrap()));
+    }
+    for line in self.unparsed_bookmarks.iter() {
+      contents.push_str(&format!("{}\n", line));
+    }
+    contents
+  }
+
   fn save_bookmarks(&mut self) {
     let f = self.open_bookmarks(true);
     match f {
       Ok(mut file) => {
-        for url in self.bookmarks.iter() {
-          writeln!(file, "{}", url.get_url().unwrap()).unwrap();
-        }
+        write!(file, "{}", self.format_bookmarks()).unwrap();
       }
       Err(e) => {
         println!("{}", e);
@@ -502,8 +1102,39 @@ impl ClientState {
     }
   }
 
+  // Write bookmarks as a gopher menu, which can be published with the serve
+  // command
+  fn export_bookmarks(&self, path: &str) -> Result<String, String> {
+    if path.is_empty() {
+      return Err("No file given to export to".to_string());
+    }
+    let items = self
+      .bookmarks
+      .iter()
+      .map(|url| {
+        let description = url.get_url().unwrap_or_default();
+        let mut item = GopherMenuLine {
+          r#type: url.r#type.clone(),
+          description: description.clone(),
+          selector: url.selector.clone(),
+          host: url.host.clone(),
+          port: url.port.to_string(),
+          gopher_plus: None,
+        };
+        // Links to other protocols are given as selectors by convention
+        if url.scheme != Scheme::Gopher {
+          item.r#type = "h".to_string();
+          item.selector = format!("URL:{}", description);
+        }
+        item
+      })
+      .collect();
+    let menu = GopherMenuResponse::from_items(items);
+    save::write_file(path, menu.to_wire().as_bytes())
+  }
+
   fn display_bookmarks(&self) {
-    if &self.bookmarks.len() > &0 {
+    if !self.bookmarks.is_empty() {
       println!("Bookmarks:");
       for (index, link) in self.bookmarks.iter().enumerate() {
         println!("[bk {}] {}", index, link.get_url().unwrap());
@@ -514,6 +1145,31 @@ impl ClientState {
   }
 }
 
+// Names of the commands, used for completion
+const COMMAND_NAMES: [&str; 21] = [
+  "go",
+  "up",
+  "back",
+  "bk",
+  "bookmarks",
+  "save",
+  "find",
+  "filter",
+  "view",
+  "dump",
+  "export",
+  "mirror",
+  "check",
+  "tab",
+  "tabs",
+  "session",
+  "sessions",
+  "alias",
+  "unalias",
+  "help",
+  "quit",
+];
+
 #[derive(Debug, PartialEq)]
 enum Commands {
   Up,
@@ -523,12 +1179,35 @@ enum Commands {
   DisplayBookmarks,
   AddBookmark(String),
   RemoveBookmark(String),
+  ExportBookmarks(String),
   GoBookmarkIndex(String),
+  Save(String),
+  SaveRendered(String),
+  SaveLink(String),
+  Find(String),
+  Filter(String),
+  DisplayTabs,
+  NewTab(String),
+  GoTab(String),
+  CloseTab,
+  RenameTab(String),
+  SaveSession(String),
+  LoadSession(String),
+  ListSessions,
+  DumpJson,
+  ExportHtml(String),
+  Mirror(String),
+  Check(String),
+  ViewAs(String),
+  DisplayAliases,
+  AddAlias(String),
+  RemoveAlias(String),
   Help,
   Quit,
 }
 
 impl Commands {
+  #[allow(clippy::needless_return)]
   fn parse(input: String) -> Result<Commands, String> {
     let mut args = "".to_string();
     let mut command = input.trim().to_string();
@@ -541,24 +1220,96 @@ impl Commands {
       "back" => Ok(Commands::Back),
       "quit" => Ok(Commands::Quit),
       "go" => {
-        if args == "" {
+        if args.is_empty() {
           return Err("No URL to go to".to_string());
         }
         return Ok(Commands::GoURL(args));
       }
-      "bk" | "bookmarks" if args == "" => Ok(Commands::DisplayBookmarks),
-      "bk" | "bookmarks" if args.starts_with(char::is_numeric) => {
-        return Ok(Commands::GoBookmarkIndex(args));
+      "save" if args.starts_with(char::is_numeric) => Ok(Commands::SaveLink(args)),
+      "save" if args.starts_with("--rendered") => Ok(Commands::SaveRendered(
+        args["--rendered".len()..].trim().to_string(),
+      )),
+      "save" => Ok(Commands::Save(args)),
+      "find" => {
+        if args.is_empty() {
+          return Err("Nothing to find".to_string());
+        }
+        return Ok(Commands::Find(args));
       }
-      "bk" | "bookmarks" => {
+      "filter" => Ok(Commands::Filter(args)),
+      "tab" | "tabs" if args.is_empty() => Ok(Commands::DisplayTabs),
+      "tab" if args.starts_with(char::is_numeric) => Ok(Commands::GoTab(args)),
+      "tab" => {
+        // Parsing again to get subcommands
+        let mut command = args.clone();
+        args = "".to_string();
+        if let Some(index) = command.find(" ") {
+          args = command.split_off(index).trim().to_string();
+        }
+        match &command[..] {
+          "new" => Ok(Commands::NewTab(args)),
+          "close" => Ok(Commands::CloseTab),
+          "name" => Ok(Commands::RenameTab(args)),
+          _ => Err("Tab subcommand not found".to_string()),
+        }
+      }
+      "dump" => match &args[..] {
+        "json" => Ok(Commands::DumpJson),
+        _ => Err(format!("Unknown dump format \"{}\"", args)),
+      },
+      "export" => {
+        let (format, args) = match args.find(' ') {
+          Some(idx) => (args[..idx].to_string(), args[idx..].trim().to_string()),
+          None => (args, String::new()),
+        };
+        match &format[..] {
+          "html" => Ok(Commands::ExportHtml(args)),
+          _ => Err(format!("Unknown export format \"{}\"", format)),
+        }
+      }
+      "mirror" => Ok(Commands::Mirror(args)),
+      "check" => Ok(Commands::Check(args)),
+      "view" => match args.strip_prefix("as ") {
+        Some(mode) => Ok(Commands::ViewAs(mode.trim().to_string())),
+        None => Err("Usage: view as [menu|text|raw]".to_string()),
+      },
+      "alias" if args.is_empty() => Ok(Commands::DisplayAliases),
+      "alias" => Ok(Commands::AddAlias(args)),
+      "unalias" => {
+        if args.is_empty() {
+          return Err("No alias to remove".to_string());
+        }
+        return Ok(Commands::RemoveAlias(args));
+      }
+      "session" | "sessions" if args.is_empty() || args == "list" => Ok(Commands::ListSessions),
+      "session" => {
         // Parsing again to get subcommands
         let mut command = args.clone();
+        args = "".to_string();
         if let Some(index) = command.find(" ") {
           args = command.split_off(index).trim().to_string();
         }
+        match &command[..] {
+          "save" | "load" if args.is_empty() => Err("No session name given".to_string()),
+          "save" => Ok(Commands::SaveSession(args)),
+          "load" => Ok(Commands::LoadSession(args)),
+          _ => Err("Session subcommand not found".to_string()),
+        }
+      }
+      "bk" | "bookmarks" if args.is_empty() => Ok(Commands::DisplayBookmarks),
+      "bk" | "bookmarks" if args.starts_with(char::is_numeric) => {
+        return Ok(Commands::GoBookmarkIndex(args));
+      }
+      "bk" | "bookmarks" => {
+        // Parsing again to get subcommands
+        let (command, args) = match args.find(' ') {
+          Some(idx) => (args[..idx].to_string(), args[idx..].trim().to_string()),
+          None => (args, String::new()),
+        };
         match &command[..] {
           "add" => Ok(Commands::AddBookmark(args)),
           "rm" => Ok(Commands::RemoveBookmark(args)),
+          "export" => Ok(Commands::ExportBookmarks(args)),
           _ => Err("Bookmark subcommand not found".to_string()),
         }
       }
@@ -572,6 +1323,16 @@ impl Commands {
     }
   }
 
+  // Same as parse, but unknown commands are errors rather than asking for
+  // help, so that scripts with a typo fail
+  fn parse_strict(input: String) -> Result<Commands, String> {
+    let verb = input.split_whitespace().next().unwrap_or("").to_string();
+    match Commands::parse(input)? {
+      Commands::Help if verb != "help" => Err(format!("Unknown command \"{}\"", verb)),
+      command => Ok(command),
+    }
+  }
+
   fn help() {
     println!(
       "Please enter one of the following commands:\n\
@@ -583,133 +1344,507 @@ impl Commands {
        \tbk [index]: Follow bookmark\n\
        \tbk add [url]: Add bookmark\n\
        \tbk rm [index]: Remove bookmark\n\
+       \tbk export [file]: Export bookmarks as a gopher menu\n\
+       \tsave [file]: Save current page as received\n\
+       \tsave --rendered [file]: Save current page as displayed\n\
+       \tsave [index]: Download link without displaying it\n\
+       \tfind [text|/regex/]: List matching lines of current page\n\
+       \tfilter [text|/regex/]: Display matching items of current menu\n\
+       \tview as [menu|text|raw]: Display current page differently\n\
+       \tdump json: Display current page as JSON\n\
+       \texport html [file]: Export current page as HTML\n\
+       \texport html [directory/] [depth]: Export current page and pages it links to\n\
+       \tmirror [url] [directory] [options]: Copy pages reachable from url, see README\n\
+       \tcheck [url] [--depth n] [--format text|json]: Find broken links of a server\n\
+       \ttabs: List tabs\n\
+       \ttab new [url]: Open a new tab\n\
+       \ttab [index]: Switch to tab\n\
+       \ttab name [name]: Name current tab\n\
+       \ttab close: Close current tab\n\
+       \tsessions: List saved sessions\n\
+       \tsession save [name]: Save tabs as a session\n\
+       \tsession load [name]: Replace tabs by a saved session\n\
+       \talias: List aliases and macros\n\
+       \talias [name] [commands]: Define alias for this session\n\
+       \tunalias [name]: Remove alias for this session\n\
        \tquit: Quit this program"
     );
   }
-}
+}
+
+// Run a command, returning false when the user wants to quit
+fn execute(command: Result<Commands, String>, state: &mut ClientState) -> Result<bool, String> {
+  match command {
+    Ok(Commands::GoURL(url)) => {
+      let gopher_url = GopherURL::from(&url)?;
+      manage_url_request(gopher_url, state)?;
+    }
+    Ok(Commands::GoIndex(index)) => match &state.last_response.get_link_url(&index) {
+      Ok(link_url) => {
+        let url = GopherURL::from(link_url)?;
+        manage_url_request(url, state)?;
+      }
+      Err(msg) => {
+        return Err(sanitize(msg, state.config.sanitize));
+      }
+    },
+    Ok(Commands::Up) => match state.history.first() {
+      Some(last_url) => match last_url.get_url_parent_selector() {
+        Some(parent_url) => {
+          let url = GopherURL::from(&parent_url)?;
+          manage_url_request(url, state)?;
+        }
+        None => {
+          return Err("Seems there is no parent for this document".to_string());
+        }
+      },
+      None => {
+        return Err("There is no current document".to_string());
+      }
+    },
+    Ok(Commands::Back) => {
+      state.go_back()?;
+    }
+    Ok(Commands::DisplayBookmarks) => state.display_bookmarks(),
+    Ok(Commands::GoBookmarkIndex(args)) => {
+      let index = match args.parse::<usize>() {
+        Ok(i) => i,
+        Err(error) => {
+          return Err(format!("Could not parse the bookmarks index: {:?}", error));
+        }
+      };
+      if let Some(url) = state.bookmarks.get(index) {
+        // We need to url.clone() because the URL needs to be kept in the
+        // bookmarks AND in the browsing history
+        manage_url_request(url.clone(), state)?;
+      } else {
+        return Err("There is no bookmark at this index".to_string());
+      }
+    }
+    Ok(Commands::AddBookmark(args)) => {
+      let url = GopherURL::from(&args)?;
+      state.bookmarks.push(url);
+      state.save_bookmarks();
+      state.display_bookmarks();
+    }
+    Ok(Commands::ExportBookmarks(path)) => println!("{}", state.export_bookmarks(&path)?),
+    Ok(Commands::RemoveBookmark(args)) => {
+      let index = match args.parse::<usize>() {
+        Ok(i) => i,
+        Err(error) => {
+          return Err(format!("Could not parse the bookmarks index: {:?}", error));
+        }
+      };
+      if index >= state.bookmarks.len() {
+        return Err("There is no bookmark at this index".to_string());
+      }
+      state.bookmarks.remove(index);
+      state.save_bookmarks();
+      state.display_bookmarks();
+    }
+    Ok(Commands::Save(path)) => println!("{}", state.save_page(&path, false)?),
+    Ok(Commands::SaveRendered(path)) => println!("{}", state.save_page(&path, true)?),
+    Ok(Commands::SaveLink(index)) => match state.save_link(&index, "") {
+      Ok(msg) => println!("{}", msg),
+      Err(msg) => return Err(sanitize(&msg, state.config.sanitize)),
+    },
+    Ok(Commands::Find(pattern)) => {
+      let pattern = Pattern::from(&pattern)?;
+      let host = state
+        .history
+        .first()
+        .map(|url| url.host.clone())
+        .unwrap_or_default();
+      let lines = state.last_response.find(&pattern, &state.config, &host);
+      // Not finding anything is an error so that scripts can check pages
+      if lines.is_empty() {
+        return Err("No match in the current page".to_string());
+      }
+      for line in lines {
+        println!("{}", line);
+      }
+    }
+    Ok(Commands::Filter(pattern)) => {
+      let pattern = Pattern::from(&pattern)?;
+      let host = state
+        .history
+        .first()
+        .map(|url| url.host.clone())
+        .unwrap_or_default();
+      let lines = state.last_response.filter(&pattern, &state.config, &host)?;
+      if lines.is_empty() {
+        return Err("No matching item in the current menu".to_string());
+      }
+      for line in lines {
+        println!("{}", line);
+      }
+    }
+    Ok(Commands::DisplayTabs) => state.display_tabs(),
+    Ok(Commands::NewTab(url)) => state.new_tab(&url)?,
+    Ok(Commands::GoTab(index)) => state.switch_tab(&index)?,
+    Ok(Commands::CloseTab) => state.close_tab()?,
+    Ok(Commands::RenameTab(name)) => state.rename_tab(&name),
+    Ok(Commands::SaveSession(name)) => {
+      state.save_session(&name)?;
+      println!("Session {} saved", name);
+    }
+    Ok(Commands::LoadSession(name)) => {
+      if !state.restore_session(&name)? {
+        return Err(format!("There is no session named {}", name));
+      }
+      state.show_current_tab()?;
+    }
+    Ok(Commands::ListSessions) => {
+      let names = session::list_sessions()?;
+      if names.is_empty() {
+        println!("There are no saved sessions");
+      } else {
+        println!("Sessions:");
+        for name in names {
+          println!("  {}", name);
+        }
+      }
+    }
+    Ok(Commands::DumpJson) => {
+      println!("{}", state.last_response.to_json(state.history.first()));
+    }
+    Ok(Commands::ExportHtml(args)) => println!("{}", state.export_html(&args)?),
+    Ok(Commands::Mirror(args)) => {
+      let (url, directory, options) = mirror::parse_args(&args)?;
+      println!(
+        "{}",
+        mirror::mirror(&url, &directory, &options, &state.config)?
+      );
+    }
+    Ok(Commands::Check(args)) => {
+      let (url, options) = check::parse_args(&args, &state.config)?;
+      let (summary, broken) = check::check(&url, &options, &state.config)?;
+      println!("{}", summary);
+      // Broken links are an error so that scripts fail
+      if broken > 0 {
+        return Err(format!("Found {} broken links", broken));
+      }
+    }
+    Ok(Commands::ViewAs(mode)) => state.view_as(&mode)?,
+    Ok(Commands::DisplayAliases) => {
+      if state.config.aliases.is_empty() {
+        println!("There are no aliases");
+      } else {
+        println!("Aliases:");
+        for (name, expansion) in &state.config.aliases {
+          println!("  {} = {}", name, expansion);
+        }
+      }
+    }
+    Ok(Commands::AddAlias(definition)) => {
+      let (name, expansion) = alias::parse_definition(&definition)?;
+      state.config.aliases.insert(name, expansion);
+    }
+    Ok(Commands::RemoveAlias(name)) => {
+      if state.config.aliases.remove(&name).is_none() {
+        return Err(format!("There is no alias named {}", name));
+      }
+    }
+    Err(msg) => return Err(format!("Command parsing error: {}", msg)),
+    Ok(Commands::Quit) => return Ok(false),
+    Ok(Commands::Help) => Commands::help(),
+  }
+  Ok(true)
+}
+
+// Ask a question to the user and return the trimmed answer, or an empty
+// answer to pick the default when running a script
+fn prompt(question: &str) -> String {
+  if BATCH_MODE.load(Ordering::Relaxed) {
+    return String::new();
+  }
+  print!("{}", question);
+  io::stdout().flush().unwrap();
+
+  let mut answer = String::new();
+  stdin().read_line(&mut answer).expect("Failed to read line");
+  answer.trim().to_string()
+}
+
+// Command line options
+struct Options {
+  // Do not restore the session of the last run
+  fresh: bool,
+  // Run commands from this file instead of the prompt
+  script: Option<String>,
+  // Do not stop scripts at the first error
+  keep_going: bool,
+  // Output format of pages, overriding the configuration
+  format: Option<String>,
+  // Report connections, overriding the configuration
+  verbose: bool,
+  // Serve gopher pages over HTTP instead of browsing
+  serve_http: bool,
+  // Address to serve on
+  listen: Option<String>,
+  // Serve on addresses reachable from other machines
+  allow_remote: bool,
+  // Serve a directory over gopher instead of browsing
+  serve: bool,
+  directory: Option<String>,
+  port: Option<String>,
+  // Arguments of the gophermap command, run instead of browsing
+  gophermap: Option<Vec<String>>,
+  url: Option<String>,
+}
+
+impl Options {
+  fn from(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+      fresh: false,
+      script: None,
+      keep_going: false,
+      format: None,
+      verbose: false,
+      serve_http: false,
+      listen: None,
+      allow_remote: false,
+      serve: false,
+      directory: None,
+      port: None,
+      gophermap: None,
+      url: None,
+    };
+    let mut args = args.iter().skip(1);
+    while let Some(arg) = args.next() {
+      match &arg[..] {
+        "--fresh" => options.fresh = true,
+        "--keep-going" => options.keep_going = true,
+        "--verbose" => options.verbose = true,
+        "--format" => match args.next() {
+          Some(format) => options.format = Some(format.to_string()),
+          None => return Err("No format given to --format".to_string()),
+        },
+        "--listen" => match args.next() {
+          Some(address) => options.listen = Some(address.to_string()),
+          None => return Err("No address given to --listen".to_string()),
+        },
+        "--allow-remote" => options.allow_remote = true,
+        "--port" => match args.next() {
+          Some(port) => options.port = Some(port.to_string()),
+          None => return Err("No port given to --port".to_string()),
+        },
+        "serve-http" if options.url.is_none() => options.serve_http = true,
+        "serve" if options.url.is_none() => options.serve = true,
+        "gophermap" if options.url.is_none() => {
+          options.gophermap = Some(args.by_ref().cloned().collect());
+        }
+        _ if options.serve && !arg.starts_with("--") => options.directory = Some(arg.to_string()),
+        "--script" => match args.next() {
+          Some(path) => options.script = Some(path.to_string()),
+          None => return Err("No script given to --script".to_string()),
+        },
+        _ if arg.starts_with("--") => return Err(format!("Unknown option {}", arg)),
+        _ => options.url = Some(arg.to_string()),
+      }
+    }
+    // Options of other modes would be silently ignored
+    if (options.listen.is_some() || options.allow_remote) && !options.serve_http {
+      return Err("--listen and --allow-remote are only options of serve-http".to_string());
+    }
+    if options.port.is_some() && !options.serve {
+      return Err("--port is only an option of serve".to_string());
+    }
+    Ok(options)
+  }
+}
+
+// Configuration file with settings given as options on top
+fn load_config(options: &Options) -> Config {
+  let mut config = Config::load();
+  if let Some(format) = &options.format {
+    if let Err(msg) = config.set("format", format) {
+      eprintln!("{}", msg);
+      process::exit(2);
+    }
+  }
+  if options.verbose {
+    config.verbose = true;
+  }
+  config
+}
+
+// Run commands without prompt from a script file, or from stdin when it is
+// not a terminal. The last session is left untouched.
+fn run_script(options: &Options) -> ! {
+  BATCH_MODE.store(true, Ordering::Relaxed);
+
+  let mut state = ClientState::new();
+  state.config = load_config(options);
+  state.load_bookmarks();
+
+  if let Some(url) = &options.url {
+    let result = GopherURL::from(url)
+      .map_err(String::from)
+      .and_then(|url| manage_url_request(url, &mut state));
+    if let Err(msg) = result {
+      eprintln!("{}", msg);
+      if !options.keep_going {
+        process::exit(1);
+      }
+    }
+  }
+
+  let success = match &options.script {
+    Some(path) => match File::open(path) {
+      Ok(file) => script::run(BufReader::new(file), path, options.keep_going, &mut state),
+      Err(e) => {
+        eprintln!("Failed to open {}: {}", path, e);
+        process::exit(2);
+      }
+    },
+    None => script::run(stdin().lock(), "stdin", options.keep_going, &mut state),
+  };
+  process::exit(if success { 0 } else { 1 });
+}
+
+fn main() {
+  // Must be done before any thread is spawned
+  let last_session = Arc::new(Mutex::new(None));
+  signals::save_session_on_signals(last_session.clone(), session::LAST_SESSION);
+
+  let args: Vec<String> = env::args().collect();
+  let options = match Options::from(&args) {
+    Ok(options) => options,
+    Err(msg) => {
+      println!(
+        "{}\nUsage: {} [--fresh] [--verbose] [--format text|json] [--script file] [--keep-going] [url]\n       {} serve-http [--listen address [--allow-remote]]\n       {} serve [directory] [--port port]\n       {} gophermap lint|fmt [file]",
+        msg, SOFTWARE_NAME, SOFTWARE_NAME, SOFTWARE_NAME, SOFTWARE_NAME
+      );
+      process::exit(2);
+    }
+  };
+
+  if options.serve_http {
+    let address = options
+      .listen
+      .as_deref()
+      .unwrap_or(gateway::DEFAULT_ADDRESS);
+    if let Err(msg) = gateway::serve(address, options.allow_remote, load_config(&options)) {
+      eprintln!("{}", msg);
+      process::exit(1);
+    }
+    process::exit(0);
+  }
 
-fn main() {
-  println!(
-    "Welcome to {} v{}!",
-    SOFTWARE_NAME.to_string(),
-    VERSION.to_string()
-  );
+  if let Some(args) = &options.gophermap {
+    process::exit(gophermap::run(args));
+  }
+
+  if options.serve {
+    let port = match &options.port {
+      Some(port) => match port.parse::<u16>() {
+        Ok(port) if port > 0 => port,
+        _ => {
+          eprintln!("Invalid port \"{}\"", port);
+          process::exit(2);
+        }
+      },
+      None => server::DEFAULT_PORT,
+    };
+    let directory = options.directory.as_deref().unwrap_or(".");
+    if let Err(msg) = server::serve(directory, port) {
+      eprintln!("{}", msg);
+      process::exit(1);
+    }
+    process::exit(0);
+  }
+
+  if options.script.is_some() || !stdin().is_terminal() {
+    run_script(&options);
+  }
+
+  println!("Welcome to {} v{}!", SOFTWARE_NAME, VERSION);
   println!(
     "Enter 'help' if you don't know how to start. Have a nice journey in the Gopherspace!\n"
   );
 
-  let mut state = ClientState {
-    bookmarks: Vec::new(),
-    history: Vec::new(),
-    last_response: GopherResponse::Text(GopherTextResponse::new()),
-  };
+  let mut state = ClientState::new();
+  state.config = load_config(&options);
   state.load_bookmarks();
 
+  let mut editor = LineEditor::new();
+
+  // Come back to the tabs open when quitting, unless asked not to
+  if !options.fresh {
+    let restored = match state.restore_session(session::LAST_SESSION) {
+      Ok(true) if options.url.is_none() => state.show_current_tab(),
+      Ok(_) => Ok(()),
+      Err(msg) => Err(msg),
+    };
+    if let Err(msg) = restored {
+      println!("{}", msg);
+    }
+  }
+
   // Get directly page if URL provided as argument
-  let args: Vec<String> = env::args().collect();
-  if let Some(url) = args.get(1) {
-    let parsed_url = GopherURL::from(url);
-    manage_url_request(parsed_url, &mut state);
+  if let Some(url) = &options.url {
+    let result = GopherURL::from(url)
+      .map_err(String::from)
+      .and_then(|parsed_url| manage_url_request(parsed_url, &mut state));
+    if let Err(msg) = result {
+      println!("{}", msg);
+    }
   }
 
+  let mut session_key = String::new();
   loop {
-    if let Some(last_url) = state.history.get(0) {
-      if let Some(full_url) = last_url.get_url() {
-        println!("\nCurrent page: {}", full_url);
+    // Keep the session up to date in case marmotte is stopped by a signal,
+    // copying it only when it changed
+    let key = state.session_key();
+    if key != session_key {
+      if let Ok(mut session) = last_session.lock() {
+        *session = Some(state.get_session());
       }
+      session_key = key;
     }
-    print!("{}> ", SOFTWARE_NAME.to_string());
-    io::stdout().flush().unwrap();
 
-    let mut command_input = String::new();
-    stdin()
-      .read_line(&mut command_input)
-      .expect("Failed to read line");
-
-    let command = Commands::parse(command_input);
-
-    match command {
-      Ok(Commands::GoURL(url)) => {
-        let gopher_url = GopherURL::from(&url);
-        manage_url_request(gopher_url, &mut state);
-      }
-      Ok(Commands::GoIndex(index)) => match &state.last_response.get_link_url(&index) {
-        Ok(link_url) => {
-          let url = GopherURL::from(&link_url);
-          manage_url_request(url, &mut state);
-        }
-        Err(msg) => {
-          println!("{}", msg);
-          continue;
-        }
-      },
-      Ok(Commands::Up) => match state.history.get(0) {
-        Some(last_url) => match last_url.get_url_parent_selector() {
-          Some(parent_url) => {
-            let url = GopherURL::from(&parent_url);
-            manage_url_request(url, &mut state);
-          }
-          None => {
-            println!("Seems there is no parent for this document");
-            continue;
-          }
-        },
-        None => {
-          println!("There is no current document");
-          continue;
-        }
-      },
-      Ok(Commands::Back) => match state.go_back() {
-        Ok(_msg) => {}
-        Err(msg) => {
-          println!("{}", msg);
-          continue;
-        }
-      },
-      Ok(Commands::DisplayBookmarks) => state.display_bookmarks(),
-      Ok(Commands::GoBookmarkIndex(args)) => {
-        let index = match args.parse::<usize>() {
-          Ok(i) => i,
-          Err(error) => {
-            println!("Could not parse the bookmarks index: {:?}", error);
-            continue;
-          }
-        };
-        if let Some(url) = state.bookmarks.get(index) {
-          // We need to url.clone() because the URL needs to be kept in the
-          // bookmarks AND in the browsing history
-          manage_url_request(url.clone(), &mut state);
+    if let Some(last_url) = state.history.first() {
+      if let Some(full_url) = last_url.get_url() {
+        let tab = if state.tabs.len() > 1 {
+          format!(" (tab {}/{})", state.current_tab + 1, state.tabs.len())
         } else {
-          println!("There is no bookmark at this index");
-        }
-      }
-      Ok(Commands::AddBookmark(args)) => {
-        let url = GopherURL::from(&args);
-        state.bookmarks.push(url);
-        state.save_bookmarks();
-        state.display_bookmarks();
-      }
-      Ok(Commands::RemoveBookmark(args)) => {
-        let index = match args.parse::<usize>() {
-          Ok(i) => i,
-          Err(error) => {
-            println!("Could not parse the bookmarks index: {:?}", error);
-            continue;
-          }
+          String::new()
         };
-        state.bookmarks.remove(index);
-        state.save_bookmarks();
-        state.display_bookmarks();
-      }
-      Err(msg) => println!("Command parsing error: {}", msg),
-      Ok(Commands::Quit) => {
-        println!("Goodbye!");
-        break;
+        println!(
+          "\nCurrent page{}: {}",
+          tab,
+          sanitize(&full_url, state.config.sanitize)
+        );
       }
-      _ => {
-        Commands::help();
+    }
+    // Quit at the end of input
+    let command_input = editor
+      .read_line(&format!("{}> ", SOFTWARE_NAME), &|line| {
+        state.complete(line)
+      })
+      .unwrap_or_else(|| "quit".to_string());
+
+    // Aliases and macros may give several commands
+    let commands = match alias::expand(&command_input, &state.config.aliases) {
+      Ok(commands) => commands,
+      Err(msg) => {
+        println!("{}", msg);
         continue;
       }
+    };
+    for command_input in commands {
+      match execute(Commands::parse(command_input), &mut state) {
+        Ok(true) => {}
+        Ok(false) => {
+          if let Err(msg) = state.save_session(session::LAST_SESSION) {
+            println!("{}", msg);
+          }
+          println!("Goodbye!");
+          return;
+        }
+        Err(msg) => println!("{}", msg),
+      }
     }
   }
 }
@@ -718,84 +1853,83 @@ fn main() {
 mod tests_gopher_url {
   use super::*;
 
-  impl PartialEq for GopherURL {
-    fn eq(&self, other: &Self) -> bool {
-      self.host == other.host
-        && self.port == other.port
-        && self.r#type == other.r#type
-        && self.selector == other.selector
-    }
-  }
-
   #[test]
   fn should_import_any_valid_url() {
     let mut expected = GopherURL {
       host: "zaibatsu.circumlunar.space".to_string(),
-      port: "70".to_string(),
+      port: 70,
       r#type: "1".to_string(),
       selector: "/~solderpunk/".to_string(),
+      ..GopherURL::new()
     };
     // Complete Gopher URL
     assert_eq!(
       expected,
-      GopherURL::from("gopher://zaibatsu.circumlunar.space:70/1/~solderpunk/")
+      GopherURL::from("gopher://zaibatsu.circumlunar.space:70/1/~solderpunk/").unwrap()
     );
     // Without gopher://
     assert_eq!(
       expected,
-      GopherURL::from("zaibatsu.circumlunar.space:70/1/~solderpunk/")
+      GopherURL::from("zaibatsu.circumlunar.space:70/1/~solderpunk/").unwrap()
     );
     // With gopher:// but without port number
     assert_eq!(
       expected,
-      GopherURL::from("gopher://zaibatsu.circumlunar.space/1/~solderpunk/")
+      GopherURL::from("gopher://zaibatsu.circumlunar.space/1/~solderpunk/").unwrap()
     );
     // Without gopher:// and without port number
     assert_eq!(
       expected,
-      GopherURL::from("zaibatsu.circumlunar.space/1/~solderpunk/")
+      GopherURL::from("zaibatsu.circumlunar.space/1/~solderpunk/").unwrap()
     );
 
     expected = GopherURL {
       host: "zaibatsu.circumlunar.space".to_string(),
-      port: "70".to_string(),
+      port: 70,
       r#type: "1".to_string(),
       selector: "".to_string(),
+      ..GopherURL::new()
     };
     // Hostname only
-    assert_eq!(expected, GopherURL::from("zaibatsu.circumlunar.space"));
+    assert_eq!(
+      expected,
+      GopherURL::from("zaibatsu.circumlunar.space").unwrap()
+    );
 
     expected = GopherURL {
       host: "zaibatsu.circumlunar.space".to_string(),
-      port: "70".to_string(),
+      port: 70,
       r#type: "0".to_string(),
       selector: "/~solderpunk/phlog/project-gemini.txt".to_string(),
+      ..GopherURL::new()
     };
     // Text resource URL
     assert_eq!(
       expected,
-      GopherURL::from("zaibatsu.circumlunar.space/0/~solderpunk/phlog/project-gemini.txt")
+      GopherURL::from("zaibatsu.circumlunar.space/0/~solderpunk/phlog/project-gemini.txt").unwrap()
     );
 
     expected = GopherURL {
       host: "khzae.net".to_string(),
-      port: "105".to_string(),
+      port: 105,
       r#type: "1".to_string(),
       selector: "/".to_string(),
+      ..GopherURL::new()
     };
     // Non-standard port
-    assert_eq!(expected, GopherURL::from("khzae.net:105/1/"));
+    assert_eq!(expected, GopherURL::from("khzae.net:105/1/").unwrap());
 
     expected = GopherURL {
       host: "alexschroeder.ch".to_string(),
-      port: "70".to_string(),
+      port: 70,
       r#type: "0".to_string(),
       selector: "Alex_Schroeder".to_string(),
+      ..GopherURL::new()
     };
     // Selector without '/'
     assert_eq!(
       expected,
-      GopherURL::from("gopher://alexschroeder.ch/0Alex_Schroeder")
+      GopherURL::from("gopher://alexschroeder.ch/0Alex_Schroeder").unwrap()
     );
   }
 
@@ -804,13 +1938,23 @@ mod tests_gopher_url {
     // get_server()
     assert_eq!(
       "zaibatsu.circumlunar.space:70".to_string(),
-      GopherURL::from("gopher://zaibatsu.circumlunar.space:70/1/~solderpunk/").get_server()
+      GopherURL::from("gopher://zaibatsu.circumlunar.space:70/1/~solderpunk/")
+        .unwrap()
+        .get_server()
     );
     // get_url()
     assert_eq!(
       Some("gopher://zaibatsu.circumlunar.space:70/1/~solderpunk".to_string()),
-      GopherURL::from("gopher://zaibatsu.circumlunar.space:70/1/~solderpunk").get_url()
+      GopherURL::from("gopher://zaibatsu.circumlunar.space:70/1/~solderpunk")
+        .unwrap()
+        .get_url()
     );
+    // get_request() with search terms and a Gopher+ string
+    let mut url = GopherURL::from("gopher://[::1]/7/lookup%09gopher%20hole").unwrap();
+    assert_eq!("[::1]:70", url.get_server());
+    assert_eq!("/lookup\tgopher hole", url.get_request());
+    url.gopher_plus = Some("$".to_string());
+    assert_eq!("/lookup\tgopher hole\t$", url.get_request());
   }
 
   #[test]
@@ -818,40 +1962,50 @@ mod tests_gopher_url {
     // None when already at root even with resource type
     assert_eq!(
       None,
-      GopherURL::from("gopher://zaibatsu.circumlunar.space:70/1").get_url_parent_selector()
+      GopherURL::from("gopher://zaibatsu.circumlunar.space:70/1")
+        .unwrap()
+        .get_url_parent_selector()
     );
     // None when already at root
     assert_eq!(
       None,
-      GopherURL::from("gopher://zaibatsu.circumlunar.space:70").get_url_parent_selector()
+      GopherURL::from("gopher://zaibatsu.circumlunar.space:70")
+        .unwrap()
+        .get_url_parent_selector()
     );
     // Menu parent for a text resource
     assert_eq!(
       Some("gopher://zaibatsu.circumlunar.space:70/1/~solderpunk/phlog".to_string()),
       GopherURL::from("zaibatsu.circumlunar.space/0/~solderpunk/phlog/project-gemini.txt")
+        .unwrap()
         .get_url_parent_selector()
     );
     // Menu parent for a text resource without '/'
     assert_eq!(
       Some("gopher://alexschroeder.ch:70".to_string()),
-      GopherURL::from("gopher://alexschroeder.ch:70/0Alex_Schroeder").get_url_parent_selector()
+      GopherURL::from("gopher://alexschroeder.ch:70/0Alex_Schroeder")
+        .unwrap()
+        .get_url_parent_selector()
     );
     // Menu parent for a menu resource
     assert_eq!(
       Some("gopher://zaibatsu.circumlunar.space:70/1/~solderpunk".to_string()),
       GopherURL::from("gopher://zaibatsu.circumlunar.space:70/1/~solderpunk/phlog")
+        .unwrap()
         .get_url_parent_selector()
     );
     // Root menu parent for a menu resource
     assert_eq!(
       Some("gopher://zaibatsu.circumlunar.space:70/1".to_string()),
       GopherURL::from("gopher://zaibatsu.circumlunar.space:70/1/~solderpunk")
+        .unwrap()
         .get_url_parent_selector()
     );
     // Root menu parent for a menu resource
     assert_eq!(
       Some("gopher://zaibatsu.circumlunar.space:70/1".to_string()),
       GopherURL::from("gopher://zaibatsu.circumlunar.space:70/1/~solderpunk/")
+        .unwrap()
         .get_url_parent_selector()
     );
   }
@@ -861,16 +2015,6 @@ mod tests_gopher_url {
 mod tests_gopher_menu_line {
   use super::*;
 
-  impl PartialEq for GopherMenuLine {
-    fn eq(&self, other: &Self) -> bool {
-      self.host == other.host
-        && self.port == other.port
-        && self.r#type == other.r#type
-        && self.selector == other.selector
-        && self.description == other.description
-    }
-  }
-
   #[test]
   fn should_import_any_menu_line() {
     let mut expected = GopherMenuLine {
@@ -879,6 +2023,7 @@ mod tests_gopher_menu_line {
       r#type: "1".to_string(),
       selector: "/home".to_string(),
       description: "Floodgap Home".to_string(),
+      gopher_plus: None,
     };
     // Menu line
     assert_eq!(
@@ -892,6 +2037,7 @@ mod tests_gopher_menu_line {
       r#type: "i".to_string(),
       selector: "".to_string(),
       description: "              ,-.      .-,".to_string(),
+      gopher_plus: None,
     };
     // Information line with graphics
     assert_eq!(
@@ -905,6 +2051,7 @@ mod tests_gopher_menu_line {
       r#type: "i".to_string(),
       selector: "".to_string(),
       description: "Find movie showtimes by postal code/zip.".to_string(),
+      gopher_plus: None,
     };
     // Information line with text
     assert_eq!(
@@ -918,6 +2065,7 @@ mod tests_gopher_menu_line {
       r#type: "0".to_string(),
       selector: "/rfc1436.txt".to_string(),
       description: "RFC 1436 (gopher protocol)".to_string(),
+      gopher_plus: None,
     };
     // Text resource line
     assert_eq!(
@@ -931,6 +2079,7 @@ mod tests_gopher_menu_line {
       r#type: "7".to_string(),
       selector: "/dict/search".to_string(),
       description: "Search dictionary".to_string(),
+      gopher_plus: None,
     };
     // Search resource line
     assert_eq!(
@@ -944,6 +2093,7 @@ mod tests_gopher_menu_line {
       r#type: "0".to_string(),
       selector: "moo selector".to_string(),
       description: "Some file or other".to_string(),
+      gopher_plus: Some("+".to_string()),
     };
     // Gopher+ Text resource line
     assert_eq!(
@@ -952,6 +2102,31 @@ mod tests_gopher_menu_line {
     );
   }
 
+  #[test]
+  fn should_serialize_to_wire_format() {
+    for line in [
+      "1Floodgap Home\t/home\tgopher.floodgap.com\t70",
+      "i\t\terror.host\t1",
+      "0Some file or other\tmoo selector\thost2\t70\t+",
+      "7Search\t/search\t::1\t7070\t?",
+    ] {
+      let item = GopherMenuLine::from(line).unwrap();
+      assert_eq!(format!("{}\r\n", line), item.to_wire());
+      assert_eq!(
+        Ok(item.clone()),
+        GopherMenuLine::from_strict(item.to_wire().trim_end_matches('\n'))
+      );
+    }
+
+    // Tabs and line breaks would break the line
+    let mut item = GopherMenuLine::from("0Notes\t/notes.txt\tsdf.org\t70").unwrap();
+    item.description = "To do:\tsleep\r\nand eat".to_string();
+    assert_eq!(
+      "0To do: sleep  and eat\t/notes.txt\tsdf.org\t70\r\n",
+      item.to_wire()
+    );
+  }
+
   #[test]
   fn should_return_formatted_attributes() {
     // get_url()
@@ -991,6 +2166,139 @@ mod tests_gopher_menu_line {
 mod tests_gopher_menu_response {
   use super::*;
 
+  #[test]
+  fn should_parse_menus_leniently() {
+    let response = GopherMenuResponse::from(
+      "iTitle\t\terror.host\t1\n\
+       \n\
+       1Sub\t/sub\tlocalhost  \n\
+       0Doc\t/doc\tlocalhost\t7070 \r\n\
+       plain text\r\n\
+       .\r\n\
+       \r\n",
+    );
+    let lines: Vec<GopherMenuLine> = response
+      .lines
+      .into_iter()
+      .map(|line| line.unwrap())
+      .collect();
+    assert_eq!(5, lines.len());
+    assert_eq!(("i", ""), (&lines[1].r#type[..], &lines[1].description[..]));
+    assert_eq!(
+      ("localhost", "70"),
+      (&lines[2].host[..], &lines[2].port[..])
+    );
+    assert_eq!("7070", lines[3].port);
+    assert_eq!(
+      ("i", "plain text"),
+      (&lines[4].r#type[..], &lines[4].description[..])
+    );
+    assert_eq!(vec![2, 3], response.links);
+  }
+
+  #[test]
+  fn should_serialize_menus_back() {
+    let response = GopherMenuResponse::from(
+      "Title\n\
+       1Sub\t/sub\tlocalhost  \n\
+       0Doc\t/doc\tlocalhost\t7070\t+\n\
+       broken\tline\n\
+       plain text\n",
+    );
+    let wire = response.to_wire();
+    assert_eq!(
+      "iTitle\t\t\t70\r\n\
+       1Sub\t/sub\tlocalhost\t70\r\n\
+       0Doc\t/doc\tlocalhost\t7070\t+\r\n\
+       iplain text\t\t\t70\r\n\
+       .\r\n",
+      wire
+    );
+
+    // The serialized menu follows RFC 1436 and gives the same items
+    let parsed = GopherMenuResponse::parse(&wire, MenuMode::Strict);
+    assert!(parsed.lines.iter().all(|line| line.is_ok()));
+    assert_eq!(wire, parsed.to_wire());
+    let items = |menu: GopherMenuResponse| -> Vec<GopherMenuLine> {
+      menu.lines.into_iter().flatten().collect()
+    };
+    assert_eq!(items(response), items(parsed));
+  }
+
+  #[test]
+  fn should_number_the_same_links_when_built_from_items() {
+    let parsed = GopherMenuResponse::from(
+      "iFiles\t\terror.host\t1\r\n\
+       hWeb\tURL:https://example.org\tlocalhost\t70\r\n\
+       9Archive\t/archive.tgz\tlocalhost\t70\r\n\
+       3Gone\t\terror.host\t1\r\n",
+    );
+    let built = GopherMenuResponse::from_items(parsed.lines.iter().flatten().cloned().collect());
+    assert_eq!(vec![1, 2], built.links);
+    let mut config = Config::new();
+    config.colours = theme::ColourMode::Never;
+    assert_eq!(
+      GopherResponse::Menu(parsed).render(&config, "localhost"),
+      GopherResponse::Menu(built).render(&config, "localhost")
+    );
+  }
+
+  #[test]
+  fn should_report_deviations_in_strict_mode() {
+    let response = GopherMenuResponse::parse(
+      "iTitle\t\terror.host\t1\r\n\
+       \r\n\
+       1Sub\t/sub\tlocalhost\t70 \r\n\
+       .\r\n\
+       0Doc\t/doc\tlocalhost\tgopher\r\n\
+       1Sub\t/sub\tlocalhost\t70\n",
+      MenuMode::Strict,
+    );
+    let errors: Vec<String> = response
+      .lines
+      .into_iter()
+      .filter_map(|line| line.err())
+      .collect();
+    assert_eq!(
+      vec![
+        "Empty line".to_string(),
+        "Trailing whitespace in: \"1Sub\t/sub\tlocalhost\t70 \"".to_string(),
+        "\".\" line before the end of the menu".to_string(),
+        "Invalid port in: \"0Doc\t/doc\tlocalhost\tgopher\"".to_string(),
+        "Missing CR before LF in: \"1Sub\t/sub\tlocalhost\t70\"".to_string(),
+        "Missing \".\" line at the end of the menu".to_string(),
+      ],
+      errors
+    );
+  }
+
+  #[test]
+  fn should_detect_error_items() {
+    assert_eq!(
+      Some("'/missing' does not exist (no handler found)".to_string()),
+      parse_error_item(
+        b"3'/missing' does not exist (no handler found)\t\terror.host\t1\r\n.\r\n",
+        "0"
+      )
+    );
+    assert_eq!(
+      Some("Not found".to_string()),
+      parse_error_item(b"3Not found\t\r\n", "1")
+    );
+    assert_eq!(None, parse_error_item(b"3 reasons to use gopher\r\n", "1"));
+    assert_eq!(
+      None,
+      parse_error_item(
+        b"iWelcome\t\terror.host\t1\r\n3Oops\t\terror.host\t1\r\n",
+        "1"
+      )
+    );
+    // Documents which only look like error items
+    assert_eq!(None, parse_error_item(b"3\tapples\r\n5\tpears\r\n", "0"));
+    assert_eq!(None, parse_error_item(b"3\tapples\tgreen\tround\r\n", "0"));
+    assert_eq!(None, parse_error_item(b"3Not found\t\r\n", "9"));
+  }
+
   #[test]
   fn should_return_right_link() {
     let response = "\
@@ -1044,39 +2352,170 @@ mod tests_gopher_menu_response {
       parsed_response.get_link_url("20")
     );
   }
+
+  #[test]
+  fn should_only_open_text_and_menus() {
+    let response = "\
+                    iFiles		error.host	1\r\n\
+                    9Archive	/archive.tar.gz	khzae.net	70\r\n\
+                    0Notes	/notes.txt	khzae.net	70\r\n\
+                    .";
+    let parsed_response = GopherResponse::Menu(GopherMenuResponse::from(response));
+    assert_eq!(
+      Err("Link 1 is not a text or a menu, use \"save 1\" to download it".to_string()),
+      parsed_response.get_link_url("1")
+    );
+    assert_eq!(
+      Ok("gopher://khzae.net:70/9/archive.tar.gz".to_string()),
+      parsed_response.get_item_url("1")
+    );
+    assert_eq!(
+      Ok("gopher://khzae.net:70/0/notes.txt".to_string()),
+      parsed_response.get_link_url("2")
+    );
+    assert_eq!(
+      vec!["gopher://khzae.net:70/0/notes.txt".to_string()],
+      parsed_response.get_links()
+    );
+  }
+  #[test]
+  fn should_render_aligned_columns() {
+    let response = "\
+                    iWelcome		error.host	1\r\n\
+                    1About	/about	khzae.net	70\r\n\
+                    1Super Dimension Fortress (SDF)	/	sdf.org	70\r\n\
+                    0RFC 4266 (gopher URI scheme)	/rfc4266.txt	khzae.net	70\r\n\
+                    7Search dictionary	/dict/search	khzae.net	70\r\n\
+                    .";
+    let parsed_response = GopherResponse::Menu(GopherMenuResponse::from(response));
+    let mut config = Config::new();
+    config.colours = theme::ColourMode::Never;
+    assert_eq!(
+      vec![
+        "             Welcome".to_string(),
+        "MENU    [1]  About/".to_string(),
+        "MENU    [2]  Super Dimension Fortress (SDF)/ \u{2197} sdf.org".to_string(),
+        "TXT     [3]  RFC 4266 (gopher URI scheme)".to_string(),
+        "SEARCH  [4]  Search dictionary".to_string(),
+      ],
+      parsed_response.render(&config, "khzae.net")
+    );
+
+    config.colours = theme::ColourMode::Always;
+    assert_eq!(
+      "\u{1b}[32mTXT   \u{1b}[0m  \u{1b}[33m[3]\u{1b}[0m  \u{1b}[32mRFC 4266 (gopher URI scheme)\u{1b}[0m"
+        .to_string(),
+      parsed_response.render(&config, "khzae.net")[3]
+    );
+  }
+}
+
+#[cfg(test)]
+mod tests_gopher_text_response {
+  use super::*;
+
+  #[test]
+  fn should_find_urls() {
+    assert_eq!(
+      vec!["gopher://khzae.net/0/rfc1436.txt", "gopher://sdf.org"],
+      find_urls("See gopher://khzae.net/0/rfc1436.txt, (or gopher://sdf.org).")
+    );
+    assert!(find_urls("No link, only gopher:// and http://example.com").is_empty());
+  }
+
+  #[test]
+  fn should_keep_link_numbers_when_wrapping() {
+    let response = "\
+                    Some links to read: gopher://khzae.net/0/rfc1436.txt and\r\n\
+                    gopher://sdf.org which is a nice place.\r\n\
+                    .\r\n";
+    let parsed_response = GopherResponse::Text(GopherTextResponse::from(response));
+    let mut config = Config::new();
+    config.colours = theme::ColourMode::Never;
+    config.wrap = WrapMode::Column(30);
+    assert_eq!(
+      vec![
+        "Some links to read:".to_string(),
+        "gopher://khzae.net/0/rfc1436.txt\u{a0}[1]".to_string(),
+        "and gopher://sdf.org\u{a0}[2] which".to_string(),
+        "is a nice place.".to_string(),
+      ],
+      parsed_response.render(&config, "")
+    );
+    assert_eq!(
+      Ok("gopher://sdf.org".to_string()),
+      parsed_response.get_link_url("2")
+    );
+    assert_eq!(
+      Err("Given index is out of bounds".to_string()),
+      parsed_response.get_link_url("3")
+    );
+
+    // Numbers are given to the links found when parsing, whatever colours
+    let coloured = GopherResponse::Text(GopherTextResponse::from(
+      "\x1b[1mgopher://a.example/0/x\x1b[31my\x1b[0m and gopher://b.example\r\n",
+    ));
+    config.sanitize = SanitizeMode::Colours;
+    assert_eq!(
+      vec![
+        "gopher://a.example/0/xy\u{a0}[1]".to_string(),
+        "and gopher://b.example\u{a0}[2]".to_string(),
+        "".to_string(),
+      ],
+      coloured.render(&config, "")
+    );
+    assert_eq!(
+      Ok("gopher://b.example".to_string()),
+      coloured.get_link_url("2")
+    );
+
+    // Only glued when wrapping
+    config.sanitize = SanitizeMode::Strict;
+    config.wrap = WrapMode::Off;
+    assert_eq!(
+      "gopher://sdf.org [2] which is a nice place.".to_string(),
+      parsed_response.render(&config, "")[1]
+    );
+  }
 }
 
 #[cfg(test)]
 mod tests_state {
   use super::*;
 
+  #[test]
+  fn should_keep_unparsed_bookmarks() {
+    let mut state = ClientState::new();
+    state.parse_bookmarks("gopher://khzae.net\ngopher://[::1\n\ngopher://sdf.org/1/users\n");
+    assert_eq!(2, state.bookmarks.len());
+    state.bookmarks.remove(0);
+    assert_eq!(
+      "gopher://sdf.org:70/1/users\ngopher://[::1\n",
+      state.format_bookmarks()
+    );
+  }
+
   #[test]
   fn should_prepare_going_back() {
     // Set initial state
-    let current_page = GopherURL::from("gopher://khzae.net");
-    let mut state = ClientState {
-      bookmarks: Vec::new(),
-      history: Vec::new(),
-      last_response: GopherResponse::Text(GopherTextResponse::new()),
-    };
+    let current_page = GopherURL::from("gopher://khzae.net").unwrap();
+    let mut state = ClientState::new();
     state.history.insert(0, current_page);
     state.history.insert(
       1,
-      GopherURL::from("gopher://zaibatsu.circumlunar.space/1/~solderpunk"),
+      GopherURL::from("gopher://zaibatsu.circumlunar.space/1/~solderpunk").unwrap(),
+    );
+    state.history.insert(
+      2,
+      GopherURL::from("gopher://zaibatsu.circumlunar.space").unwrap(),
     );
-    state
-      .history
-      .insert(2, GopherURL::from("gopher://zaibatsu.circumlunar.space"));
 
     // Set expected state
-    let expected_last_page_history = GopherURL::from("gopher://zaibatsu.circumlunar.space");
+    let expected_last_page_history =
+      GopherURL::from("gopher://zaibatsu.circumlunar.space").unwrap();
     let expected_previous_url =
-      GopherURL::from("gopher://zaibatsu.circumlunar.space/1/~solderpunk");
-    let mut expected_state = ClientState {
-      bookmarks: Vec::new(),
-      history: Vec::new(),
-      last_response: GopherResponse::Text(GopherTextResponse::new()),
-    };
+      GopherURL::from("gopher://zaibatsu.circumlunar.space/1/~solderpunk").unwrap();
+    let mut expected_state = ClientState::new();
     expected_state.history.push(expected_last_page_history);
 
     // Get back url
@@ -1112,6 +2551,10 @@ mod tests_commands {
       Ok(Commands::RemoveBookmark("2".to_string())),
       Commands::parse("bk rm 2".to_string())
     );
+    assert_eq!(
+      Ok(Commands::ExportBookmarks("bookmarks.gph".to_string())),
+      Commands::parse("bk export bookmarks.gph".to_string())
+    );
     assert_eq!(
       Ok(Commands::GoBookmarkIndex("2".to_string())),
       Commands::parse("bk 2".to_string())
@@ -1120,6 +2563,54 @@ mod tests_commands {
       Ok(Commands::GoIndex("2".to_string())),
       Commands::parse("2".to_string())
     );
+    assert_eq!(
+      Ok(Commands::Save("".to_string())),
+      Commands::parse("save".to_string())
+    );
+    assert_eq!(
+      Ok(Commands::Save("page.txt".to_string())),
+      Commands::parse("save page.txt".to_string())
+    );
+    assert_eq!(
+      Ok(Commands::SaveRendered("page.txt".to_string())),
+      Commands::parse("save --rendered page.txt".to_string())
+    );
+    assert_eq!(
+      Ok(Commands::SaveLink("3".to_string())),
+      Commands::parse("save 3".to_string())
+    );
+    assert_eq!(
+      Ok(Commands::Find("/^RFC/".to_string())),
+      Commands::parse("find /^RFC/".to_string())
+    );
+    assert_eq!(
+      Ok(Commands::Filter("phlog".to_string())),
+      Commands::parse("filter phlog".to_string())
+    );
+    assert_eq!(
+      Ok(Commands::DisplayTabs),
+      Commands::parse("tabs".to_string())
+    );
+    assert_eq!(
+      Ok(Commands::NewTab("".to_string())),
+      Commands::parse("tab new".to_string())
+    );
+    assert_eq!(
+      Ok(Commands::NewTab("gopherpedia.com".to_string())),
+      Commands::parse("tab new gopherpedia.com".to_string())
+    );
+    assert_eq!(
+      Ok(Commands::GoTab("2".to_string())),
+      Commands::parse("tab 2".to_string())
+    );
+    assert_eq!(
+      Ok(Commands::CloseTab),
+      Commands::parse("tab close".to_string())
+    );
+    assert_eq!(
+      Ok(Commands::RenameTab("work".to_string())),
+      Commands::parse("tab name work".to_string())
+    );
     assert_eq!(Ok(Commands::Help), Commands::parse("help".to_string()));
   }
 
@@ -1161,9 +2652,57 @@ mod tests_commands {
       Ok(Commands::Help),
       Commands::parse("fly me to the moon".to_string())
     );
+    assert_eq!(
+      Err("Unknown command \"fly\"".to_string()),
+      Commands::parse_strict("fly me to the moon".to_string())
+    );
+    assert_eq!(
+      Ok(Commands::Help),
+      Commands::parse_strict("help".to_string())
+    );
     assert_eq!(
       Err("Bookmark subcommand not found".to_string()),
       Commands::parse("bk something".to_string())
     );
+    assert_eq!(
+      Err("Nothing to find".to_string()),
+      Commands::parse("find".to_string())
+    );
+    assert_eq!(
+      Err("No session name given".to_string()),
+      Commands::parse("session save".to_string())
+    );
+  }
+}
+
+#[cfg(test)]
+mod tests_options {
+  use super::*;
+
+  fn parse(args: &str) -> Result<Options, String> {
+    let args: Vec<String> = args.split_whitespace().map(String::from).collect();
+    Options::from(&args)
+  }
+
+  #[test]
+  fn should_only_accept_options_of_the_mode() {
+    let options = parse("marmotte serve-http --listen 127.0.0.1:8080 --allow-remote").unwrap();
+    assert_eq!(
+      (true, Some("127.0.0.1:8080".to_string()), true),
+      (options.serve_http, options.listen, options.allow_remote)
+    );
+    assert_eq!(
+      Some("7071".to_string()),
+      parse("marmotte serve hole --port 7071").unwrap().port
+    );
+    assert_eq!(
+      Err("--listen and --allow-remote are only options of serve-http".to_string()),
+      parse("marmotte --listen 0.0.0.0:8070 sdf.org").map(|_| ())
+    );
+    assert!(parse("marmotte serve --allow-remote").is_err());
+    assert_eq!(
+      Err("--port is only an option of serve".to_string()),
+      parse("marmotte serve-http --port 8080").map(|_| ())
+    );
   }
 }
@@ -0,0 +1,550 @@
+// Copyright © Killian Kemps (2019)
+//
+// Killian Kemps <developer@killiankemps.fr>
+//
+// This software is a computer program whose purpose is to communicate with
+// the Gopher protocol.
+//
+// This software is governed by the CeCILL license under French law and
+// abiding by the rules of distribution of free software.  You can  use,
+// modify and/ or redistribute the software under the terms of the CeCILL
+// license as circulated by CEA, CNRS and INRIA at the following URL
+// "http://www.cecill.info".
+//
+// As a counterpart to the access to the source code and  rights to copy,
+// modify and redistribute granted by the license, users are provided only
+// with a limited warranty  and the software's author,  the holder of the
+// economic rights,  and the successive licensors  have only  limited
+// liability.
+//
+// In this respect, the user's attention is drawn to the risks associated
+// with loading,  using,  modifying and/or developing or reproducing the
+// software by the user in light of its specific status of free software,
+// that may mean  that it is complicated to manipulate,  and  that  also
+// therefore means  that it is reserved for developers  and  experienced
+// professionals having in-depth computer knowledge. Users are therefore
+// encouraged to load and test the software's suitability as regards their
+// requirements in conditions enabling the security of their systems and/or
+// data to be ensured and,  more generally, to use and operate it in the
+// same conditions as regards security.
+//
+// The fact that you are presently reading this means that you have had
+// knowledge of the CeCILL license and that you accept its terms.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::{fetch, GopherMenuResponse, GopherURL, SOFTWARE_NAME};
+
+// Progress of a mirror, one line per requested URL, used to resume it
+const MANIFEST_NAME: &str = "manifest.txt";
+// Menus are stored like gopher servers expect them
+const MENU_FILENAME: &str = "gophermap";
+
+#[derive(Debug, PartialEq)]
+pub struct MirrorOptions {
+  // Number of menus to walk from the start page
+  pub depth: usize,
+  // Only follow links to the host of the start page
+  pub same_host: bool,
+  // Only follow links whose selector starts with this prefix
+  pub prefix: Option<String>,
+  // Time to wait between two requests
+  pub delay: Duration,
+}
+
+impl MirrorOptions {
+  fn new() -> MirrorOptions {
+    MirrorOptions {
+      depth: 3,
+      same_host: true,
+      prefix: None,
+      delay: Duration::from_millis(1000),
+    }
+  }
+}
+
+// Parse "<url> <directory> [options]" as given to the mirror command
+pub fn parse_args(args: &str) -> Result<(GopherURL, PathBuf, MirrorOptions), String> {
+  let usage =
+    "Usage: mirror [url] [directory] [--depth n] [--all-hosts] [--prefix selector] [--delay ms]";
+  let mut args = args.split_whitespace();
+  let (url, directory) = match (args.next(), args.next()) {
+    (Some(url), Some(directory)) => (GopherURL::from(url)?, PathBuf::from(directory)),
+    _ => return Err(usage.to_string()),
+  };
+
+  let mut options = MirrorOptions::new();
+  while let Some(arg) = args.next() {
+    match arg {
+      "--all-hosts" => options.same_host = false,
+      "--depth" | "--delay" | "--prefix" => {
+        let value = match args.next() {
+          Some(value) => value,
+          None => return Err(format!("No value given to {}", arg)),
+        };
+        match arg {
+          "--prefix" => options.prefix = Some(value.to_string()),
+          _ => match value.parse::<u64>() {
+            Ok(number) if arg == "--depth" => options.depth = number as usize,
+            Ok(number) => options.delay = Duration::from_millis(number),
+            Err(error) => return Err(format!("Could not parse {}: {:?}", arg, error)),
+          },
+        }
+      }
+      _ => return Err(format!("Unknown option {}\n{}", arg, usage)),
+    }
+  }
+  Ok((url, directory, options))
+}
+
+// Disallowed selectors of a robots.txt file for marmotte
+#[derive(Debug, PartialEq)]
+struct Robots {
+  disallowed: Vec<String>,
+}
+
+impl Robots {
+  fn from(text: &str) -> Robots {
+    let mut disallowed = Vec::new();
+    // Whether the rules being read apply to us, and whether the user agent
+    // lines of the current group are over
+    let mut applies = false;
+    let mut in_rules = false;
+
+    for line in text.lines() {
+      let line = line.split('#').next().unwrap_or("").trim();
+      let (field, value) = match line.find(':') {
+        Some(idx) => (line[..idx].trim().to_lowercase(), line[idx + 1..].trim()),
+        None => continue,
+      };
+      match &field[..] {
+        "user-agent" => {
+          if in_rules {
+            applies = false;
+            in_rules = false;
+          }
+          let agent = value.to_lowercase();
+          applies = applies || agent == "*" || agent.contains(SOFTWARE_NAME);
+        }
+        "disallow" => {
+          in_rules = true;
+          if applies && !value.is_empty() {
+            disallowed.push(with_leading_slash(value));
+          }
+        }
+        _ => in_rules = true,
+      }
+    }
+    Robots { disallowed }
+  }
+
+  fn allows(&self, selector: &str) -> bool {
+    let selector = with_leading_slash(selector);
+    !self
+      .disallowed
+      .iter()
+      .any(|prefix| selector.starts_with(prefix))
+  }
+}
+
+// Selectors are compared with and without leading slash as servers often
+// accept both
+fn with_leading_slash(selector: &str) -> String {
+  if selector.starts_with('/') {
+    selector.to_string()
+  } else {
+    format!("/{}", selector)
+  }
+}
+
+// Path of a page in the mirror: <host>_<port>/<selector>, menus being
+// stored in a gophermap file of their directory
+fn mirror_path(url: &GopherURL) -> Result<PathBuf, String> {
+  // Hosts come from menus sent by servers
+  if ["", ".", ".."].contains(&url.host.trim()) {
+    return Err(format!("Invalid host \"{}\"", url.host));
+  }
+  let mut path = PathBuf::from(format!("{}_{}", sanitize_segment(&url.host), url.port));
+  for segment in url.selector.split('/') {
+    if !["", ".", ".."].contains(&segment) {
+      path.push(sanitize_segment(segment));
+    }
+  }
+  if url.r#type == "1" || path.components().count() == 1 {
+    path.push(MENU_FILENAME);
+  }
+  Ok(path)
+}
+
+fn sanitize_segment(segment: &str) -> String {
+  segment
+    .chars()
+    .map(|c| {
+      if c.is_control() || c == '/' || c == '\\' || c == ':' {
+        '_'
+      } else {
+        c
+      }
+    })
+    .collect()
+}
+
+// Pages may need the same name for a file and a directory, like a document
+// "/a" and a menu "/a/b", or the same file, like a document and a menu "/a".
+// Names already taken by the other kind or by another page get a "+n"
+// suffix, the first free one being kept.
+fn free_path(directory: &Path, path: &Path, claimed: &HashSet<PathBuf>) -> PathBuf {
+  let components: Vec<&std::ffi::OsStr> = path.iter().collect();
+  let mut free = PathBuf::new();
+  for (index, component) in components.iter().enumerate() {
+    let is_file = index == components.len() - 1;
+    let mut suffix = 0;
+    loop {
+      let candidate = match suffix {
+        0 => free.join(component),
+        _ => free.join(format!("{}+{}", component.to_string_lossy(), suffix)),
+      };
+      let target = directory.join(&candidate);
+      let taken = claimed.contains(&candidate)
+        || if is_file {
+          target.is_dir()
+        } else {
+          target.is_file()
+        };
+      if !taken {
+        free = candidate;
+        break;
+      }
+      suffix += 1;
+    }
+  }
+  free
+}
+
+// Items which can be downloaded, others are information lines, searches or
+// pointers to other protocols
+fn is_downloadable(url: &GopherURL) -> bool {
+  match &url.r#type[..] {
+    "i" | "2" | "3" | "7" | "8" | "+" | "T" => false,
+    "h" => !url.selector.starts_with("URL:"),
+    _ => true,
+  }
+}
+
+// URLs already requested in a previous run, with the path of their file
+fn read_manifest(path: &Path) -> HashMap<String, PathBuf> {
+  let mut done = HashMap::new();
+  if let Ok(contents) = fs::read_to_string(path) {
+    for line in contents.lines() {
+      let fields: Vec<&str> = line.split('\t').collect();
+      if let ["ok", url, file] = fields[..] {
+        done.insert(url.to_string(), PathBuf::from(file));
+      }
+    }
+  }
+  done
+}
+
+struct Mirror<'a> {
+  directory: &'a Path,
+  options: &'a MirrorOptions,
+  config: &'a Config,
+  start: &'a GopherURL,
+  robots: HashMap<String, Robots>,
+  manifest: fs::File,
+  // Whether a request was already sent, to wait before the next one
+  requested: bool,
+}
+
+impl<'a> Mirror<'a> {
+  fn wait(&mut self) {
+    if self.requested {
+      thread::sleep(self.options.delay);
+    }
+    self.requested = true;
+  }
+
+  fn is_allowed(&mut self, url: &GopherURL) -> bool {
+    if self.options.same_host
+      && (!url.host.eq_ignore_ascii_case(&self.start.host) || url.port != self.start.port)
+    {
+      return false;
+    }
+    if let Some(prefix) = &self.options.prefix {
+      if !with_leading_slash(&url.selector).starts_with(&with_leading_slash(prefix)) {
+        return false;
+      }
+    }
+
+    let server = url.get_server();
+    if !self.robots.contains_key(&server) {
+      let mut robots_url = GopherURL::new();
+      robots_url.host = url.host.clone();
+      robots_url.port = url.port;
+      robots_url.r#type = "0".to_string();
+      robots_url.selector = "/robots.txt".to_string();
+      self.wait();
+      // Servers without robots.txt usually answer with an error menu line
+      let robots = match fetch(&robots_url, self.config) {
+        Ok(raw) if !raw.starts_with(b"3") => Robots::from(&String::from_utf8_lossy(&raw)),
+        _ => Robots::from(""),
+      };
+      self.robots.insert(server.clone(), robots);
+    }
+    self.robots[&server].allows(&url.selector)
+  }
+
+  fn record(&mut self, line: &str) -> Result<(), String> {
+    match writeln!(self.manifest, "{}", line) {
+      Ok(_) => Ok(()),
+      Err(e) => Err(format!("Problem writing manifest: {}", e)),
+    }
+  }
+}
+
+// Copy the pages reachable from the start URL into the directory. Pages
+// listed in the manifest of a previous run are read from disk instead of
+// being requested again, so that interrupted mirrors can be resumed.
+pub fn mirror(
+  start: &GopherURL,
+  directory: &Path,
+  options: &MirrorOptions,
+  config: &Config,
+) -> Result<String, String> {
+  if let Err(e) = fs::create_dir_all(directory) {
+    return Err(format!("Problem creating {}: {}", directory.display(), e));
+  }
+  let manifest_path = directory.join(MANIFEST_NAME);
+  let done = read_manifest(&manifest_path);
+  let manifest = match OpenOptions::new()
+    .create(true)
+    .append(true)
+    .open(&manifest_path)
+  {
+    Ok(file) => file,
+    Err(e) => {
+      return Err(format!(
+        "Problem opening {}: {}",
+        manifest_path.display(),
+        e
+      ))
+    }
+  };
+  let mut mirror = Mirror {
+    directory,
+    options,
+    config,
+    start,
+    robots: HashMap::new(),
+    manifest,
+    requested: false,
+  };
+
+  let (mut saved, mut skipped, mut failed) = (0, 0, 0);
+  // Files of pages, which other pages can't use
+  let mut claimed: HashSet<PathBuf> = done.values().cloned().collect();
+  let mut seen = HashSet::new();
+  let mut queue = VecDeque::new();
+  queue.push_back((start.clone(), 0));
+
+  while let Some((url, depth)) = queue.pop_front() {
+    let full_url = match url.get_url() {
+      Some(full_url) => full_url,
+      None => continue,
+    };
+    if !seen.insert(full_url.clone()) {
+      continue;
+    }
+
+    let path = match mirror_path(&url) {
+      Ok(path) => path,
+      Err(msg) => {
+        failed += 1;
+        println!("{}: {}", full_url, msg);
+        mirror.record(&format!("failed\t{}\t{}", full_url, msg))?;
+        continue;
+      }
+    };
+    let raw = if let Some(previous_path) = done.get(&full_url) {
+      skipped += 1;
+      fs::read(directory.join(previous_path)).unwrap_or_default()
+    } else {
+      if !mirror.is_allowed(&url) {
+        continue;
+      }
+      mirror.wait();
+      let path = free_path(directory, &path, &claimed);
+      let result =
+        fetch(&url, config).and_then(|raw| store(&mirror.directory.join(&path), &raw).map(|_| raw));
+      match result {
+        Ok(raw) => {
+          saved += 1;
+          claimed.insert(path.clone());
+          println!("{} -> {}", full_url, path.display());
+          mirror.record(&format!("ok\t{}\t{}", full_url, path.display()))?;
+          raw
+        }
+        Err(msg) => {
+          failed += 1;
+          println!("{}: {}", full_url, msg);
+          mirror.record(&format!("failed\t{}\t{}", full_url, msg))?;
+          continue;
+        }
+      }
+    };
+
+    if url.r#type != "1" || depth >= options.depth {
+      continue;
+    }
+    let menu = GopherMenuResponse::from(&String::from_utf8_lossy(&raw));
+    for item in menu.lines.iter().flatten() {
+      if let Some(link) = item.to_url().filter(is_downloadable) {
+        queue.push_back((link, depth + 1));
+      }
+    }
+  }
+
+  Ok(format!(
+    "Mirrored {} pages to {} ({} from a previous run, {} failed)",
+    saved + skipped,
+    directory.display(),
+    skipped,
+    failed
+  ))
+}
+
+fn store(path: &Path, contents: &[u8]) -> Result<(), String> {
+  if let Some(parent) = path.parent() {
+    if let Err(e) = fs::create_dir_all(parent) {
+      return Err(format!("Problem creating {}: {}", parent.display(), e));
+    }
+  }
+  match fs::write(path, contents) {
+    Ok(_) => Ok(()),
+    Err(e) => Err(format!("Problem writing {}: {}", path.display(), e)),
+  }
+}
+
+#[cfg(test)]
+mod tests_mirror {
+  use super::*;
+
+  #[test]
+  fn should_parse_options() {
+    let (url, directory, options) =
+      parse_args("gopher://sdf.org/1/users mirror --depth 2 --all-hosts --prefix /users --delay 0")
+        .unwrap();
+    assert_eq!("/users", url.selector);
+    assert_eq!(PathBuf::from("mirror"), directory);
+    assert_eq!(
+      MirrorOptions {
+        depth: 2,
+        same_host: false,
+        prefix: Some("/users".to_string()),
+        delay: Duration::from_millis(0),
+      },
+      options
+    );
+    assert!(parse_args("sdf.org").is_err());
+    assert_eq!(
+      Err("No value given to --depth".to_string()),
+      parse_args("sdf.org mirror --depth").map(|_| ())
+    );
+  }
+
+  #[test]
+  fn should_follow_robots_rules() {
+    let robots = Robots::from(
+      "User-agent: crawler\n\
+       Disallow: /\n\
+       \n\
+       User-agent: marmotte\n\
+       User-agent: *\n\
+       Disallow: /private # Not for bots\n\
+       Disallow: cgi-bin\n\
+       Disallow:\n",
+    );
+    assert_eq!(
+      vec!["/private".to_string(), "/cgi-bin".to_string()],
+      robots.disallowed
+    );
+    assert!(robots.allows("/phlog"));
+    assert!(robots.allows(""));
+    assert!(!robots.allows("/private/notes.txt"));
+    assert!(!robots.allows("cgi-bin/search"));
+    assert!(Robots::from("").allows("/private"));
+  }
+
+  #[test]
+  fn should_store_pages_like_a_server() {
+    assert_eq!(
+      Ok(PathBuf::from("sdf.org_70/gophermap")),
+      mirror_path(&GopherURL::from("sdf.org").unwrap())
+    );
+    assert_eq!(
+      Ok(PathBuf::from("sdf.org_70/users/gophermap")),
+      mirror_path(&GopherURL::from("sdf.org/1/users/").unwrap())
+    );
+    assert_eq!(
+      Ok(PathBuf::from("sdf.org_7070/users/notes.txt")),
+      mirror_path(&GopherURL::from("sdf.org:7070/0/../users/./notes.txt").unwrap())
+    );
+
+    // Hosts of menu items are not trusted
+    let mut url = GopherURL::from("sdf.org/0/notes.txt").unwrap();
+    url.host = "/etc/cron.d/x".to_string();
+    assert_eq!(
+      Ok(PathBuf::from("_etc_cron.d_x_70/notes.txt")),
+      mirror_path(&url)
+    );
+    url.host = "..".to_string();
+    assert_eq!(Err("Invalid host \"..\"".to_string()), mirror_path(&url));
+  }
+
+  #[test]
+  fn should_give_clashing_pages_distinct_paths() {
+    let directory = std::env::temp_dir().join(format!("marmotte-clash-{}", std::process::id()));
+    let mut claimed = HashSet::new();
+    let mut save = |selector: &str| {
+      let url = GopherURL::from(&format!("sdf.org/{}", selector)).unwrap();
+      let path = free_path(&directory, &mirror_path(&url).unwrap(), &claimed);
+      store(&directory.join(&path), b"").unwrap();
+      claimed.insert(path.clone());
+      path
+    };
+    assert_eq!(PathBuf::from("sdf.org_70/a"), save("0/a"));
+    assert_eq!(PathBuf::from("sdf.org_70/a+1/gophermap"), save("1/a"));
+    assert_eq!(PathBuf::from("sdf.org_70/a+1/b"), save("0/a/b"));
+    assert_eq!(PathBuf::from("sdf.org_70/a+2"), save("9/a"));
+    assert_eq!(PathBuf::from("sdf.org_70/c/d"), save("0/c/d"));
+    assert_eq!(PathBuf::from("sdf.org_70/c+1"), save("0/c"));
+    fs::remove_dir_all(&directory).unwrap();
+  }
+
+  #[test]
+  fn should_resume_from_manifest() {
+    let directory = std::env::temp_dir().join(format!("marmotte-mirror-{}", std::process::id()));
+    fs::create_dir_all(&directory).unwrap();
+    let manifest = directory.join(MANIFEST_NAME);
+    fs::write(
+      &manifest,
+      "ok\tgopher://sdf.org:70/1\tsdf.org_70/gophermap\n\
+       failed\tgopher://sdf.org:70/0/gone\tFailed to connect\n",
+    )
+    .unwrap();
+    let done = read_manifest(&manifest);
+    fs::remove_dir_all(&directory).unwrap();
+
+    assert_eq!(1, done.len());
+    assert_eq!(
+      Some(&PathBuf::from("sdf.org_70/gophermap")),
+      done.get("gopher://sdf.org:70/1")
+    );
+  }
+}
@@ -0,0 +1,274 @@
+// Copyright © Killian Kemps (2019)
+//
+// Killian Kemps <developer@killiankemps.fr>
+//
+// This software is a computer program whose purpose is to communicate with
+// the Gopher protocol.
+//
+// This software is governed by the CeCILL license under French law and
+// abiding by the rules of distribution of free software.  You can  use,
+// modify and/ or redistribute the software under the terms of the CeCILL
+// license as circulated by CEA, CNRS and INRIA at the following URL
+// "http://www.cecill.info".
+//
+// As a counterpart to the access to the source code and  rights to copy,
+// modify and redistribute granted by the license, users are provided only
+// with a limited warranty  and the software's author,  the holder of the
+// economic rights,  and the successive licensors  have only  limited
+// liability.
+//
+// In this respect, the user's attention is drawn to the risks associated
+// with loading,  using,  modifying and/or developing or reproducing the
+// software by the user in light of its specific status of free software,
+// that may mean  that it is complicated to manipulate,  and  that  also
+// therefore means  that it is reserved for developers  and  experienced
+// professionals having in-depth computer knowledge. Users are therefore
+// encouraged to load and test the software's suitability as regards their
+// requirements in conditions enabling the security of their systems and/or
+// data to be ensured and,  more generally, to use and operate it in the
+// same conditions as regards security.
+//
+// The fact that you are presently reading this means that you have had
+// knowledge of the CeCILL license and that you accept its terms.
+
+use std::iter::Peekable;
+use std::str::Chars;
+
+use crate::config::SanitizeMode;
+
+const ESC: char = '\u{1b}';
+const BEL: char = '\u{07}';
+// 8-bit equivalents of ESC [, ESC \, ESC ], ESC P, ESC X, ESC ^ and ESC _
+const CSI: char = '\u{9b}';
+const ST: char = '\u{9c}';
+const STRING_INTRODUCERS: [char; 5] = ['\u{9d}', '\u{90}', '\u{98}', '\u{9e}', '\u{9f}'];
+
+// Remove control characters and terminal escape sequences from content sent
+// by a server so that it can't retitle the window, move the cursor or spoof
+// lines printed by marmotte. Tabs are kept as they are part of the layout.
+// In colours mode, SGR sequences only changing text attributes and colours
+// are kept, and attributes are reset at the end of the string.
+pub fn sanitize(input: &str, mode: SanitizeMode) -> String {
+  let mut output = String::with_capacity(input.len());
+  let mut chars = input.chars().peekable();
+  let mut coloured = false;
+
+  while let Some(c) = chars.next() {
+    match c {
+      ESC => match chars.peek() {
+        Some('[') => {
+          chars.next();
+          if let Some(sequence) = read_control_sequence(&mut chars) {
+            if mode == SanitizeMode::Colours && is_safe_sgr(&sequence) {
+              output.push(ESC);
+              output.push('[');
+              output.push_str(&sequence);
+              coloured = true;
+            }
+          }
+        }
+        Some(']') | Some('P') | Some('X') | Some('^') | Some('_') => {
+          chars.next();
+          skip_control_string(&mut chars);
+        }
+        // nF sequences such as character set designations "ESC ( B"
+        Some(' '..='/') => {
+          for intermediate in chars.by_ref() {
+            if !matches!(intermediate, ' '..='/') {
+              break;
+            }
+          }
+        }
+        // Two characters sequences such as "ESC c" which resets the terminal
+        Some('0'..='~') => {
+          chars.next();
+        }
+        _ => {}
+      },
+      CSI => {
+        if let Some(sequence) = read_control_sequence(&mut chars) {
+          if mode == SanitizeMode::Colours && is_safe_sgr(&sequence) {
+            output.push(ESC);
+            output.push('[');
+            output.push_str(&sequence);
+            coloured = true;
+          }
+        }
+      }
+      c if STRING_INTRODUCERS.contains(&c) => skip_control_string(&mut chars),
+      '\t' => output.push(c),
+      // C0 controls, DEL and C1 controls
+      c if c.is_control() => {}
+      c => output.push(c),
+    }
+  }
+
+  if coloured {
+    output.push_str("\u{1b}[0m");
+  }
+  output
+}
+
+// Read the parameters and final byte of a control sequence following its
+// introducer. Return None when the sequence is malformed, in which case the
+// offending character is left for the caller to process.
+fn read_control_sequence(chars: &mut Peekable<Chars>) -> Option<String> {
+  let mut sequence = String::new();
+  while let Some(&c) = chars.peek() {
+    match c {
+      // Parameter and intermediate bytes
+      ' '..='?' => {
+        sequence.push(c);
+        chars.next();
+      }
+      // Final byte
+      '@'..='~' => {
+        sequence.push(c);
+        chars.next();
+        return Some(sequence);
+      }
+      _ => return None,
+    }
+  }
+  None
+}
+
+// Skip an OSC, DCS, SOS, PM or APC string up to its terminator. Unterminated
+// strings are skipped until the end of the input.
+fn skip_control_string(chars: &mut Peekable<Chars>) {
+  while let Some(c) = chars.next() {
+    match c {
+      BEL | ST => return,
+      ESC if chars.peek() == Some(&'\\') => {
+        chars.next();
+        return;
+      }
+      _ => {}
+    }
+  }
+}
+
+// Check that a control sequence is an SGR sequence which only sets
+// intensity, italic, underline, inverse or colours. Blinking and concealed
+// text are not considered safe.
+fn is_safe_sgr(sequence: &str) -> bool {
+  let params = match sequence.strip_suffix('m') {
+    Some(params) => params,
+    None => return false,
+  };
+  if !params.chars().all(|c| c.is_ascii_digit() || c == ';') {
+    return false;
+  }
+
+  let mut values = Vec::new();
+  for param in params.split(';') {
+    if param.is_empty() {
+      values.push(0);
+    } else {
+      match param.parse::<u32>() {
+        Ok(value) => values.push(value),
+        Err(_) => return false,
+      }
+    }
+  }
+
+  let mut values = values.into_iter();
+  while let Some(value) = values.next() {
+    match value {
+      0..=4 | 7 | 22..=24 | 27 | 30..=37 | 39..=47 | 49 | 90..=97 | 100..=107 => {}
+      // Extended colours: 38;5;n or 38;2;r;g;b (48 for background)
+      38 | 48 => {
+        let components = match values.next() {
+          Some(5) => 1,
+          Some(2) => 3,
+          _ => return false,
+        };
+        for _ in 0..components {
+          match values.next() {
+            Some(component) if component <= 255 => {}
+            _ => return false,
+          }
+        }
+      }
+      _ => return false,
+    }
+  }
+  true
+}
+
+#[cfg(test)]
+mod tests_sanitize {
+  use super::*;
+
+  #[test]
+  fn should_remove_control_characters() {
+    assert_eq!(
+      "Hello\tworld".to_string(),
+      sanitize("Hel\u{07}lo\t\u{08}world\r", SanitizeMode::Strict)
+    );
+    // C1 controls
+    assert_eq!(
+      "abc".to_string(),
+      sanitize("a\u{85}b\u{8d}c", SanitizeMode::Strict)
+    );
+    // Non-ASCII characters are kept
+    assert_eq!(
+      "Café ☕ ─┐".to_string(),
+      sanitize("Café ☕ ─┐", SanitizeMode::Strict)
+    );
+  }
+
+  #[test]
+  fn should_remove_escape_sequences() {
+    // Window title
+    assert_eq!(
+      "before after".to_string(),
+      sanitize("before \u{1b}]0;pwned\u{07}after", SanitizeMode::Strict)
+    );
+    assert_eq!(
+      "before after".to_string(),
+      sanitize("before \u{1b}]2;pwned\u{1b}\\after", SanitizeMode::Strict)
+    );
+    // Cursor movement and screen clearing
+    assert_eq!(
+      "Current page: gopher://evil".to_string(),
+      sanitize(
+        "\u{1b}[2J\u{1b}[1;1HCurrent page: gopher://evil",
+        SanitizeMode::Strict
+      )
+    );
+    // Terminal reset, character set designation and 8-bit CSI
+    assert_eq!(
+      "text".to_string(),
+      sanitize("\u{1b}c\u{1b}(0te\u{9b}31mxt", SanitizeMode::Strict)
+    );
+    // Colours are removed in strict mode
+    assert_eq!(
+      "red".to_string(),
+      sanitize("\u{1b}[31mred\u{1b}[0m", SanitizeMode::Strict)
+    );
+  }
+
+  #[test]
+  fn should_keep_safe_colours_when_allowed() {
+    assert_eq!(
+      "\u{1b}[1;31mred\u{1b}[0m\u{1b}[0m".to_string(),
+      sanitize("\u{1b}[1;31mred\u{1b}[0m", SanitizeMode::Colours)
+    );
+    assert_eq!(
+      "\u{1b}[38;5;208m\u{1b}[48;2;0;0;0mart\u{1b}[0m".to_string(),
+      sanitize(
+        "\u{1b}[38;5;208m\u{1b}[48;2;0;0;0mart",
+        SanitizeMode::Colours
+      )
+    );
+    // Other sequences and unsafe attributes are still removed
+    assert_eq!(
+      "hidden blink moved".to_string(),
+      sanitize(
+        "\u{1b}[8mhidden \u{1b}[5mblink \u{1b}[10Amoved\u{1b}[38;5;999m",
+        SanitizeMode::Colours
+      )
+    );
+  }
+}
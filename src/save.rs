@@ -0,0 +1,225 @@
+// Copyright © Killian Kemps (2019)
+//
+// Killian Kemps <developer@killiankemps.fr>
+//
+// This software is a computer program whose purpose is to communicate with
+// the Gopher protocol.
+//
+// This software is governed by the CeCILL license under French law and
+// abiding by the rules of distribution of free software.  You can  use,
+// modify and/ or redistribute the software under the terms of the CeCILL
+// license as circulated by CEA, CNRS and INRIA at the following URL
+// "http://www.cecill.info".
+//
+// As a counterpart to the access to the source code and  rights to copy,
+// modify and redistribute granted by the license, users are provided only
+// with a limited warranty  and the software's author,  the holder of the
+// economic rights,  and the successive licensors  have only  limited
+// liability.
+//
+// In this respect, the user's attention is drawn to the risks associated
+// with loading,  using,  modifying and/or developing or reproducing the
+// software by the user in light of its specific status of free software,
+// that may mean  that it is complicated to manipulate,  and  that  also
+// therefore means  that it is reserved for developers  and  experienced
+// professionals having in-depth computer knowledge. Users are therefore
+// encouraged to load and test the software's suitability as regards their
+// requirements in conditions enabling the security of their systems and/or
+// data to be ensured and,  more generally, to use and operate it in the
+// same conditions as regards security.
+//
+// The fact that you are presently reading this means that you have had
+// knowledge of the CeCILL license and that you accept its terms.
+
+use std::fs;
+use std::path::Path;
+
+use crate::theme::ColourMode;
+use crate::{fetch, prompt, ClientState, GopherURL};
+
+impl ClientState {
+  // Save the current page, either as received from the server or as it is
+  // displayed without colours
+  pub fn save_page(&self, path: &str, rendered: bool) -> Result<String, String> {
+    let url = match self.history.first() {
+      Some(url) => url,
+      None => return Err("There is no current document".to_string()),
+    };
+
+    let contents = if rendered {
+      let mut config = self.config.clone();
+      config.colours = ColourMode::Never;
+      let mut lines = self.last_response.render(&config, &url.host).join("\n");
+      lines.push('\n');
+      lines.into_bytes()
+    } else {
+      self.last_raw_response.clone()
+    };
+
+    let path = choose_path(path, url, rendered);
+    write_file(&path, &contents)
+  }
+
+  // Download a link of the current page without displaying it, whatever
+  // the type of item
+  pub fn save_link(&self, index: &str, path: &str) -> Result<String, String> {
+    let url = GopherURL::from(&self.last_response.get_item_url(index)?)?;
+    let path = choose_path(path, &url, false);
+    if Path::new(&path).exists() && !confirm_overwrite(&path) {
+      return Err("Nothing saved".to_string());
+    }
+    // Confirmation was asked before downloading
+    store(&path, &fetch(&url, &self.config)?)
+  }
+}
+
+// File name based on the last part of the selector, or on the host for the
+// server root
+fn suggest_filename(url: &GopherURL, rendered: bool) -> String {
+  let (name, from_host) = match url.selector.trim_end_matches('/').rsplit('/').next() {
+    Some(name) if !name.is_empty() => (name, false),
+    _ => (&url.host[..], true),
+  };
+
+  let mut filename: String = name
+    .chars()
+    .map(|c| {
+      if c.is_alphanumeric() || "._-".contains(c) {
+        c
+      } else {
+        '_'
+      }
+    })
+    .collect();
+  // Avoid hidden files
+  filename = filename.trim_start_matches('.').to_string();
+  if filename.is_empty() {
+    filename = "index".to_string();
+  }
+
+  // Dots of host names don't start an extension
+  if from_host || !filename.contains('.') {
+    if url.r#type == "1" && !rendered {
+      filename.push_str(".gph");
+    } else {
+      filename.push_str(".txt");
+    }
+  }
+  filename
+}
+
+fn choose_path(path: &str, url: &GopherURL, rendered: bool) -> String {
+  if !path.is_empty() {
+    return path.to_string();
+  }
+  let suggested = suggest_filename(url, rendered);
+  let answer = prompt(&format!("Save as [{}]: ", suggested));
+  if answer.is_empty() {
+    suggested
+  } else {
+    answer
+  }
+}
+
+pub fn confirm_overwrite(path: &str) -> bool {
+  let answer = prompt(&format!("{} already exists, overwrite it? [y/N] ", path));
+  ["y", "yes"].contains(&&answer.to_lowercase()[..])
+}
+
+pub fn write_file(path: &str, contents: &[u8]) -> Result<String, String> {
+  if Path::new(path).exists() && !confirm_overwrite(path) {
+    return Err("Nothing saved".to_string());
+  }
+  store(path, contents)
+}
+
+fn store(path: &str, contents: &[u8]) -> Result<String, String> {
+  match fs::write(path, contents) {
+    Ok(_) => Ok(format!("Saved {} bytes to {}", contents.len(), path)),
+    Err(e) => Err(format!("Problem writing {}: {}", path, e)),
+  }
+}
+
+#[cfg(test)]
+mod tests_save {
+  use super::*;
+  use crate::{GopherMenuResponse, GopherResponse};
+  use std::io::{Read, Write};
+  use std::net::TcpListener;
+  use std::thread;
+
+  #[test]
+  fn should_save_binary_items() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let handle = thread::spawn(move || {
+      let (mut stream, _) = listener.accept().unwrap();
+      let mut selector = [0; 64];
+      let read = stream.read(&mut selector).unwrap();
+      stream.write_all(&[0x1f, 0x8b, 0, 0xff]).unwrap();
+      String::from_utf8_lossy(&selector[..read]).to_string()
+    });
+
+    let mut state = ClientState::new();
+    state.last_response = GopherResponse::Menu(GopherMenuResponse::from(&format!(
+      "iFiles\t\terror.host\t1\r\n9Archive\t/archive.tar.gz\t127.0.0.1\t{}\r\n.\r\n",
+      port
+    )));
+    let path = std::env::temp_dir().join(format!("marmotte-save-{}", std::process::id()));
+    let path = path.to_string_lossy().to_string();
+    assert_eq!(
+      Ok(format!("Saved 4 bytes to {}", path)),
+      state.save_link("1", &path)
+    );
+    assert_eq!("/archive.tar.gz\r\n", handle.join().unwrap());
+    assert_eq!(vec![0x1f, 0x8b, 0, 0xff], fs::read(&path).unwrap());
+    fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn should_suggest_filename_from_selector() {
+    assert_eq!(
+      "project-gemini.txt".to_string(),
+      suggest_filename(
+        &GopherURL::from("zaibatsu.circumlunar.space/0/~solderpunk/phlog/project-gemini.txt")
+          .unwrap(),
+        false
+      )
+    );
+    assert_eq!(
+      "phlog.gph".to_string(),
+      suggest_filename(
+        &GopherURL::from("zaibatsu.circumlunar.space/1/~solderpunk/phlog/").unwrap(),
+        false
+      )
+    );
+    assert_eq!(
+      "phlog.txt".to_string(),
+      suggest_filename(
+        &GopherURL::from("zaibatsu.circumlunar.space/1/~solderpunk/phlog/").unwrap(),
+        true
+      )
+    );
+    // Server root
+    assert_eq!(
+      "khzae.net.gph".to_string(),
+      suggest_filename(&GopherURL::from("khzae.net").unwrap(), false)
+    );
+    assert_eq!(
+      "khzae.net.txt".to_string(),
+      suggest_filename(&GopherURL::from("khzae.net").unwrap(), true)
+    );
+    // Unsafe characters
+    assert_eq!(
+      "Alex_Schroeder_Page.txt".to_string(),
+      suggest_filename(
+        &GopherURL::from("alexschroeder.ch/0Alex Schroeder:Page").unwrap(),
+        false
+      )
+    );
+    assert_eq!(
+      "htaccess.txt".to_string(),
+      suggest_filename(&GopherURL::from("example.org/0/.htaccess").unwrap(), false)
+    );
+  }
+}
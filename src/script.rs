@@ -0,0 +1,118 @@
+// Copyright © Killian Kemps (2019)
+//
+// Killian Kemps <developer@killiankemps.fr>
+//
+// This software is a computer program whose purpose is to communicate with
+// the Gopher protocol.
+//
+// This software is governed by the CeCILL license under French law and
+// abiding by the rules of distribution of free software.  You can  use,
+// modify and/ or redistribute the software under the terms of the CeCILL
+// license as circulated by CEA, CNRS and INRIA at the following URL
+// "http://www.cecill.info".
+//
+// As a counterpart to the access to the source code and  rights to copy,
+// modify and redistribute granted by the license, users are provided only
+// with a limited warranty  and the software's author,  the holder of the
+// economic rights,  and the successive licensors  have only  limited
+// liability.
+//
+// In this respect, the user's attention is drawn to the risks associated
+// with loading,  using,  modifying and/or developing or reproducing the
+// software by the user in light of its specific status of free software,
+// that may mean  that it is complicated to manipulate,  and  that  also
+// therefore means  that it is reserved for developers  and  experienced
+// professionals having in-depth computer knowledge. Users are therefore
+// encouraged to load and test the software's suitability as regards their
+// requirements in conditions enabling the security of their systems and/or
+// data to be ensured and,  more generally, to use and operate it in the
+// same conditions as regards security.
+//
+// The fact that you are presently reading this means that you have had
+// knowledge of the CeCILL license and that you accept its terms.
+
+use std::io::BufRead;
+
+use crate::{alias, execute, ClientState, Commands};
+
+// Run commands read from a script, one per line. Empty lines and lines
+// starting with '#' are ignored. Errors are reported on stderr with the
+// script name and line number, and stop the script unless keep_going is set.
+// Returns whether every command succeeded.
+pub fn run<R: BufRead>(reader: R, name: &str, keep_going: bool, state: &mut ClientState) -> bool {
+  let mut success = true;
+  for (number, line) in reader.lines().enumerate() {
+    let line = match line {
+      Ok(line) => line,
+      Err(e) => {
+        eprintln!("{}: Failed to read script: {}", name, e);
+        return false;
+      }
+    };
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+      continue;
+    }
+
+    match run_line(line, state) {
+      Ok(true) => {}
+      Ok(false) => break,
+      Err(msg) => {
+        eprintln!("{}:{}: {}", name, number + 1, msg);
+        success = false;
+        if !keep_going {
+          break;
+        }
+      }
+    }
+  }
+  success
+}
+
+// Returns false when the script asks to quit
+fn run_line(line: &str, state: &mut ClientState) -> Result<bool, String> {
+  for command in alias::expand(line, &state.config.aliases)? {
+    if !execute(Commands::parse_strict(command), state)? {
+      return Ok(false);
+    }
+  }
+  Ok(true)
+}
+
+#[cfg(test)]
+mod tests_script {
+  use super::*;
+
+  #[test]
+  fn should_stop_on_first_error() {
+    let script = "# Check aliases\nalias b back\n\nb\nalias q quit\n";
+    let mut state = ClientState::new();
+    assert!(!run(script.as_bytes(), "test.mrm", false, &mut state));
+    assert!(!state.config.aliases.contains_key("q"));
+  }
+
+  #[test]
+  fn should_fail_on_unknown_commands() {
+    let script = "alias b back\nunalais b\nalias q quit\n";
+    let mut state = ClientState::new();
+    assert!(!run(script.as_bytes(), "test.mrm", false, &mut state));
+    assert!(!state.config.aliases.contains_key("q"));
+  }
+
+  #[test]
+  fn should_keep_going_when_asked() {
+    let script = "alias b back\nb\nalias q quit\nq\nalias r back\n";
+    let mut state = ClientState::new();
+    assert!(!run(script.as_bytes(), "test.mrm", true, &mut state));
+    assert!(state.config.aliases.contains_key("q"));
+    // Nothing is run after quitting
+    assert!(!state.config.aliases.contains_key("r"));
+  }
+
+  #[test]
+  fn should_succeed_without_errors() {
+    let script = "alias b back\nunalias b\nquit\nback\n";
+    let mut state = ClientState::new();
+    assert!(run(script.as_bytes(), "test.mrm", false, &mut state));
+  }
+}
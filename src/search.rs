@@ -0,0 +1,242 @@
+// Copyright © Killian Kemps (2019)
+//
+// Killian Kemps <developer@killiankemps.fr>
+//
+// This software is a computer program whose purpose is to communicate with
+// the Gopher protocol.
+//
+// This software is governed by the CeCILL license under French law and
+// abiding by the rules of distribution of free software.  You can  use,
+// modify and/ or redistribute the software under the terms of the CeCILL
+// license as circulated by CEA, CNRS and INRIA at the following URL
+// "http://www.cecill.info".
+//
+// As a counterpart to the access to the source code and  rights to copy,
+// modify and redistribute granted by the license, users are provided only
+// with a limited warranty  and the software's author,  the holder of the
+// economic rights,  and the successive licensors  have only  limited
+// liability.
+//
+// In this respect, the user's attention is drawn to the risks associated
+// with loading,  using,  modifying and/or developing or reproducing the
+// software by the user in light of its specific status of free software,
+// that may mean  that it is complicated to manipulate,  and  that  also
+// therefore means  that it is reserved for developers  and  experienced
+// professionals having in-depth computer knowledge. Users are therefore
+// encouraged to load and test the software's suitability as regards their
+// requirements in conditions enabling the security of their systems and/or
+// data to be ensured and,  more generally, to use and operate it in the
+// same conditions as regards security.
+//
+// The fact that you are presently reading this means that you have had
+// knowledge of the CeCILL license and that you accept its terms.
+
+use regex::Regex;
+
+use crate::config::Config;
+use crate::theme::{ColourMode, Theme};
+use crate::GopherResponse;
+
+// Number of lines displayed before and after a match
+const CONTEXT_LINES: usize = 1;
+
+pub enum Pattern {
+  // Case-insensitive text
+  Text(String),
+  Regex(Regex),
+}
+
+impl Pattern {
+  // Patterns between slashes are regular expressions, e.g. "/^phlog/"
+  pub fn from(pattern: &str) -> Result<Pattern, String> {
+    if pattern.len() > 1 && pattern.starts_with('/') && pattern.ends_with('/') {
+      match Regex::new(&pattern[1..pattern.len() - 1]) {
+        Ok(regex) => Ok(Pattern::Regex(regex)),
+        Err(e) => Err(format!("Invalid regular expression: {}", e)),
+      }
+    } else {
+      Ok(Pattern::Text(pattern.to_lowercase()))
+    }
+  }
+
+  pub fn matches(&self, text: &str) -> bool {
+    match self {
+      Pattern::Text(pattern) => text.to_lowercase().contains(pattern),
+      Pattern::Regex(regex) => regex.is_match(text),
+    }
+  }
+}
+
+impl GopherResponse {
+  // Lines matching the pattern with their line number and surrounding lines,
+  // in the same way as grep: "12:" for matches, "11-" for context and "--"
+  // between groups of lines
+  pub fn find(&self, pattern: &Pattern, config: &Config, host: &str) -> Vec<String> {
+    let mut plain_config = config.clone();
+    plain_config.colours = ColourMode::Never;
+    let plain_lines = self.render(&plain_config, host);
+    let lines = self.render(config, host);
+    let colours = config.colours.enabled();
+
+    let matches: Vec<usize> = plain_lines
+      .iter()
+      .enumerate()
+      .filter(|(_, line)| pattern.matches(line))
+      .map(|(index, _)| index)
+      .collect();
+
+    let mut output = Vec::new();
+    let mut last_displayed: Option<usize> = None;
+    for (position, &index) in matches.iter().enumerate() {
+      let first = index.saturating_sub(CONTEXT_LINES);
+      let last = (index + CONTEXT_LINES).min(lines.len() - 1);
+      let first = match last_displayed {
+        Some(displayed) if displayed + 1 >= first => displayed + 1,
+        Some(_) => {
+          output.push("--".to_string());
+          first
+        }
+        None => first,
+      };
+      // Stop the context before the next match, it is displayed on its own
+      let last = match matches.get(position + 1) {
+        Some(&next) if next <= last => next - 1,
+        _ => last,
+      };
+
+      for line_index in first..=last {
+        let separator = if line_index == index { ":" } else { "-" };
+        let number = format!("{}{}", line_index + 1, separator);
+        let line = if line_index == index {
+          lines[line_index].clone()
+        } else {
+          Theme::paint(&config.theme.info, &plain_lines[line_index], colours)
+        };
+        output.push(format!("{} {}", number, line).trim_end().to_string());
+      }
+      last_displayed = Some(last);
+    }
+    output
+  }
+
+  // Menu lines whose description or URL match the pattern, rendered with
+  // their original link indexes
+  pub fn filter(
+    &self,
+    pattern: &Pattern,
+    config: &Config,
+    host: &str,
+  ) -> Result<Vec<String>, String> {
+    let response = match self {
+      GopherResponse::Menu(response) => response,
+      GopherResponse::Text(_) | GopherResponse::Gemini(_) => {
+        return Err("Only menus can be filtered".to_string())
+      }
+    };
+
+    let lines = self.render(config, host);
+    Ok(
+      response
+        .lines
+        .iter()
+        .zip(lines)
+        .filter(|(line, _)| match line {
+          Ok(line) => pattern.matches(&line.description) || pattern.matches(&line.get_url()),
+          Err(_) => false,
+        })
+        .map(|(_, rendered)| rendered)
+        .collect(),
+    )
+  }
+}
+
+#[cfg(test)]
+mod tests_search {
+  use super::*;
+  use crate::GopherMenuResponse;
+
+  fn menu() -> GopherResponse {
+    GopherResponse::Menu(GopherMenuResponse::from(
+      "\
+       iWelcome to the directory		error.host	1\r\n\
+       1About	/about	khzae.net	70\r\n\
+       i 		error.host	1\r\n\
+       1Super Dimension Fortress (SDF)	/	sdf.org	70\r\n\
+       0RFC 4266 (gopher URI scheme)	/rfc4266.txt	khzae.net	70\r\n\
+       0RFC 1436 (gopher protocol)	/rfc1436.txt	khzae.net	70\r\n\
+       .",
+    ))
+  }
+
+  fn plain_config() -> Config {
+    let mut config = Config::new();
+    config.colours = ColourMode::Never;
+    config
+  }
+
+  #[test]
+  fn should_parse_patterns() {
+    assert!(Pattern::from("gopher")
+      .unwrap()
+      .matches("The GOPHER protocol"));
+    assert!(Pattern::from("/^RFC [0-9]+/").unwrap().matches("RFC 1436"));
+    assert!(!Pattern::from("/^RFC [0-9]+/")
+      .unwrap()
+      .matches("About RFC 1436"));
+    assert!(Pattern::from("/[/").is_err());
+  }
+
+  #[test]
+  fn should_find_lines_with_context() {
+    assert_eq!(
+      vec![
+        "3-".to_string(),
+        "4: MENU  [2]  Super Dimension Fortress (SDF)/ \u{2197} sdf.org".to_string(),
+        "5: TXT   [3]  RFC 4266 (gopher URI scheme)".to_string(),
+        "6: TXT   [4]  RFC 1436 (gopher protocol)".to_string(),
+      ],
+      menu().find(
+        &Pattern::from("/SDF|gopher/").unwrap(),
+        &plain_config(),
+        "khzae.net"
+      )
+    );
+    assert_eq!(
+      vec![
+        "1-            Welcome to the directory".to_string(),
+        "2: MENU  [1]  About/".to_string(),
+        "3-".to_string(),
+        "--".to_string(),
+        "5- TXT   [3]  RFC 4266 (gopher URI scheme)".to_string(),
+        "6: TXT   [4]  RFC 1436 (gopher protocol)".to_string(),
+      ],
+      menu().find(
+        &Pattern::from("/About|1436/").unwrap(),
+        &plain_config(),
+        "khzae.net"
+      )
+    );
+  }
+
+  #[test]
+  fn should_filter_menu_keeping_indexes() {
+    assert_eq!(
+      Ok(vec![
+        "TXT   [3]  RFC 4266 (gopher URI scheme)".to_string(),
+        "TXT   [4]  RFC 1436 (gopher protocol)".to_string(),
+      ]),
+      menu().filter(&Pattern::from("rfc").unwrap(), &plain_config(), "khzae.net")
+    );
+    // Links are matched on their URL too
+    assert_eq!(
+      Ok(vec![
+        "MENU  [2]  Super Dimension Fortress (SDF)/ \u{2197} sdf.org".to_string()
+      ]),
+      menu().filter(
+        &Pattern::from("sdf.org").unwrap(),
+        &plain_config(),
+        "khzae.net"
+      )
+    );
+  }
+}
@@ -0,0 +1,358 @@
+// Copyright © Killian Kemps (2019)
+//
+// Killian Kemps <developer@killiankemps.fr>
+//
+// This software is a computer program whose purpose is to communicate with
+// the Gopher protocol.
+//
+// This software is governed by the CeCILL license under French law and
+// abiding by the rules of distribution of free software.  You can  use,
+// modify and/ or redistribute the software under the terms of the CeCILL
+// license as circulated by CEA, CNRS and INRIA at the following URL
+// "http://www.cecill.info".
+//
+// As a counterpart to the access to the source code and  rights to copy,
+// modify and redistribute granted by the license, users are provided only
+// with a limited warranty  and the software's author,  the holder of the
+// economic rights,  and the successive licensors  have only  limited
+// liability.
+//
+// In this respect, the user's attention is drawn to the risks associated
+// with loading,  using,  modifying and/or developing or reproducing the
+// software by the user in light of its specific status of free software,
+// that may mean  that it is complicated to manipulate,  and  that  also
+// therefore means  that it is reserved for developers  and  experienced
+// professionals having in-depth computer knowledge. Users are therefore
+// encouraged to load and test the software's suitability as regards their
+// requirements in conditions enabling the security of their systems and/or
+// data to be ensured and,  more generally, to use and operate it in the
+// same conditions as regards security.
+//
+// The fact that you are presently reading this means that you have had
+// knowledge of the CeCILL license and that you accept its terms.
+
+// Minimal gopher server publishing a local directory, to preview gopher
+// holes. Directories are served as menus, generated from their content
+// unless they contain a gophermap, and files are sent as they are.
+
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::sniff::{self, Content, SNIFF_SIZE};
+use crate::{GopherMenuLine, GopherMenuResponse};
+
+pub const DEFAULT_PORT: u16 = 7070;
+// Menus written by hand, also used by mirrors
+const MENU_FILENAME: &str = "gophermap";
+// Selectors are short, longer requests are cut
+const MAX_REQUEST_SIZE: u64 = 4096;
+// Clients which don't send their request in time are left
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Server address as given in menus, the one the client connected to
+struct Server<'a> {
+  root: &'a Path,
+  host: String,
+  port: String,
+}
+
+fn menu_line(
+  item_type: &str,
+  description: &str,
+  selector: &str,
+  host: &str,
+  port: &str,
+) -> GopherMenuLine {
+  GopherMenuLine {
+    r#type: item_type.to_string(),
+    description: description.to_string(),
+    selector: selector.to_string(),
+    host: host.to_string(),
+    port: port.to_string(),
+    gopher_plus: None,
+  }
+}
+
+fn error_menu(message: &str) -> GopherMenuResponse {
+  GopherMenuResponse::from_items(vec![menu_line("3", message, "", "error.host", "1")])
+}
+
+// Type of a file from its content: images, other binary content or text
+fn item_type(path: &Path) -> &'static str {
+  let mut start = Vec::new();
+  let read = File::open(path).and_then(|file| file.take(SNIFF_SIZE as u64).read_to_end(&mut start));
+  match (read, sniff::sniff(&start)) {
+    (Err(_), _) => "9",
+    (_, Content::Binary("GIF image")) => "g",
+    (_, Content::Binary("PNG image")) | (_, Content::Binary("JPEG image")) => "I",
+    (_, Content::Binary(_)) => "9",
+    _ => "0",
+  }
+}
+
+// Path of the file or directory of a selector, which must stay in the root.
+// Hidden files are not listed in menus and can't be requested either.
+fn resolve(root: &Path, selector: &str) -> Option<PathBuf> {
+  let relative = Path::new(selector.trim_start_matches('/'));
+  if relative.components().any(|component| match component {
+    Component::Normal(name) => name.to_string_lossy().starts_with('.'),
+    _ => true,
+  }) {
+    return None;
+  }
+  // Symbolic links may point outside of the root
+  let path = root.join(relative).canonicalize().ok()?;
+  if path.starts_with(root) {
+    Some(path)
+  } else {
+    None
+  }
+}
+
+impl<'a> Server<'a> {
+  // Selector of a path in the root, starting with '/'
+  fn selector(&self, path: &Path) -> String {
+    let relative = path.strip_prefix(self.root).unwrap_or(path);
+    format!("/{}", relative.to_string_lossy())
+  }
+
+  // Menu listing a directory, hidden files being left out
+  fn generate_menu(&self, directory: &Path) -> io::Result<GopherMenuResponse> {
+    let mut entries: Vec<PathBuf> = fs::read_dir(directory)?
+      .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+      .collect();
+    entries.sort();
+
+    let mut items = Vec::new();
+    for path in entries {
+      let name = match path.file_name().and_then(|name| name.to_str()) {
+        Some(name) => name,
+        None => continue,
+      };
+      if name.starts_with('.') || name == MENU_FILENAME || name.contains(['\t', '\r', '\n']) {
+        continue;
+      }
+      let item_type = if path.is_dir() { "1" } else { item_type(&path) };
+      let line = menu_line(
+        item_type,
+        name,
+        &self.selector(&path),
+        &self.host,
+        &self.port,
+      );
+      items.push(line);
+    }
+    Ok(GopherMenuResponse::from_items(items))
+  }
+
+  // Complete the lines of a gophermap: text without tab is information, and
+  // items may leave out their host and port, which are the ones of this
+  // server, and give selectors relative to the directory of the gophermap
+  fn complete_gophermap(&self, directory: &Path, gophermap: &str) -> GopherMenuResponse {
+    let mut items = Vec::new();
+    for line in gophermap.lines() {
+      let line = line.trim_end_matches('\r');
+      if line == "." {
+        break;
+      }
+      let mut fields: Vec<&str> = line.split('\t').collect();
+      if fields.len() == 1 {
+        items.push(menu_line("i", line, "", &self.host, &self.port));
+        continue;
+      }
+      // Information and error lines have no target
+      let is_text = fields[0].starts_with(['i', '3']);
+      let selector = match fields[1] {
+        "" if !is_text => fields[0].get(1..).unwrap_or("").to_string(),
+        selector => selector.to_string(),
+      };
+      let is_local = !is_text && (fields.len() < 3 || fields[2].is_empty());
+      let selector = if is_local && !selector.starts_with('/') && !selector.starts_with("URL:") {
+        format!(
+          "{}/{}",
+          self.selector(directory).trim_end_matches('/'),
+          selector
+        )
+      } else {
+        selector
+      };
+      if fields.len() < 4 {
+        fields.resize(4, "");
+      }
+      if fields[2].is_empty() {
+        fields[2] = &self.host;
+      }
+      if fields[3].is_empty() {
+        fields[3] = &self.port;
+      }
+      fields[1] = &selector;
+      match GopherMenuLine::from_lenient(&fields.join("\t")) {
+        Ok(item) => items.push(item),
+        Err(msg) => eprintln!("{}: {}", directory.join(MENU_FILENAME).display(), msg),
+      }
+    }
+    GopherMenuResponse::from_items(items)
+  }
+
+  // Answer a request, returning a status to log
+  fn answer(&self, selector: &str, client: &mut dyn Write) -> io::Result<&'static str> {
+    let path = match resolve(self.root, selector) {
+      Some(path) => path,
+      None => {
+        client.write_all(error_menu("Not found").to_wire().as_bytes())?;
+        return Ok("not found");
+      }
+    };
+
+    if path.is_dir() {
+      let menu = match fs::read_to_string(path.join(MENU_FILENAME)) {
+        Ok(gophermap) => self.complete_gophermap(&path, &gophermap),
+        Err(_) => self.generate_menu(&path)?,
+      };
+      client.write_all(menu.to_wire().as_bytes())?;
+      return Ok("menu");
+    }
+    match File::open(&path) {
+      Ok(mut file) => {
+        io::copy(&mut file, client)?;
+        Ok("file")
+      }
+      Err(_) => {
+        client.write_all(error_menu("Not readable").to_wire().as_bytes())?;
+        Ok("not readable")
+      }
+    }
+  }
+}
+
+fn handle(client: TcpStream, root: &Path, port: u16) -> io::Result<()> {
+  client.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+  let mut request = String::new();
+  BufReader::new((&client).take(MAX_REQUEST_SIZE)).read_line(&mut request)?;
+  // Search terms and Gopher+ strings are ignored
+  let selector = request
+    .trim_end_matches(['\r', '\n'])
+    .split('\t')
+    .next()
+    .unwrap_or("");
+
+  let server = Server {
+    root,
+    host: client.local_addr()?.ip().to_string(),
+    port: port.to_string(),
+  };
+  let mut client = client;
+  let status = server.answer(selector, &mut client)?;
+  println!("{:?} -> {}", selector, status);
+  Ok(())
+}
+
+// Serve the directory until the program is stopped, each request in its own
+// thread
+pub fn serve(directory: &str, port: u16) -> Result<(), String> {
+  let root = match Path::new(directory).canonicalize() {
+    Ok(root) if root.is_dir() => Arc::new(root),
+    _ => return Err(format!("{} is not a directory", directory)),
+  };
+  let address = format!("127.0.0.1:{}", port);
+  let listener = match TcpListener::bind(&address) {
+    Ok(listener) => listener,
+    Err(e) => return Err(format!("Failed to listen on {}: {}", address, e)),
+  };
+  println!("Serving {} on gopher://{}/", root.display(), address);
+
+  for client in listener.incoming() {
+    match client {
+      Ok(client) => {
+        let root = root.clone();
+        thread::spawn(move || {
+          if let Err(e) = handle(client, &root, port) {
+            eprintln!("Failed to answer request: {}", e);
+          }
+        });
+      }
+      Err(e) => eprintln!("Failed to accept connection: {}", e),
+    }
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests_server {
+  use super::*;
+  use std::env;
+
+  fn hole(name: &str) -> PathBuf {
+    let root = env::temp_dir().join(format!("marmotte-server-{}-{}", name, std::process::id()));
+    fs::create_dir_all(root.join("phlog")).unwrap();
+    fs::write(root.join("about.txt"), "About\n").unwrap();
+    fs::write(root.join("logo.png"), b"\x89PNG\r\n\x1a\n\0").unwrap();
+    fs::write(root.join("tool.bin"), b"\x7fELF\0\0").unwrap();
+    fs::write(root.join(".hidden"), "").unwrap();
+    root.canonicalize().unwrap()
+  }
+
+  fn get(root: &Path, selector: &str) -> String {
+    let server = Server {
+      root,
+      host: "localhost".to_string(),
+      port: "7070".to_string(),
+    };
+    let mut response = Vec::new();
+    server.answer(selector, &mut response).unwrap();
+    String::from_utf8_lossy(&response).to_string()
+  }
+
+  #[test]
+  fn should_generate_menus() {
+    let root = hole("generate");
+    assert_eq!(
+      "0about.txt\t/about.txt\tlocalhost\t7070\r\n\
+       Ilogo.png\t/logo.png\tlocalhost\t7070\r\n\
+       1phlog\t/phlog\tlocalhost\t7070\r\n\
+       9tool.bin\t/tool.bin\tlocalhost\t7070\r\n\
+       .\r\n",
+      get(&root, "")
+    );
+    assert_eq!("About\n", get(&root, "/about.txt"));
+    assert!(get(&root, "/../etc/passwd").starts_with("3Not found\t"));
+    assert!(get(&root, "/.hidden").starts_with("3Not found\t"));
+    fs::create_dir_all(root.join(".git")).unwrap();
+    fs::write(root.join(".git/config"), "[core]\n").unwrap();
+    assert!(get(&root, "/.git/config").starts_with("3Not found\t"));
+    assert!(get(&root, ".git").starts_with("3Not found\t"));
+    assert!(get(&root, "/missing").starts_with("3Not found\t"));
+    fs::remove_dir_all(root).unwrap();
+  }
+
+  #[test]
+  fn should_honour_gophermaps() {
+    let root = hole("gophermap");
+    fs::write(
+      root.join("phlog/gophermap"),
+      "Welcome\n\
+       0About\t/about.txt\n\
+       0First post\tfirst.txt\n\
+       i.\t\t\t\n\
+       1Floodgap\t/\tgopher.floodgap.com\t70\n\
+       .\n\
+       ignored\n",
+    )
+    .unwrap();
+    assert_eq!(
+      "iWelcome\t\tlocalhost\t7070\r\n\
+       0About\t/about.txt\tlocalhost\t7070\r\n\
+       0First post\t/phlog/first.txt\tlocalhost\t7070\r\n\
+       i.\t\tlocalhost\t7070\r\n\
+       1Floodgap\t/\tgopher.floodgap.com\t70\r\n\
+       .\r\n",
+      get(&root, "/phlog")
+    );
+    fs::remove_dir_all(root).unwrap();
+  }
+}
@@ -0,0 +1,353 @@
+// Copyright © Killian Kemps (2019)
+//
+// Killian Kemps <developer@killiankemps.fr>
+//
+// This software is a computer program whose purpose is to communicate with
+// the Gopher protocol.
+//
+// This software is governed by the CeCILL license under French law and
+// abiding by the rules of distribution of free software.  You can  use,
+// modify and/ or redistribute the software under the terms of the CeCILL
+// license as circulated by CEA, CNRS and INRIA at the following URL
+// "http://www.cecill.info".
+//
+// As a counterpart to the access to the source code and  rights to copy,
+// modify and redistribute granted by the license, users are provided only
+// with a limited warranty  and the software's author,  the holder of the
+// economic rights,  and the successive licensors  have only  limited
+// liability.
+//
+// In this respect, the user's attention is drawn to the risks associated
+// with loading,  using,  modifying and/or developing or reproducing the
+// software by the user in light of its specific status of free software,
+// that may mean  that it is complicated to manipulate,  and  that  also
+// therefore means  that it is reserved for developers  and  experienced
+// professionals having in-depth computer knowledge. Users are therefore
+// encouraged to load and test the software's suitability as regards their
+// requirements in conditions enabling the security of their systems and/or
+// data to be ensured and,  more generally, to use and operate it in the
+// same conditions as regards security.
+//
+// The fact that you are presently reading this means that you have had
+// knowledge of the CeCILL license and that you accept its terms.
+
+use std::env;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+use crate::config::MenuMode;
+use crate::tabs::Tab;
+use crate::{parse_response, ClientState, GopherURL, Scheme, SOFTWARE_NAME};
+
+// Session saved when quitting and restored at launch
+pub const LAST_SESSION: &str = "last";
+
+// Tabs and their last response, as saved on disk
+#[derive(Clone)]
+pub struct Session {
+  pub tabs: Vec<Tab>,
+  pub current_tab: usize,
+}
+
+fn get_sessions_path() -> Result<PathBuf, String> {
+  match env::var("HOME") {
+    Ok(home) => Ok(PathBuf::from(format!(
+      "{}/.{}/sessions",
+      home, SOFTWARE_NAME
+    ))),
+    Err(e) => Err(format!(
+      "Could not get path to sessions because $HOME is not set: {:?}",
+      e
+    )),
+  }
+}
+
+// Sessions are stored in ~/.marmotte/sessions/<name>/
+fn get_session_path(name: &str) -> Result<PathBuf, String> {
+  let valid_name = !name.is_empty()
+    && !name.starts_with('.')
+    && name
+      .chars()
+      .all(|c| c.is_alphanumeric() || "-_.".contains(c));
+  if !valid_name {
+    return Err(format!(
+      "Invalid session name \"{}\", use letters, digits, '-', '_' or '.'",
+      name
+    ));
+  }
+  Ok(get_sessions_path()?.join(name))
+}
+
+// Names of the saved sessions
+pub fn list_sessions() -> Result<Vec<String>, String> {
+  let entries = match fs::read_dir(get_sessions_path()?) {
+    Ok(entries) => entries,
+    Err(error) => match error.kind() {
+      ErrorKind::NotFound => return Ok(Vec::new()),
+      _ => return Err(format!("Problem reading the sessions folder: {:?}", error)),
+    },
+  };
+  let mut names: Vec<String> = entries
+    .filter_map(|entry| entry.ok())
+    .filter(|entry| entry.path().join("tabs.txt").exists())
+    .map(|entry| entry.file_name().to_string_lossy().to_string())
+    .collect();
+  names.sort();
+  Ok(names)
+}
+
+// Tabs are stored as text: a "tab" line with the tab name followed by
+// "url" lines for its history, most recent first. The "current" line gives
+// the index of the current tab.
+fn serialize_tabs(tabs: &[Tab], current_tab: usize) -> String {
+  let mut contents = format!("current {}\n", current_tab);
+  for tab in tabs {
+    contents.push_str(format!("tab {}\n", tab.name).trim_end());
+    contents.push('\n');
+    for url in &tab.history {
+      if let Some(url) = url.get_url() {
+        contents.push_str(&format!("url {}\n", url));
+      }
+    }
+  }
+  contents
+}
+
+fn parse_tabs(contents: &str) -> Result<(Vec<Tab>, usize), String> {
+  let mut tabs: Vec<Tab> = Vec::new();
+  let mut current_tab = 0;
+
+  for (index, line) in contents.lines().enumerate() {
+    let mut value = "".to_string();
+    let mut keyword = line.trim().to_string();
+    if let Some(idx) = keyword.find(" ") {
+      value = keyword.split_off(idx).trim().to_string();
+    }
+
+    match &keyword[..] {
+      "" => {}
+      "current" => match value.parse::<usize>() {
+        Ok(idx) => current_tab = idx,
+        Err(_) => return Err(format!("Invalid current tab on line {}", index + 1)),
+      },
+      "tab" => {
+        let mut tab = Tab::new();
+        tab.name = value;
+        tabs.push(tab);
+      }
+      "url" => match tabs.last_mut() {
+        Some(tab) => match GopherURL::from(&value) {
+          Ok(url) => tab.history.push(url),
+          Err(error) => return Err(format!("{} on line {}", error, index + 1)),
+        },
+        None => return Err(format!("URL outside of a tab on line {}", index + 1)),
+      },
+      _ => return Err(format!("Unknown entry on line {}", index + 1)),
+    }
+  }
+
+  if tabs.is_empty() {
+    tabs.push(Tab::new());
+  }
+  if current_tab >= tabs.len() {
+    current_tab = 0;
+  }
+  Ok((tabs, current_tab))
+}
+
+impl Session {
+  // Write tabs.txt and the last response of each tab in tab-<index>.raw
+  pub fn save(&self, name: &str) -> Result<(), String> {
+    let path = get_session_path(name)?;
+    if let Err(e) = fs::create_dir_all(&path) {
+      return Err(format!("Problem creating folder to store session: {:?}", e));
+    }
+
+    // Remove responses of tabs which may have been closed since last save
+    if let Ok(entries) = fs::read_dir(&path) {
+      for entry in entries.filter_map(|entry| entry.ok()) {
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        if file_name.starts_with("tab-") && file_name.ends_with(".raw") {
+          let _ = fs::remove_file(entry.path());
+        }
+      }
+    }
+
+    for (index, tab) in self.tabs.iter().enumerate() {
+      if tab.last_raw_response.is_empty() {
+        continue;
+      }
+      if let Err(e) = fs::write(
+        path.join(format!("tab-{}.raw", index)),
+        &tab.last_raw_response,
+      ) {
+        return Err(format!("Problem writing the session file: {:?}", e));
+      }
+    }
+
+    match fs::write(
+      path.join("tabs.txt"),
+      serialize_tabs(&self.tabs, self.current_tab),
+    ) {
+      Ok(_) => Ok(()),
+      Err(e) => Err(format!("Problem writing the session file: {:?}", e)),
+    }
+  }
+
+  // Return None when there is no such session
+  pub fn load(name: &str, menus: MenuMode) -> Result<Option<Session>, String> {
+    let path = get_session_path(name)?;
+    let contents = match fs::read_to_string(path.join("tabs.txt")) {
+      Ok(contents) => contents,
+      Err(error) => match error.kind() {
+        ErrorKind::NotFound => return Ok(None),
+        _ => return Err(format!("Problem reading the session file: {:?}", error)),
+      },
+    };
+
+    let (mut tabs, current_tab) = parse_tabs(&contents)?;
+    for (index, tab) in tabs.iter_mut().enumerate() {
+      // Tabs without saved response are requested again when displayed, as
+      // well as gemini pages whose MIME type is not saved
+      if let (Some(url), Ok(raw_response)) = (
+        tab
+          .history
+          .first()
+          .filter(|url| url.scheme == Scheme::Gopher),
+        fs::read(path.join(format!("tab-{}.raw", index))),
+      ) {
+        tab.last_response = parse_response(&raw_response, &url.r#type, menus);
+        tab.last_raw_response = raw_response;
+      }
+    }
+    Ok(Some(Session { tabs, current_tab }))
+  }
+}
+
+impl ClientState {
+  pub fn get_session(&mut self) -> Session {
+    self.store_current_tab();
+    Session {
+      tabs: self.tabs.clone(),
+      current_tab: self.current_tab,
+    }
+  }
+
+  // Summary of the tabs and their pages, which changes whenever a page is
+  // opened or left, or a tab is changed, so that the session is only copied
+  // again when needed
+  pub fn session_key(&self) -> String {
+    let mut key = format!(
+      "current {} {}\n",
+      self.current_tab,
+      self.last_raw_response.len()
+    );
+    for (index, tab) in self.tabs.iter().enumerate() {
+      let history = if index == self.current_tab {
+        &self.history
+      } else {
+        &tab.history
+      };
+      key.push_str(&format!("tab {}\n", tab.name));
+      for url in history {
+        key.push_str(&format!("url {}\n", url.get_url().unwrap_or_default()));
+      }
+    }
+    key
+  }
+
+  pub fn save_session(&mut self, name: &str) -> Result<(), String> {
+    self.get_session().save(name)
+  }
+
+  // Replace tabs by the ones of a saved session. Return false when there is
+  // no such session.
+  pub fn restore_session(&mut self, name: &str) -> Result<bool, String> {
+    match Session::load(name, self.config.menus)? {
+      Some(session) => {
+        self.tabs = session.tabs;
+        self.load_tab(session.current_tab);
+        Ok(true)
+      }
+      None => Ok(false),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests_session {
+  use super::*;
+
+  #[test]
+  fn should_serialize_and_parse_tabs() {
+    let mut first_tab = Tab::new();
+    first_tab.history = vec![
+      GopherURL::from("gopher://khzae.net/0/rfc1436.txt").unwrap(),
+      GopherURL::from("gopher://khzae.net").unwrap(),
+    ];
+    let mut second_tab = Tab::new();
+    second_tab.name = "Reading list".to_string();
+    second_tab.history = vec![GopherURL::from("gopher://sdf.org").unwrap()];
+    let tabs = vec![first_tab, second_tab, Tab::new()];
+
+    let contents = serialize_tabs(&tabs, 1);
+    assert_eq!(
+      "current 1\n\
+       tab\n\
+       url gopher://khzae.net:70/0/rfc1436.txt\n\
+       url gopher://khzae.net:70/1\n\
+       tab Reading list\n\
+       url gopher://sdf.org:70/1\n\
+       tab\n"
+        .to_string(),
+      contents
+    );
+
+    let (parsed_tabs, current_tab) = parse_tabs(&contents).unwrap();
+    assert_eq!(1, current_tab);
+    assert_eq!(3, parsed_tabs.len());
+    assert_eq!(tabs[0].history, parsed_tabs[0].history);
+    assert_eq!("Reading list".to_string(), parsed_tabs[1].name);
+    assert_eq!(tabs[1].history, parsed_tabs[1].history);
+    assert!(parsed_tabs[2].history.is_empty());
+  }
+
+  #[test]
+  fn should_only_change_session_key_with_pages_or_tabs() {
+    let mut state = ClientState::new();
+    let key = state.session_key();
+    state.get_session();
+    assert_eq!(key, state.session_key());
+
+    state
+      .history
+      .insert(0, GopherURL::from("gopher://sdf.org").unwrap());
+    let opened = state.session_key();
+    assert_ne!(key, opened);
+    state.tabs[0].name = "Reading list".to_string();
+    assert_ne!(opened, state.session_key());
+  }
+
+  #[test]
+  fn should_validate_session_names() {
+    assert!(get_session_path("work").is_ok());
+    assert!(get_session_path("reading-list_2.0").is_ok());
+    assert!(get_session_path("").is_err());
+    assert!(get_session_path("..").is_err());
+    assert!(get_session_path("../bookmarks").is_err());
+    assert!(get_session_path(".hidden").is_err());
+  }
+
+  #[test]
+  fn should_reject_invalid_sessions() {
+    assert_eq!(
+      Some("URL outside of a tab on line 1".to_string()),
+      parse_tabs("url gopher://khzae.net").err()
+    );
+    assert_eq!(
+      Some("Unknown entry on line 2".to_string()),
+      parse_tabs("tab\nbookmark gopher://khzae.net").err()
+    );
+  }
+}
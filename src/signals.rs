@@ -0,0 +1,79 @@
+// Copyright © Killian Kemps (2019)
+//
+// Killian Kemps <developer@killiankemps.fr>
+//
+// This software is a computer program whose purpose is to communicate with
+// the Gopher protocol.
+//
+// This software is governed by the CeCILL license under French law and
+// abiding by the rules of distribution of free software.  You can  use,
+// modify and/ or redistribute the software under the terms of the CeCILL
+// license as circulated by CEA, CNRS and INRIA at the following URL
+// "http://www.cecill.info".
+//
+// As a counterpart to the access to the source code and  rights to copy,
+// modify and redistribute granted by the license, users are provided only
+// with a limited warranty  and the software's author,  the holder of the
+// economic rights,  and the successive licensors  have only  limited
+// liability.
+//
+// In this respect, the user's attention is drawn to the risks associated
+// with loading,  using,  modifying and/or developing or reproducing the
+// software by the user in light of its specific status of free software,
+// that may mean  that it is complicated to manipulate,  and  that  also
+// therefore means  that it is reserved for developers  and  experienced
+// professionals having in-depth computer knowledge. Users are therefore
+// encouraged to load and test the software's suitability as regards their
+// requirements in conditions enabling the security of their systems and/or
+// data to be ensured and,  more generally, to use and operate it in the
+// same conditions as regards security.
+//
+// The fact that you are presently reading this means that you have had
+// knowledge of the CeCILL license and that you accept its terms.
+
+use std::sync::{Arc, Mutex};
+
+use crate::editor;
+use crate::session::Session;
+
+// Save the latest session in the given slot when the terminal is closed
+// (SIGHUP) or when marmotte is asked to stop (SIGTERM), then exit.
+//
+// The main thread spends its time waiting for user input, so signals are
+// blocked in every thread and waited for by a dedicated thread. This must be
+// called before spawning any other thread as they inherit the signal mask.
+#[cfg(unix)]
+pub fn save_session_on_signals(session: Arc<Mutex<Option<Session>>>, name: &'static str) {
+  use std::{mem, process, ptr, thread};
+
+  let signals = unsafe {
+    let mut signals: libc::sigset_t = mem::zeroed();
+    libc::sigemptyset(&mut signals);
+    libc::sigaddset(&mut signals, libc::SIGTERM);
+    libc::sigaddset(&mut signals, libc::SIGHUP);
+    libc::pthread_sigmask(libc::SIG_BLOCK, &signals, ptr::null_mut());
+    signals
+  };
+
+  thread::spawn(move || {
+    let mut signal: libc::c_int = 0;
+    if unsafe { libc::sigwait(&signals, &mut signal) } != 0 {
+      return;
+    }
+    editor::restore_terminal();
+    // Recover the session even if the main thread panicked while holding it
+    let session = match session.lock() {
+      Ok(session) => session,
+      Err(poisoned) => poisoned.into_inner(),
+    };
+    if let Some(session) = session.as_ref() {
+      if let Err(msg) = session.save(name) {
+        eprintln!("{}", msg);
+      }
+    }
+    process::exit(128 + signal);
+  });
+}
+
+#[cfg(not(unix))]
+pub fn save_session_on_signals(_session: Arc<Mutex<Option<Session>>>, _name: &'static str) {}
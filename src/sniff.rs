@@ -0,0 +1,321 @@
+// Copyright © Killian Kemps (2019)
+//
+// Killian Kemps <developer@killiankemps.fr>
+//
+// This software is a computer program whose purpose is to communicate with
+// the Gopher protocol.
+//
+// This software is governed by the CeCILL license under French law and
+// abiding by the rules of distribution of free software.  You can  use,
+// modify and/ or redistribute the software under the terms of the CeCILL
+// license as circulated by CEA, CNRS and INRIA at the following URL
+// "http://www.cecill.info".
+//
+// As a counterpart to the access to the source code and  rights to copy,
+// modify and redistribute granted by the license, users are provided only
+// with a limited warranty  and the software's author,  the holder of the
+// economic rights,  and the successive licensors  have only  limited
+// liability.
+//
+// In this respect, the user's attention is drawn to the risks associated
+// with loading,  using,  modifying and/or developing or reproducing the
+// software by the user in light of its specific status of free software,
+// that may mean  that it is complicated to manipulate,  and  that  also
+// therefore means  that it is reserved for developers  and  experienced
+// professionals having in-depth computer knowledge. Users are therefore
+// encouraged to load and test the software's suitability as regards their
+// requirements in conditions enabling the security of their systems and/or
+// data to be ensured and,  more generally, to use and operate it in the
+// same conditions as regards security.
+//
+// The fact that you are presently reading this means that you have had
+// knowledge of the CeCILL license and that you accept its terms.
+
+use crate::sanitize::sanitize;
+use crate::{ClientState, GopherMenuResponse, GopherResponse, GopherTextResponse};
+
+// Signatures at the start of common binary formats
+const MAGIC_NUMBERS: [(&[u8], &str); 12] = [
+  (b"\x89PNG", "PNG image"),
+  (b"GIF8", "GIF image"),
+  (b"\xff\xd8\xff", "JPEG image"),
+  (b"%PDF", "PDF document"),
+  (b"PK\x03\x04", "ZIP archive"),
+  (b"\x1f\x8b", "gzip archive"),
+  (b"\x7fELF", "executable"),
+  (b"OggS", "Ogg audio"),
+  (b"ID3", "MP3 audio"),
+  (b"fLaC", "FLAC audio"),
+  (b"RIFF", "RIFF media"),
+  (b"\xfd7zXZ", "xz archive"),
+];
+// Only the beginning of responses is looked at for binary content
+pub const SNIFF_SIZE: usize = 1024;
+// MIME types of the formats above
+const MIME_TYPES: [(&str, &str); 11] = [
+  ("PNG image", "image/png"),
+  ("GIF image", "image/gif"),
+  ("JPEG image", "image/jpeg"),
+  ("PDF document", "application/pdf"),
+  ("ZIP archive", "application/zip"),
+  ("gzip archive", "application/gzip"),
+  ("executable", "application/x-executable"),
+  ("Ogg audio", "audio/ogg"),
+  ("MP3 audio", "audio/mpeg"),
+  ("FLAC audio", "audio/flac"),
+  ("xz archive", "application/x-xz"),
+];
+// MIME types of other formats, from the extension of selectors
+const EXTENSIONS: [(&str, &str); 8] = [
+  ("wav", "audio/wav"),
+  ("avi", "video/x-msvideo"),
+  ("mp4", "video/mp4"),
+  ("webm", "video/webm"),
+  ("webp", "image/webp"),
+  ("bmp", "image/bmp"),
+  ("tar", "application/x-tar"),
+  ("epub", "application/epub+zip"),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Content {
+  Menu,
+  Text,
+  Html,
+  // Name of the format when known
+  Binary(&'static str),
+}
+
+impl Content {
+  fn describe(&self) -> String {
+    match self {
+      Content::Menu => "a menu".to_string(),
+      Content::Text => "a text document".to_string(),
+      Content::Html => "an HTML page".to_string(),
+      Content::Binary(name) => format!("binary content ({})", name),
+    }
+  }
+
+  fn is_same_kind(&self, other: &Content) -> bool {
+    match (self, other) {
+      (Content::Binary(_), Content::Binary(_)) => true,
+      _ => self == other,
+    }
+  }
+}
+
+// Guess what a response contains from magic numbers, NUL bytes and the
+// shape of its lines: menus are mostly made of 4 tab-separated fields
+pub fn sniff(raw: &[u8]) -> Content {
+  for (magic, name) in MAGIC_NUMBERS.iter() {
+    if raw.starts_with(magic) {
+      return Content::Binary(name);
+    }
+  }
+  let start = &raw[..raw.len().min(SNIFF_SIZE)];
+  // Control characters other than whitespace and escape sequences
+  let controls = start
+    .iter()
+    .filter(|byte| matches!(byte, 0..=8 | 0x0e..=0x1a | 0x1c..=0x1f))
+    .count();
+  if start.contains(&0) || controls * 10 > start.len() {
+    return Content::Binary("unknown format");
+  }
+
+  let text = String::from_utf8_lossy(raw);
+  let beginning = text.trim_start().to_lowercase();
+  if beginning.starts_with("<!doctype html") || beginning.starts_with("<html") {
+    return Content::Html;
+  }
+
+  let lines: Vec<&str> = text
+    .lines()
+    .map(|line| line.trim_end_matches('\r'))
+    .filter(|line| line.trim() != "" && *line != ".")
+    .collect();
+  let menu_lines = lines
+    .iter()
+    .filter(|line| !line.starts_with('\t') && line.split('\t').count() >= 4)
+    .count();
+  if menu_lines > 0 && menu_lines * 2 >= lines.len() {
+    Content::Menu
+  } else {
+    Content::Text
+  }
+}
+
+// Guess the MIME type of binary content from its magic number, or else from
+// the extension of its selector
+pub fn mime_type(raw: &[u8], selector: &str) -> &'static str {
+  if let Content::Binary(name) = sniff(raw) {
+    if let Some((_, mime_type)) = MIME_TYPES.iter().find(|(format, _)| *format == name) {
+      return mime_type;
+    }
+  }
+  let extension = match selector.rsplit_once('.') {
+    Some((_, extension)) => extension.to_lowercase(),
+    None => String::new(),
+  };
+  match EXTENSIONS.iter().find(|(known, _)| *known == extension) {
+    Some((_, mime_type)) => mime_type,
+    None => "application/octet-stream",
+  }
+}
+
+// Content expected from the type of an item, if any
+pub fn expected(item_type: &str) -> Option<Content> {
+  match item_type {
+    "0" | "4" | "6" => Some(Content::Text),
+    "1" | "7" => Some(Content::Menu),
+    "h" => Some(Content::Html),
+    "5" | "9" | "g" | "I" | "s" | "d" | ";" => Some(Content::Binary("unknown format")),
+    _ => None,
+  }
+}
+
+// Message telling that the response doesn't match the type of its URL
+pub fn mismatch(item_type: &str, raw: &[u8]) -> Option<String> {
+  let content = sniff(raw);
+  match expected(item_type) {
+    Some(expected) if !expected.is_same_kind(&content) => Some(format!(
+      "Note: the server sent {} although the URL is for {}, use \"view as\" to display it differently",
+      content.describe(),
+      expected.describe()
+    )),
+    _ => None,
+  }
+}
+
+impl ClientState {
+  // Display the current page again as a menu, as text, or as received
+  pub fn view_as(&mut self, mode: &str) -> Result<(), String> {
+    if self.history.is_empty() {
+      return Err("There is no current document".to_string());
+    }
+    if let Content::Binary(name) = sniff(&self.last_raw_response) {
+      return Err(format!(
+        "The current page is binary content ({}), use the save command to keep it",
+        name
+      ));
+    }
+
+    let text = String::from_utf8_lossy(&self.last_raw_response).to_string();
+    match mode {
+      "menu" => {
+        self.last_response =
+          GopherResponse::Menu(GopherMenuResponse::parse(&text, self.config.menus))
+      }
+      "text" => self.last_response = GopherResponse::Text(GopherTextResponse::from(&text)),
+      "raw" => {
+        // Tabs are made visible to see the fields of menus
+        for line in text.lines() {
+          println!(
+            "{}",
+            sanitize(line, self.config.sanitize).replace('\t', "\u{2192}")
+          );
+        }
+        return Ok(());
+      }
+      _ => return Err(format!("Unknown view \"{}\", use menu, text or raw", mode)),
+    }
+    self
+      .last_response
+      .display(&self.config, self.history.first());
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests_sniff {
+  use super::*;
+
+  #[test]
+  fn should_sniff_content() {
+    assert_eq!(
+      Content::Menu,
+      sniff(b"iWelcome\t\terror.host\t1\r\nJust text\r\n1Sub\t/sub\tsdf.org\t70\r\n.\r\n")
+    );
+    assert_eq!(
+      Content::Text,
+      sniff(b"Hello\r\nUse tabs in menus:\r\n1Sub\t/sub\tsdf.org\t70\r\nThat's all\r\n")
+    );
+    assert_eq!(Content::Text, sniff(b""));
+    assert_eq!(
+      Content::Html,
+      sniff(b"\n<!DOCTYPE html>\n<html><body>Hi</body></html>")
+    );
+    assert_eq!(
+      Content::Binary("PNG image"),
+      sniff(b"\x89PNG\r\n\x1a\n\0\0")
+    );
+    assert_eq!(Content::Binary("unknown format"), sniff(b"some\0bytes"));
+    // Escape sequences of ANSI art are text
+    assert_eq!(Content::Text, sniff(b"\x1b[31mred\x1b[0m\r\n"));
+  }
+
+  #[test]
+  fn should_tell_about_mismatches() {
+    let menu = b"1Sub\t/sub\tsdf.org\t70\r\n.\r\n";
+    assert_eq!(None, mismatch("1", menu));
+    assert_eq!(None, mismatch("+", menu));
+    assert_eq!(
+      Some(
+        "Note: the server sent a menu although the URL is for a text document, \
+         use \"view as\" to display it differently"
+          .to_string()
+      ),
+      mismatch("0", menu)
+    );
+    assert_eq!(None, mismatch("I", b"GIF89a"));
+  }
+
+  #[test]
+  fn should_trust_type_of_texts_and_menus() {
+    let menu = b"1Sub\t/sub\tsdf.org\t70\r\n.\r\n";
+    let text = b"Some notes\r\n";
+    let menus = crate::MenuMode::Lenient;
+    assert!(matches!(
+      crate::parse_response(menu, "0", menus),
+      GopherResponse::Text(_)
+    ));
+    assert!(matches!(
+      crate::parse_response(text, "1", menus),
+      GopherResponse::Menu(_)
+    ));
+    assert!(matches!(
+      crate::parse_response(menu, "9", menus),
+      GopherResponse::Menu(_)
+    ));
+    // Unless the content is obviously something else
+    assert!(matches!(
+      crate::parse_response(b"<html><body>Hi</body></html>", "1", menus),
+      GopherResponse::Text(_)
+    ));
+    assert_eq!(
+      vec!["(PNG image, 6 bytes, use the save command to keep it)".to_string()],
+      match crate::parse_response(b"\x89PNG\0\0", "1", menus) {
+        GopherResponse::Text(response) => response.lines,
+        _ => Vec::new(),
+      }
+    );
+  }
+
+  #[test]
+  fn should_view_page_differently() {
+    let mut state = ClientState::new();
+    assert!(state.view_as("menu").is_err());
+
+    state
+      .history
+      .push(crate::GopherURL::from("sdf.org/0/notes").unwrap());
+    state.last_raw_response = b"1Sub\t/sub\tsdf.org\t70\r\n".to_vec();
+    assert_eq!(Ok(()), state.view_as("menu"));
+    assert!(matches!(state.last_response, GopherResponse::Menu(_)));
+    assert_eq!(Ok(()), state.view_as("text"));
+    assert!(matches!(state.last_response, GopherResponse::Text(_)));
+    assert_eq!(
+      Err("Unknown view \"pdf\", use menu, text or raw".to_string()),
+      state.view_as("pdf")
+    );
+  }
+}
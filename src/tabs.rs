@@ -0,0 +1,232 @@
+// Copyright © Killian Kemps (2019)
+//
+// Killian Kemps <developer@killiankemps.fr>
+//
+// This software is a computer program whose purpose is to communicate with
+// the Gopher protocol.
+//
+// This software is governed by the CeCILL license under French law and
+// abiding by the rules of distribution of free software.  You can  use,
+// modify and/ or redistribute the software under the terms of the CeCILL
+// license as circulated by CEA, CNRS and INRIA at the following URL
+// "http://www.cecill.info".
+//
+// As a counterpart to the access to the source code and  rights to copy,
+// modify and redistribute granted by the license, users are provided only
+// with a limited warranty  and the software's author,  the holder of the
+// economic rights,  and the successive licensors  have only  limited
+// liability.
+//
+// In this respect, the user's attention is drawn to the risks associated
+// with loading,  using,  modifying and/or developing or reproducing the
+// software by the user in light of its specific status of free software,
+// that may mean  that it is complicated to manipulate,  and  that  also
+// therefore means  that it is reserved for developers  and  experienced
+// professionals having in-depth computer knowledge. Users are therefore
+// encouraged to load and test the software's suitability as regards their
+// requirements in conditions enabling the security of their systems and/or
+// data to be ensured and,  more generally, to use and operate it in the
+// same conditions as regards security.
+//
+// The fact that you are presently reading this means that you have had
+// knowledge of the CeCILL license and that you accept its terms.
+
+use std::mem;
+
+use crate::{manage_url_request, ClientState, GopherResponse, GopherTextResponse, GopherURL};
+
+#[derive(Clone)]
+pub struct Tab {
+  pub name: String,
+  pub history: Vec<GopherURL>,
+  pub last_response: GopherResponse,
+  pub last_raw_response: Vec<u8>,
+}
+
+impl Tab {
+  pub fn new() -> Tab {
+    Tab {
+      name: String::new(),
+      history: Vec::new(),
+      last_response: GopherResponse::Text(GopherTextResponse::new()),
+      last_raw_response: Vec::new(),
+    }
+  }
+
+  // Name given by the user, or URL of the current page
+  pub fn get_title(&self) -> String {
+    if !self.name.is_empty() {
+      return self.name.clone();
+    }
+    match self.history.first().and_then(|url| url.get_url()) {
+      Some(url) => url,
+      None => "(empty)".to_string(),
+    }
+  }
+}
+
+// The history and responses of the current tab are kept in the client state
+// itself, its entry in the tabs vector is only up to date after calling
+// store_current_tab().
+impl ClientState {
+  pub fn store_current_tab(&mut self) {
+    let tab = &mut self.tabs[self.current_tab];
+    tab.history = self.history.clone();
+    tab.last_response = self.last_response.clone();
+    tab.last_raw_response = self.last_raw_response.clone();
+  }
+
+  pub fn load_tab(&mut self, index: usize) {
+    self.current_tab = index;
+    let tab = &mut self.tabs[index];
+    self.history = mem::take(&mut tab.history);
+    self.last_response = mem::replace(
+      &mut tab.last_response,
+      GopherResponse::Text(GopherTextResponse::new()),
+    );
+    self.last_raw_response = mem::take(&mut tab.last_raw_response);
+  }
+
+  // Display the page of the current tab, requesting it again if it has not
+  // been loaded yet, e.g. after being restored from a session. The page
+  // stays in history when it can't be requested.
+  pub fn show_current_tab(&mut self) -> Result<(), String> {
+    if self.last_raw_response.is_empty() && !self.history.is_empty() {
+      let url = self.history.remove(0);
+      if let Err(msg) = manage_url_request(url.clone(), self) {
+        self.history.insert(0, url);
+        return Err(msg);
+      }
+    } else {
+      self
+        .last_response
+        .display(&self.config, self.history.first());
+    }
+    Ok(())
+  }
+
+  pub fn new_tab(&mut self, url: &str) -> Result<(), String> {
+    self.store_current_tab();
+    self.tabs.push(Tab::new());
+    self.load_tab(self.tabs.len() - 1);
+    if !url.is_empty() {
+      manage_url_request(GopherURL::from(url)?, self)?;
+    }
+    Ok(())
+  }
+
+  pub fn switch_tab(&mut self, args: &str) -> Result<(), String> {
+    let index = self.parse_tab_index(args)?;
+    self.store_current_tab();
+    self.load_tab(index);
+    self.show_current_tab()
+  }
+
+  pub fn close_tab(&mut self) -> Result<(), String> {
+    if self.tabs.len() == 1 {
+      return Err("Can't close the last tab".to_string());
+    }
+    self.tabs.remove(self.current_tab);
+    let index = if self.current_tab > 0 {
+      self.current_tab - 1
+    } else {
+      0
+    };
+    self.load_tab(index);
+    self.show_current_tab()
+  }
+
+  pub fn rename_tab(&mut self, name: &str) {
+    self.tabs[self.current_tab].name = name.to_string();
+  }
+
+  pub fn display_tabs(&mut self) {
+    self.store_current_tab();
+    println!("Tabs:");
+    for (index, tab) in self.tabs.iter().enumerate() {
+      let marker = if index == self.current_tab { "*" } else { " " };
+      // We increase the tab index by 1 for a more user-friendly display
+      println!("{}[tab {}] {}", marker, index + 1, tab.get_title());
+    }
+  }
+
+  fn parse_tab_index(&self, args: &str) -> Result<usize, String> {
+    match args.parse::<usize>() {
+      Ok(index) if index > 0 && index <= self.tabs.len() => Ok(index - 1),
+      Ok(_) => Err("There is no tab at this index".to_string()),
+      Err(error) => Err(format!("Could not parse the tab index: {:?}", error)),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests_tabs {
+  use super::*;
+
+  #[test]
+  fn should_keep_history_per_tab() {
+    let mut state = ClientState::new();
+    state
+      .history
+      .push(GopherURL::from("gopher://khzae.net").unwrap());
+
+    state.new_tab("").unwrap();
+    assert_eq!(1, state.current_tab);
+    assert!(state.history.is_empty());
+    state
+      .history
+      .push(GopherURL::from("gopher://sdf.org").unwrap());
+    state.rename_tab("sdf");
+
+    state.store_current_tab();
+    state.load_tab(0);
+    assert_eq!(
+      vec![GopherURL::from("gopher://khzae.net").unwrap()],
+      state.history
+    );
+
+    state.store_current_tab();
+    state.load_tab(1);
+    assert_eq!(
+      vec![GopherURL::from("gopher://sdf.org").unwrap()],
+      state.history
+    );
+
+    state.store_current_tab();
+    assert_eq!(
+      "gopher://khzae.net:70/1".to_string(),
+      state.tabs[0].get_title()
+    );
+    assert_eq!("sdf".to_string(), state.tabs[1].get_title());
+  }
+
+  #[test]
+  fn should_keep_page_of_restored_tab_when_unreachable() {
+    let mut state = ClientState::new();
+    let url = GopherURL::from("gopher://127.0.0.1:1/1/").unwrap();
+    state.history = vec![GopherURL::from("gopher://sdf.org").unwrap()];
+    state.history.insert(0, url.clone());
+    assert!(state.show_current_tab().is_err());
+    assert_eq!(Some(&url), state.history.first());
+    assert_eq!(2, state.history.len());
+  }
+
+  #[test]
+  fn should_validate_tab_index() {
+    let mut state = ClientState::new();
+    state.new_tab("").unwrap();
+    assert_eq!(Ok(1), state.parse_tab_index("2"));
+    assert_eq!(
+      Err("There is no tab at this index".to_string()),
+      state.parse_tab_index("0")
+    );
+    assert_eq!(
+      Err("There is no tab at this index".to_string()),
+      state.parse_tab_index("3")
+    );
+    assert_eq!(
+      Err("Can't close the last tab".to_string()),
+      ClientState::new().close_tab()
+    );
+  }
+}
@@ -0,0 +1,185 @@
+// Copyright © Killian Kemps (2019)
+//
+// Killian Kemps <developer@killiankemps.fr>
+//
+// This software is a computer program whose purpose is to communicate with
+// the Gopher protocol.
+//
+// This software is governed by the CeCILL license under French law and
+// abiding by the rules of distribution of free software.  You can  use,
+// modify and/ or redistribute the software under the terms of the CeCILL
+// license as circulated by CEA, CNRS and INRIA at the following URL
+// "http://www.cecill.info".
+//
+// As a counterpart to the access to the source code and  rights to copy,
+// modify and redistribute granted by the license, users are provided only
+// with a limited warranty  and the software's author,  the holder of the
+// economic rights,  and the successive licensors  have only  limited
+// liability.
+//
+// In this respect, the user's attention is drawn to the risks associated
+// with loading,  using,  modifying and/or developing or reproducing the
+// software by the user in light of its specific status of free software,
+// that may mean  that it is complicated to manipulate,  and  that  also
+// therefore means  that it is reserved for developers  and  experienced
+// professionals having in-depth computer knowledge. Users are therefore
+// encouraged to load and test the software's suitability as regards their
+// requirements in conditions enabling the security of their systems and/or
+// data to be ensured and,  more generally, to use and operate it in the
+// same conditions as regards security.
+//
+// The fact that you are presently reading this means that you have had
+// knowledge of the CeCILL license and that you accept its terms.
+
+use std::env;
+use std::io::{self, IsTerminal};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColourMode {
+  // Colours when printing to a terminal and $NO_COLOR is not set
+  Auto,
+  Always,
+  Never,
+}
+
+impl ColourMode {
+  pub fn from(value: &str) -> Result<ColourMode, String> {
+    match value {
+      "auto" => Ok(ColourMode::Auto),
+      "always" => Ok(ColourMode::Always),
+      "never" => Ok(ColourMode::Never),
+      _ => Err(format!("Unknown colours mode \"{}\"", value)),
+    }
+  }
+
+  pub fn enabled(&self) -> bool {
+    match self {
+      ColourMode::Always => true,
+      ColourMode::Never => false,
+      // See https://no-color.org
+      ColourMode::Auto => match env::var("NO_COLOR") {
+        Ok(value) if !value.is_empty() => false,
+        _ => io::stdout().is_terminal(),
+      },
+    }
+  }
+}
+
+// Styles are stored as SGR parameters, e.g. "1;34" for bold blue
+#[derive(Debug, Clone, PartialEq)]
+pub struct Theme {
+  pub menu: String,
+  pub text: String,
+  pub other: String,
+  pub info: String,
+  pub index: String,
+  pub external: String,
+  pub error: String,
+}
+
+impl Theme {
+  pub fn new() -> Theme {
+    Theme {
+      menu: "1;34".to_string(),
+      text: "32".to_string(),
+      other: "36".to_string(),
+      info: "2".to_string(),
+      index: "33".to_string(),
+      external: "35".to_string(),
+      error: "31".to_string(),
+    }
+  }
+
+  // Set a style from its configuration name, e.g. "menu" and "bold blue"
+  pub fn set(&mut self, element: &str, value: &str) -> Result<(), String> {
+    let style = Theme::parse_style(value)?;
+    match element {
+      "menu" => self.menu = style,
+      "text" => self.text = style,
+      "other" => self.other = style,
+      "info" => self.info = style,
+      "index" => self.index = style,
+      "external" => self.external = style,
+      "error" => self.error = style,
+      _ => return Err(format!("Unknown theme element \"{}\"", element)),
+    }
+    Ok(())
+  }
+
+  // Convert space-separated colour and attribute names into SGR parameters.
+  // Raw SGR parameters such as "38;5;208" are accepted too.
+  fn parse_style(value: &str) -> Result<String, String> {
+    let mut params = Vec::new();
+    for word in value.split_whitespace() {
+      let param = match word {
+        "none" | "default" => continue,
+        "bold" => "1",
+        "dim" => "2",
+        "italic" => "3",
+        "underline" => "4",
+        "reverse" => "7",
+        "black" => "30",
+        "red" => "31",
+        "green" => "32",
+        "yellow" => "33",
+        "blue" => "34",
+        "magenta" => "35",
+        "cyan" => "36",
+        "white" => "37",
+        "bright_black" | "grey" | "gray" => "90",
+        "bright_red" => "91",
+        "bright_green" => "92",
+        "bright_yellow" => "93",
+        "bright_blue" => "94",
+        "bright_magenta" => "95",
+        "bright_cyan" => "96",
+        "bright_white" => "97",
+        raw if raw.chars().all(|c| c.is_ascii_digit() || c == ';') => raw,
+        _ => return Err(format!("Unknown colour \"{}\"", word)),
+      };
+      params.push(param);
+    }
+    Ok(params.join(";"))
+  }
+
+  pub fn paint(style: &str, text: &str, colours: bool) -> String {
+    if !colours || style.is_empty() || text.is_empty() {
+      return text.to_string();
+    }
+    format!("\u{1b}[{}m{}\u{1b}[0m", style, text)
+  }
+}
+
+#[cfg(test)]
+mod tests_theme {
+  use super::*;
+
+  #[test]
+  fn should_parse_styles() {
+    let mut theme = Theme::new();
+    assert_eq!(Ok(()), theme.set("menu", "bold bright_blue"));
+    assert_eq!("1;94".to_string(), theme.menu);
+    assert_eq!(Ok(()), theme.set("index", "38;5;208"));
+    assert_eq!("38;5;208".to_string(), theme.index);
+    assert_eq!(Ok(()), theme.set("info", "none"));
+    assert_eq!("".to_string(), theme.info);
+    assert_eq!(
+      Err("Unknown colour \"rainbow\"".to_string()),
+      theme.set("text", "bold rainbow")
+    );
+    assert_eq!(
+      Err("Unknown theme element \"title\"".to_string()),
+      theme.set("title", "red")
+    );
+  }
+
+  #[test]
+  fn should_paint_only_when_colours_enabled() {
+    assert_eq!(
+      "\u{1b}[1;34mAbout\u{1b}[0m".to_string(),
+      Theme::paint("1;34", "About", true)
+    );
+    assert_eq!("About".to_string(), Theme::paint("1;34", "About", false));
+    assert_eq!("About".to_string(), Theme::paint("", "About", true));
+  }
+}
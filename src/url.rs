@@ -0,0 +1,499 @@
+// Copyright © Killian Kemps (2019)
+//
+// Killian Kemps <developer@killiankemps.fr>
+//
+// This software is a computer program whose purpose is to communicate with
+// the Gopher protocol.
+//
+// This software is governed by the CeCILL license under French law and
+// abiding by the rules of distribution of free software.  You can  use,
+// modify and/ or redistribute the software under the terms of the CeCILL
+// license as circulated by CEA, CNRS and INRIA at the following URL
+// "http://www.cecill.info".
+//
+// As a counterpart to the access to the source code and  rights to copy,
+// modify and redistribute granted by the license, users are provided only
+// with a limited warranty  and the software's author,  the holder of the
+// economic rights,  and the successive licensors  have only  limited
+// liability.
+//
+// In this respect, the user's attention is drawn to the risks associated
+// with loading,  using,  modifying and/or developing or reproducing the
+// software by the user in light of its specific status of free software,
+// that may mean  that it is complicated to manipulate,  and  that  also
+// therefore means  that it is reserved for developers  and  experienced
+// professionals having in-depth computer knowledge. Users are therefore
+// encouraged to load and test the software's suitability as regards their
+// requirements in conditions enabling the security of their systems and/or
+// data to be ensured and,  more generally, to use and operate it in the
+// same conditions as regards security.
+//
+// The fact that you are presently reading this means that you have had
+// knowledge of the CeCILL license and that you accept its terms.
+
+// Gopher URLs as specified by RFC 4266:
+//
+//   gopher://<host>:<port>/<type><selector>%09<search>%09<gopher+ string>
+//
+// The scheme may be left out when typing URLs. Every part after the type is
+// percent-encoded, and the port defaults to 70.
+//
+// Gemini URLs are parsed too, keeping their path as the selector and their
+// query as the search, both still percent-encoded as they are sent as is.
+
+use std::fmt;
+use std::net::Ipv6Addr;
+
+use crate::{gemini, GopherURL, Scheme};
+
+const SCHEME: &str = "gopher://";
+const DEFAULT_PORT: u16 = 70;
+// Separator of the selector, search and Gopher+ string, '%09' once encoded
+const SEPARATOR: char = '\t';
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+  UnsupportedScheme(String),
+  EmptyHost,
+  InvalidHost(String),
+  InvalidPort(String),
+  InvalidPercentEncoding(String),
+  // Line breaks and NUL would end the request sent to the server
+  InvalidCharacter(String),
+}
+
+impl fmt::Display for ParseError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      ParseError::UnsupportedScheme(scheme) => write!(f, "Unsupported URL scheme \"{}\"", scheme),
+      ParseError::EmptyHost => write!(f, "No host in URL"),
+      ParseError::InvalidHost(host) => write!(f, "Invalid host \"{}\"", host),
+      ParseError::InvalidPort(port) => write!(f, "Invalid port \"{}\"", port),
+      ParseError::InvalidPercentEncoding(part) => {
+        write!(f, "Invalid percent-encoding in \"{}\"", part)
+      }
+      ParseError::InvalidCharacter(part) => write!(f, "Line break or NUL in {:?}", part),
+    }
+  }
+}
+
+// Commands report errors as strings
+impl From<ParseError> for String {
+  fn from(error: ParseError) -> String {
+    error.to_string()
+  }
+}
+
+impl GopherURL {
+  pub fn from(url: &str) -> Result<GopherURL, ParseError> {
+    let url = url.trim();
+    let rest = match url.find("://") {
+      Some(idx) if url[..idx].contains('/') => url,
+      Some(idx) if url[..idx].eq_ignore_ascii_case("gopher") => &url[SCHEME.len()..],
+      Some(idx) if url[..idx].eq_ignore_ascii_case("gemini") => {
+        return parse_gemini(&url[idx + 3..])
+      }
+      Some(idx) => return Err(ParseError::UnsupportedScheme(url[..idx].to_string())),
+      None => url,
+    };
+
+    let (authority, path) = match rest.find('/') {
+      Some(idx) => (&rest[..idx], &rest[idx + 1..]),
+      None => (rest, ""),
+    };
+    let (host, port) = parse_authority(authority, DEFAULT_PORT)?;
+
+    let mut parsed_url = GopherURL::new();
+    parsed_url.host = host;
+    parsed_url.port = port;
+
+    // The type is the first character of the path, the root menu when empty
+    if path.is_empty() {
+      return Ok(parsed_url);
+    }
+    let (item_type, path) = split_type(path)?;
+    parsed_url.r#type = item_type;
+
+    // Typed URLs may contain tabs rather than '%09'
+    let mut parts = path.splitn(3, SEPARATOR).flat_map(split_encoded_tab);
+    parsed_url.selector = decode(parts.next().unwrap_or(""))?;
+    parsed_url.search = parts.next().map(decode).transpose()?;
+    let gopher_plus: Vec<&str> = parts.collect();
+    if !gopher_plus.is_empty() {
+      // Encoded tabs are kept in the Gopher+ string, only three parts exist
+      parsed_url.gopher_plus = Some(decode(&gopher_plus.join("%09"))?);
+    }
+    let request = parsed_url.get_request();
+    if request.contains(['\r', '\n', '\0']) {
+      return Err(ParseError::InvalidCharacter(request));
+    }
+    Ok(parsed_url)
+  }
+}
+
+// Gemini URL without its scheme. Fragments are only meaningful to clients
+// and are left out.
+fn parse_gemini(rest: &str) -> Result<GopherURL, ParseError> {
+  let rest = rest.split('#').next().unwrap_or("");
+  let (authority, path) = match rest.find(['/', '?']) {
+    Some(idx) => rest.split_at(idx),
+    None => (rest, ""),
+  };
+  let (path, query) = match path.split_once('?') {
+    Some((path, query)) => (path, Some(query.to_string())),
+    None => (path, None),
+  };
+  for part in [path, query.as_deref().unwrap_or("")] {
+    if part.contains(char::is_whitespace) || decode_bytes(part).is_none() {
+      return Err(ParseError::InvalidPercentEncoding(part.to_string()));
+    }
+  }
+
+  let (host, port) = parse_authority(authority, gemini::DEFAULT_PORT)?;
+  let mut parsed_url = GopherURL::new();
+  parsed_url.scheme = Scheme::Gemini;
+  parsed_url.host = host;
+  parsed_url.port = port;
+  parsed_url.r#type = String::new();
+  // An empty path is the same as the root
+  parsed_url.selector = if path.is_empty() { "/" } else { path }.to_string();
+  parsed_url.search = query;
+  Ok(parsed_url)
+}
+
+// Separate the type from the rest of the path, the type may be encoded
+fn split_type(path: &str) -> Result<(String, &str), ParseError> {
+  if !path.starts_with('%') {
+    let length = path.chars().next().map_or(0, char::len_utf8);
+    return Ok((path[..length].to_string(), &path[length..]));
+  }
+  // A character is encoded as up to 4 bytes
+  for length in (3..=12).step_by(3) {
+    if let Some(Ok(item_type)) = path.get(..length).map(decode) {
+      if item_type.chars().count() == 1 {
+        return Ok((item_type, &path[length..]));
+      }
+    }
+  }
+  Err(ParseError::InvalidPercentEncoding(path.to_string()))
+}
+
+fn split_encoded_tab(part: &str) -> Vec<&str> {
+  let mut parts = Vec::new();
+  let mut start = 0;
+  let bytes = part.as_bytes();
+  let mut idx = 0;
+  while idx + 3 <= bytes.len() {
+    if bytes[idx] == b'%' && bytes[idx + 1] == b'0' && bytes[idx + 2] == b'9' {
+      parts.push(&part[start..idx]);
+      start = idx + 3;
+      idx += 3;
+    } else {
+      idx += 1;
+    }
+  }
+  parts.push(&part[start..]);
+  parts
+}
+
+fn parse_authority(authority: &str, default_port: u16) -> Result<(String, u16), ParseError> {
+  let (host, port) = if let Some(literal) = authority.strip_prefix('[') {
+    // IPv6 literals are enclosed in brackets
+    let end = match literal.find(']') {
+      Some(end) => end,
+      None => return Err(ParseError::InvalidHost(authority.to_string())),
+    };
+    if literal[..end].parse::<Ipv6Addr>().is_err() {
+      return Err(ParseError::InvalidHost(literal[..end].to_string()));
+    }
+    let port = match &literal[end + 1..] {
+      "" => None,
+      rest => match rest.strip_prefix(':') {
+        Some(port) => Some(port),
+        None => return Err(ParseError::InvalidHost(authority.to_string())),
+      },
+    };
+    (&literal[..end], port)
+  } else {
+    match authority.find(':') {
+      Some(idx) => (&authority[..idx], Some(&authority[idx + 1..])),
+      None => (authority, None),
+    }
+  };
+
+  if host.is_empty() {
+    return Err(ParseError::EmptyHost);
+  }
+  let valid_host = host.contains(':')
+    || host
+      .chars()
+      .all(|c| c.is_alphanumeric() || "-._~".contains(c));
+  if !valid_host {
+    return Err(ParseError::InvalidHost(host.to_string()));
+  }
+
+  let port = match port {
+    None | Some("") => default_port,
+    Some(port) => match port.parse::<u16>() {
+      Ok(number) if number > 0 && port.chars().all(|c| c.is_ascii_digit()) => number,
+      _ => return Err(ParseError::InvalidPort(port.to_string())),
+    },
+  };
+  Ok((host.to_string(), port))
+}
+
+fn decode_bytes(part: &str) -> Option<Vec<u8>> {
+  let mut bytes = Vec::with_capacity(part.len());
+  let mut input = part.bytes();
+  while let Some(byte) = input.next() {
+    if byte != b'%' {
+      bytes.push(byte);
+      continue;
+    }
+    let high = input.next().and_then(|c| (c as char).to_digit(16))?;
+    let low = input.next().and_then(|c| (c as char).to_digit(16))?;
+    bytes.push((high * 16 + low) as u8);
+  }
+  Some(bytes)
+}
+
+pub fn decode(part: &str) -> Result<String, ParseError> {
+  decode_bytes(part)
+    .and_then(|bytes| String::from_utf8(bytes).ok())
+    .ok_or_else(|| ParseError::InvalidPercentEncoding(part.to_string()))
+}
+
+// Percent-encode everything but the characters allowed in URL paths
+pub fn encode(part: &str) -> String {
+  let mut encoded = String::with_capacity(part.len());
+  for byte in part.bytes() {
+    match byte {
+      b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' => encoded.push(byte as char),
+      b'-' | b'.' | b'_' | b'~' | b'!' | b'$' | b'&' | b'\'' | b'(' | b')' | b'*' | b'+' | b','
+      | b';' | b'=' | b':' | b'@' | b'/' => encoded.push(byte as char),
+      _ => encoded.push_str(&format!("%{:02X}", byte)),
+    }
+  }
+  encoded
+}
+
+#[cfg(test)]
+mod tests_url {
+  use super::*;
+
+  #[test]
+  fn should_parse_every_component() {
+    let url = GopherURL::from("gopher://[::1]:7070/7/search%20engine%09caf%C3%A9%09+").unwrap();
+    assert_eq!("::1", url.host);
+    assert_eq!(7070, url.port);
+    assert_eq!("7", url.r#type);
+    assert_eq!("/search engine", url.selector);
+    assert_eq!(Some("café".to_string()), url.search);
+    assert_eq!(Some("+".to_string()), url.gopher_plus);
+    assert_eq!("[::1]:7070", url.get_server());
+    assert_eq!(
+      Some("gopher://[::1]:7070/7/search%20engine%09caf%C3%A9%09+".to_string()),
+      url.get_url()
+    );
+
+    // Search typed with a tab, and multi-byte type
+    let url = GopherURL::from("sdf.org/7/lookup\tgopher hole").unwrap();
+    assert_eq!(
+      ("/lookup", Some("gopher hole".to_string())),
+      (&url.selector[..], url.search)
+    );
+    assert_eq!("é", GopherURL::from("sdf.org/é").unwrap().r#type);
+    // Empty path and empty port
+    let url = GopherURL::from("GOPHER://sdf.org:/").unwrap();
+    assert_eq!(
+      (70, "1", ""),
+      (url.port, &url.r#type[..], &url.selector[..])
+    );
+  }
+
+  #[test]
+  fn should_report_errors() {
+    assert_eq!(
+      Err(ParseError::UnsupportedScheme("https".to_string())),
+      GopherURL::from("https://sdf.org")
+    );
+    assert_eq!(
+      Err(ParseError::EmptyHost),
+      GopherURL::from("gopher://:70/1")
+    );
+    assert_eq!(Err(ParseError::EmptyHost), GopherURL::from(""));
+    assert_eq!(
+      Err(ParseError::InvalidHost("sdf org".to_string())),
+      GopherURL::from("sdf org/1")
+    );
+    assert_eq!(
+      Err(ParseError::InvalidHost("::g".to_string())),
+      GopherURL::from("[::g]:70")
+    );
+    assert_eq!(
+      Err(ParseError::InvalidPort("70000".to_string())),
+      GopherURL::from("sdf.org:70000")
+    );
+    assert_eq!(
+      Err(ParseError::InvalidPort("+70".to_string())),
+      GopherURL::from("sdf.org:+70")
+    );
+    assert_eq!(
+      Err(ParseError::InvalidPercentEncoding("/100%".to_string())),
+      GopherURL::from("sdf.org/0/100%")
+    );
+    assert_eq!(
+      Err(ParseError::InvalidCharacter(
+        "x\r\nMAIL FROM:<a@b.c>".to_string()
+      )),
+      GopherURL::from("10.0.0.5:25/0x%0D%0AMAIL%20FROM:%3Ca@b.c%3E")
+    );
+    assert_eq!(
+      "Line break or NUL in \"/a\\tb\\0\"",
+      GopherURL::from("sdf.org/7/a%09b%00")
+        .unwrap_err()
+        .to_string()
+    );
+    assert_eq!(
+      "Invalid port \"0\"",
+      GopherURL::from("sdf.org:0").unwrap_err().to_string()
+    );
+  }
+
+  #[test]
+  fn should_parse_gemini_urls() {
+    let url = GopherURL::from("GEMINI://[::1]:1966/caf%C3%A9/?q=a%20b#top").unwrap();
+    assert_eq!(Scheme::Gemini, url.scheme);
+    assert_eq!(("::1", 1966), (&url.host[..], url.port));
+    assert_eq!("/caf%C3%A9/", url.selector);
+    assert_eq!(Some("q=a%20b".to_string()), url.search);
+    assert_eq!(
+      Some("gemini://[::1]:1966/caf%C3%A9/?q=a%20b".to_string()),
+      url.get_url()
+    );
+
+    let url = GopherURL::from("gemini://geminiprotocol.net").unwrap();
+    assert_eq!(("/", None), (&url.selector[..], url.search.clone()));
+    assert_eq!(
+      Some("gemini://geminiprotocol.net/".to_string()),
+      url.get_url()
+    );
+    assert_eq!(
+      Err(ParseError::InvalidPercentEncoding("/a b".to_string())),
+      GopherURL::from("gemini://geminiprotocol.net/a b")
+    );
+  }
+
+  // Small xorshift generator, so that property tests are reproducible
+  struct Random(u64);
+
+  impl Random {
+    fn next(&mut self) -> u64 {
+      self.0 ^= self.0 << 13;
+      self.0 ^= self.0 >> 7;
+      self.0 ^= self.0 << 17;
+      self.0
+    }
+
+    fn below(&mut self, max: usize) -> usize {
+      (self.next() % max as u64) as usize
+    }
+
+    fn pick<'a>(&mut self, choices: &[&'a str]) -> &'a str {
+      choices[self.below(choices.len())]
+    }
+
+    // Text made of characters which need encoding or not
+    fn text(&mut self, with_tabs: bool) -> String {
+      let alphabet = [
+        "a", "Z", "0", "/", "-", "~", " ", "%", "?", "#", ":", "[", "]", "é", "漢", "🦫", "\t",
+      ];
+      let alphabet = if with_tabs {
+        &alphabet[..]
+      } else {
+        &alphabet[..16]
+      };
+      (0..self.below(12)).map(|_| self.pick(alphabet)).collect()
+    }
+  }
+
+  fn random_url(random: &mut Random) -> GopherURL {
+    let mut url = GopherURL::new();
+    url.host = random
+      .pick(&[
+        "sdf.org",
+        "localhost",
+        "127.0.0.1",
+        "::1",
+        "fe80::1",
+        "xn--gphr-qra.example",
+        "h_st~1",
+      ])
+      .to_string();
+    url.port = 1 + random.below(65535) as u16;
+    url.r#type = random
+      .pick(&["0", "1", "7", "9", "h", "I", "+", "é", "%", "/"])
+      .to_string();
+    url.selector = random.text(false);
+    if random.below(2) == 0 {
+      url.search = Some(random.text(false));
+      if random.below(2) == 0 {
+        url.gopher_plus = Some(random.text(true));
+      }
+    }
+    url
+  }
+
+  #[test]
+  fn should_parse_serialized_urls_back() {
+    let mut random = Random(0x5eed_cafe);
+    for _ in 0..5000 {
+      let url = random_url(&mut random);
+      let serialized = url.get_url().unwrap();
+      assert_eq!(
+        Ok(url.clone()),
+        GopherURL::from(&serialized),
+        "{}",
+        serialized
+      );
+    }
+  }
+
+  #[test]
+  fn should_parse_any_input_without_panicking() {
+    let pieces = [
+      "gopher://",
+      "https://",
+      "[",
+      "]",
+      ":",
+      "::1",
+      "70",
+      "99999",
+      "/",
+      "1",
+      "%",
+      "%0",
+      "%09",
+      "%C3%A9",
+      "%ff",
+      "\t",
+      "é",
+      "漢",
+      "sdf.org",
+      " ",
+      "",
+    ];
+    let mut random = Random(0xb0b);
+    for _ in 0..20000 {
+      let input: String = (0..random.below(10))
+        .map(|_| random.pick(&pieces))
+        .collect();
+      // Whatever is parsed is serialized to a URL which gives it back
+      if let Ok(url) = GopherURL::from(&input) {
+        let serialized = url.get_url().unwrap();
+        assert_eq!(Ok(url), GopherURL::from(&serialized), "{:?}", input);
+      }
+    }
+  }
+}
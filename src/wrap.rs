@@ -0,0 +1,254 @@
+// Copyright © Killian Kemps (2019)
+//
+// Killian Kemps <developer@killiankemps.fr>
+//
+// This software is a computer program whose purpose is to communicate with
+// the Gopher protocol.
+//
+// This software is governed by the CeCILL license under French law and
+// abiding by the rules of distribution of free software.  You can  use,
+// modify and/ or redistribute the software under the terms of the CeCILL
+// license as circulated by CEA, CNRS and INRIA at the following URL
+// "http://www.cecill.info".
+//
+// As a counterpart to the access to the source code and  rights to copy,
+// modify and redistribute granted by the license, users are provided only
+// with a limited warranty  and the software's author,  the holder of the
+// economic rights,  and the successive licensors  have only  limited
+// liability.
+//
+// In this respect, the user's attention is drawn to the risks associated
+// with loading,  using,  modifying and/or developing or reproducing the
+// software by the user in light of its specific status of free software,
+// that may mean  that it is complicated to manipulate,  and  that  also
+// therefore means  that it is reserved for developers  and  experienced
+// professionals having in-depth computer knowledge. Users are therefore
+// encouraged to load and test the software's suitability as regards their
+// requirements in conditions enabling the security of their systems and/or
+// data to be ensured and,  more generally, to use and operate it in the
+// same conditions as regards security.
+//
+// The fact that you are presently reading this means that you have had
+// knowledge of the CeCILL license and that you accept its terms.
+
+use std::env;
+
+// Non-breaking space used to glue words which must stay on the same line,
+// such as a URL and its link number
+pub const GLUE: char = '\u{a0}';
+
+// Terminal width as given by the terminal, or taken from $COLUMNS when the
+// output is not a terminal, 80 columns otherwise
+pub fn terminal_width() -> usize {
+  #[cfg(unix)]
+  {
+    if let Some(width) = window_columns() {
+      return width;
+    }
+  }
+  match env::var("COLUMNS").map(|columns| columns.parse::<usize>()) {
+    Ok(Ok(width)) if width > 0 => width,
+    _ => 80,
+  }
+}
+
+#[cfg(unix)]
+fn window_columns() -> Option<usize> {
+  unsafe {
+    let mut size: libc::winsize = std::mem::zeroed();
+    if libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) == 0 && size.ws_col > 0 {
+      return Some(size.ws_col as usize);
+    }
+  }
+  None
+}
+
+// Number of columns taken by a line, ignoring escape sequences kept for
+// colours
+pub fn display_width(line: &str) -> usize {
+  let mut width = 0;
+  let mut chars = line.chars();
+  while let Some(c) = chars.next() {
+    if c == '\u{1b}' {
+      // Colour sequences all end with 'm'
+      for c in chars.by_ref() {
+        if c == 'm' {
+          break;
+        }
+      }
+    } else if c == '\t' {
+      width += 8 - width % 8;
+    } else {
+      width += 1;
+    }
+  }
+  width
+}
+
+// Lines which should be kept as-is: indented lines, which are usually code,
+// quotes or centered titles, and ASCII art or tables
+pub fn is_preformatted(line: &str) -> bool {
+  if line.starts_with(' ') || line.starts_with('\t') || line.contains("   ") {
+    return true;
+  }
+  // Box-drawing and block elements
+  if line.chars().any(|c| ('\u{2500}'..='\u{259f}').contains(&c)) {
+    return true;
+  }
+  // Mostly symbols, e.g. "=====" separators or drawings
+  let visible: Vec<char> = line.chars().filter(|c| !c.is_whitespace()).collect();
+  let symbols = visible
+    .iter()
+    .filter(|c| !c.is_alphanumeric() && !".,;:!?'\"()-".contains(**c))
+    .count();
+  !visible.is_empty() && symbols * 2 > visible.len()
+}
+
+// Prefix of list items ("- ", "* ", "1. "...) which start a new paragraph
+fn list_item_prefix(line: &str) -> Option<usize> {
+  for bullet in &["- ", "* ", "+ "] {
+    if line.starts_with(bullet) {
+      return Some(bullet.len());
+    }
+  }
+  let digits = line.chars().take_while(|c| c.is_ascii_digit()).count();
+  if digits > 0 && (line[digits..].starts_with(". ") || line[digits..].starts_with(") ")) {
+    return Some(digits + 2);
+  }
+  None
+}
+
+// Join the lines of each paragraph and wrap them at the given width.
+// Blank lines and preformatted lines are kept as-is. A line noticeably
+// shorter than the rest of the document ends its paragraph so that titles,
+// signatures or poems are not joined with the following lines. Words are
+// never split, so a word longer than the width gets its own line.
+pub fn reflow(lines: &[String], width: usize) -> Vec<String> {
+  let mut prose_widths: Vec<usize> = lines
+    .iter()
+    .filter(|line| line.trim() != "" && !is_preformatted(line))
+    .map(|line| display_width(line.trim_end()))
+    .collect();
+  prose_widths.sort_unstable();
+  let median_width = prose_widths
+    .get(prose_widths.len() / 2)
+    .cloned()
+    .unwrap_or(0);
+  let short_width = median_width * 3 / 4;
+
+  let mut output = Vec::new();
+  let mut paragraph: Vec<&str> = Vec::new();
+  let mut hanging_indent = 0;
+
+  for line in lines {
+    let trimmed = line.trim_end();
+    if trimmed.is_empty() || is_preformatted(trimmed) {
+      fill(&paragraph, width, hanging_indent, &mut output);
+      paragraph.clear();
+      output.push(line.to_string());
+      continue;
+    }
+
+    if let Some(prefix) = list_item_prefix(trimmed) {
+      fill(&paragraph, width, hanging_indent, &mut output);
+      paragraph.clear();
+      hanging_indent = prefix;
+    } else if paragraph.is_empty() {
+      hanging_indent = 0;
+    }
+
+    paragraph.extend(trimmed.split([' ', '\t']).filter(|w| !w.is_empty()));
+
+    if display_width(trimmed) < short_width {
+      fill(&paragraph, width, hanging_indent, &mut output);
+      paragraph.clear();
+    }
+  }
+  fill(&paragraph, width, hanging_indent, &mut output);
+
+  output
+}
+
+fn fill(words: &[&str], width: usize, hanging_indent: usize, output: &mut Vec<String>) {
+  let mut line = String::new();
+  let mut line_width = 0;
+  let mut has_words = false;
+  for word in words {
+    let word_width = display_width(word);
+    if has_words && line_width + 1 + word_width > width {
+      output.push(line);
+      line = " ".repeat(hanging_indent);
+      line_width = hanging_indent;
+      has_words = false;
+    }
+    if has_words {
+      line.push(' ');
+      line_width += 1;
+    }
+    line.push_str(word);
+    line_width += word_width;
+    has_words = true;
+  }
+  if has_words {
+    output.push(line);
+  }
+}
+
+#[cfg(test)]
+mod tests_wrap {
+  use super::*;
+
+  fn to_lines(text: &str) -> Vec<String> {
+    text.split('\n').map(|line| line.to_string()).collect()
+  }
+
+  #[test]
+  fn should_reflow_paragraphs() {
+    let text = "\
+      Gopher is a protocol designed for distributing, searching\n\
+      and retrieving documents over the Internet.\n\
+      \n\
+      Thanks!";
+    assert_eq!(
+      to_lines(
+        "\
+        Gopher is a protocol designed\n\
+        for distributing, searching\n\
+        and retrieving documents over\n\
+        the Internet.\n\
+        \n\
+        Thanks!"
+      ),
+      reflow(&to_lines(text), 30)
+    );
+  }
+
+  #[test]
+  fn should_keep_preformatted_lines() {
+    let text = "\
+      \n    fn main() {}\n\
+      ┌──────┐\n\
+      │ box  │\n\
+      └──────┘\n\
+      ==========================================\n\
+      Name     Size";
+    assert_eq!(to_lines(text), reflow(&to_lines(text), 10));
+  }
+
+  #[test]
+  fn should_wrap_list_items_with_hanging_indent() {
+    let text = "\
+      - first item which is rather long\n\
+      - second\n\
+      12. numbered item also rather long";
+    assert_eq!(
+      to_lines(
+        "\
+        - first item\n  which is\n  rather\n  long\n\
+        - second\n\
+        12. numbered\n    item\n    also\n    rather\n    long"
+      ),
+      reflow(&to_lines(text), 12)
+    );
+  }
+}